@@ -0,0 +1,220 @@
+// Local avoidance between units, using optimal reciprocal collision avoidance
+// (ORCA, as in the RVO2 library).
+//
+// Each neighbour rules out the velocities which would hit it within a time
+// horizon. ORCA approximates that with a half plane: a line through velocity
+// space, where everything on the left is allowed. Units avoiding each other
+// each take half the responsibility, so they dodge to opposite sides rather
+// than both swerving the same way. Anything else (statics, dynamic bodies and
+// units which aren't being steered) is assumed not to dodge, so the unit takes
+// all of it. The new velocity is the one closest to the preferred velocity
+// which satisfies every half plane, found with a small incremental linear
+// program. If there isn't one (a crowd can box a unit in), it takes the
+// velocity which breaks the constraints the least.
+//
+// RVO2 works from each agent's current velocity. We use the preferred ones
+// instead, so there's nothing to remember between frames (or snapshot). That
+// copes with crowds a little worse, which a longer time horizon makes up for.
+//
+// Velocities are per frame, like everything else.
+use ncollide2d::math::{Isometry, Point, Vector};
+use ncollide2d::shape::{Ball, ShapeHandle};
+use ncollide2d::bounding_volume;
+
+use crate::N;
+
+// How many frames ahead units look for each other, and for statics. Longer is
+// smoother but more timid. Too short, and units walking straight at each
+// other slow down without ever stepping aside.
+pub(crate) const AVOID_TIME_HORIZON: N = 40.0;
+pub(crate) const AVOID_STATIC_TIME_HORIZON: N = 5.0;
+const AVOID_EPSILON: N = 0.00001;
+
+// A half plane of allowed velocities: everything left of the line through
+// point going in direction (which is a unit vector).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Line {
+    point: Vector<N>,
+    direction: Vector<N>,
+}
+
+// Units are treated as circles. Anything other than a ball gets one which
+// covers it.
+pub(crate) fn bounding_radius(shape: &ShapeHandle<N>) -> N {
+    match shape.as_shape::<Ball<N>>() {
+        Some(ball) => ball.radius,
+        None => bounding_volume::aabb(shape.as_ref(), &Isometry::identity()).half_extents().norm(),
+    }
+}
+
+fn det(a: Vector<N>, b: Vector<N>) -> N {
+    a.x * b.y - a.y * b.x
+}
+
+// Something to steer around.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Neighbour {
+    pub pos: Point<N>,
+    pub vel: Vector<N>,
+    pub radius: N,
+    // Half for units which are avoiding us back, 1 for everything else.
+    pub responsibility: N,
+}
+
+// The half plane for a moving circle, given our own position, velocity and
+// radius.
+pub(crate) fn agent_line(pos: Point<N>, vel: Vector<N>, radius: N, other: &Neighbour) -> Line {
+    let rel_pos = other.pos - pos;
+    let rel_vel = vel - other.vel;
+    let dist_sq = rel_pos.norm_squared();
+    let combined = radius + other.radius;
+    let combined_sq = combined * combined;
+    let inv_horizon = 1.0 / AVOID_TIME_HORIZON;
+
+    let (direction, u) = if dist_sq > combined_sq {
+        // The velocity obstacle is a cone, with its tip cut off by a circle.
+        let w = rel_vel - rel_pos * inv_horizon;
+        let w_len_sq = w.norm_squared();
+        let dot = w.dot(&rel_pos);
+
+        if dot < 0.0 && dot * dot > combined_sq * w_len_sq {
+            // Closest to the cut off circle.
+            let w_len = w_len_sq.sqrt();
+            let unit_w = w / w_len;
+            (Vector::new(unit_w.y, -unit_w.x), unit_w * (combined * inv_horizon - w_len))
+        } else {
+            // Closest to one of the sides of the cone.
+            let leg = (dist_sq - combined_sq).sqrt();
+            let direction = if det(rel_pos, w) > 0.0 {
+                Vector::new(rel_pos.x * leg - rel_pos.y * combined, rel_pos.x * combined + rel_pos.y * leg) / dist_sq
+            } else {
+                -Vector::new(rel_pos.x * leg + rel_pos.y * combined, -rel_pos.x * combined + rel_pos.y * leg) / dist_sq
+            };
+            (direction, direction * rel_vel.dot(&direction) - rel_vel)
+        }
+    } else {
+        // Already overlapping. Get apart within a frame.
+        let w = rel_vel - rel_pos;
+        let w_len = w.norm();
+        let unit_w = if w_len > 0.0 { w / w_len } else { Vector::new(-rel_pos.y, rel_pos.x).try_normalize(0.0).unwrap_or_else(|| Vector::new(1.0, 0.0)) };
+        (Vector::new(unit_w.y, -unit_w.x), unit_w * (combined - w_len))
+    };
+
+    Line { point: vel + u * other.responsibility, direction }
+}
+
+// The half plane for a static, given the closest point on it and the distance
+// to it from the edge of the unit. normal points from the static to us.
+pub(crate) fn static_line(normal: Vector<N>, gap: N) -> Line {
+    // We can move towards it by at most the gap over the time horizon. If
+    // we're already in it, don't go any further in.
+    let limit = -gap.max(0.0) / AVOID_STATIC_TIME_HORIZON;
+    Line { point: normal * limit, direction: Vector::new(normal.y, -normal.x) }
+}
+
+// Solves along one line, subject to the lines before it. Returns false if
+// they leave nothing.
+fn linear_program1(lines: &[Line], line_no: usize, radius: N, opt: Vector<N>, direction_opt: bool, result: &mut Vector<N>) -> bool {
+    let line = lines[line_no];
+    let dot = line.point.dot(&line.direction);
+    let discriminant = dot * dot + radius * radius - line.point.norm_squared();
+    // The max speed circle doesn't reach the line.
+    if discriminant < 0.0 { return false; }
+
+    let sqrt_disc = discriminant.sqrt();
+    let mut t_left = -dot - sqrt_disc;
+    let mut t_right = -dot + sqrt_disc;
+
+    for other in lines[..line_no].iter() {
+        let denominator = det(line.direction, other.direction);
+        let numerator = det(other.direction, line.point - other.point);
+
+        if denominator.abs() <= AVOID_EPSILON {
+            // Parallel. Either it rules this whole line out, or none of it.
+            if numerator < 0.0 { return false; }
+            continue;
+        }
+
+        let t = numerator / denominator;
+        if denominator >= 0.0 { t_right = t_right.min(t); } else { t_left = t_left.max(t); }
+        if t_left > t_right { return false; }
+    }
+
+    *result = if direction_opt {
+        if opt.dot(&line.direction) > 0.0 { line.point + line.direction * t_right }
+        else { line.point + line.direction * t_left }
+    } else {
+        let t = line.direction.dot(&(opt - line.point));
+        line.point + line.direction * t.max(t_left).min(t_right)
+    };
+    true
+}
+
+// The velocity closest to opt, no faster than radius, which satisfies every
+// line. If direction_opt is set, opt is a direction to go as far as possible
+// in instead. Returns the first line which couldn't be satisfied (or
+// lines.len() if they all were), with result left at the best velocity so far.
+fn linear_program2(lines: &[Line], radius: N, opt: Vector<N>, direction_opt: bool, result: &mut Vector<N>) -> usize {
+    *result = if direction_opt {
+        opt * radius
+    } else if opt.norm_squared() > radius * radius {
+        opt.normalize() * radius
+    } else {
+        opt
+    };
+
+    for i in 0..lines.len() {
+        if det(lines[i].direction, lines[i].point - *result) > 0.0 {
+            let before = *result;
+            if !linear_program1(lines, i, radius, opt, direction_opt, result) {
+                *result = before;
+                return i;
+            }
+        }
+    }
+    lines.len()
+}
+
+// When there's no velocity which satisfies everything, find the one which
+// breaks the lines from begin onwards by the least. The first static_lines
+// lines are never broken.
+fn linear_program3(lines: &[Line], static_lines: usize, begin: usize, radius: N, result: &mut Vector<N>) {
+    let mut distance = 0.0;
+
+    for i in begin..lines.len() {
+        if det(lines[i].direction, lines[i].point - *result) <= distance { continue; }
+
+        let mut projected = lines[..static_lines].to_vec();
+        for j in static_lines..i {
+            let determinant = det(lines[i].direction, lines[j].direction);
+            let point = if determinant.abs() <= AVOID_EPSILON {
+                // Parallel, and pointing the same way.
+                if lines[i].direction.dot(&lines[j].direction) > 0.0 { continue; }
+                (lines[i].point + lines[j].point) * 0.5
+            } else {
+                lines[i].point + lines[i].direction * (det(lines[j].direction, lines[i].point - lines[j].point) / determinant)
+            };
+            let direction = (lines[j].direction - lines[i].direction).normalize();
+            projected.push(Line { point, direction });
+        }
+
+        let before = *result;
+        let away = Vector::new(-lines[i].direction.y, lines[i].direction.x);
+        if linear_program2(&projected, radius, away, true, result) < projected.len() {
+            // Can only happen through rounding, since the result is already
+            // in the projected region.
+            *result = before;
+        }
+        distance = det(lines[i].direction, lines[i].point - *result);
+    }
+}
+
+// Picks the new velocity. The static lines come first in lines.
+pub(crate) fn solve(lines: &[Line], static_lines: usize, max_speed: N, preferred: Vector<N>) -> Vector<N> {
+    let mut result = Vector::zeros();
+    let failed = linear_program2(lines, max_speed, preferred, false, &mut result);
+    if failed < lines.len() {
+        linear_program3(lines, static_lines, failed, max_speed, &mut result);
+    }
+    result
+}
@@ -0,0 +1,83 @@
+use std::f64;
+use ncollide2d::shape::*;
+use ncollide2d::math::*;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::N;
+
+// Each debug line is emitted as 5 floats: [kind, x1, y1, x2, y2].
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugLine {
+    Outline,
+    Aabb,
+    ContactPoint,
+    ContactNormal,
+    Sweep,
+    // Polygon outlines of the navigation mesh, if there is one.
+    NavMesh,
+    // The edges of vision cones.
+    Vision,
+}
+
+const DEBUG_CIRCLE_SEGMENTS: usize = 16;
+pub(crate) const DEBUG_NORMAL_LENGTH: f64 = 0.5;
+pub(crate) const DEBUG_POINT_SIZE: f64 = 0.05;
+
+pub(crate) fn push_line(out: &mut Vec<f64>, kind: DebugLine, a: Point<N>, b: Point<N>) {
+    out.extend_from_slice(&[kind as u32 as f64, a.x, a.y, b.x, b.y]);
+}
+
+pub(crate) fn push_polygon(out: &mut Vec<f64>, kind: DebugLine, points: &[Point<N>]) {
+    for i in 0..points.len() {
+        push_line(out, kind, points[i], points[(i + 1) % points.len()]);
+    }
+}
+
+// Push the outline of a collider. Unknown shapes get skipped - the AABB will
+// still show up.
+pub(crate) fn push_outline(out: &mut Vec<f64>, shape: &ShapeHandle<N>, pos: &Isometry<N>) {
+    if let Some(ball) = shape.as_shape::<Ball<N>>() {
+        let r = ball.radius;
+        let points = (0..DEBUG_CIRCLE_SEGMENTS).map(|i| {
+            let a = (i as f64) * f64::consts::PI * 2.0 / (DEBUG_CIRCLE_SEGMENTS as f64);
+            pos * Point::new(r * a.cos(), r * a.sin())
+        }).collect::<Vec<_>>();
+        push_polygon(out, DebugLine::Outline, &points);
+        // And a line showing which way its facing, like the renderer does.
+        push_line(out, DebugLine::Outline, pos * Point::origin(), pos * Point::new(r, 0.0));
+    } else if let Some(cuboid) = shape.as_shape::<Cuboid<N>>() {
+        let he = cuboid.half_extents;
+        let points = [
+            pos * Point::new(-he.x, -he.y),
+            pos * Point::new(he.x, -he.y),
+            pos * Point::new(he.x, he.y),
+            pos * Point::new(-he.x, he.y),
+        ];
+        push_polygon(out, DebugLine::Outline, &points);
+    } else if let Some(poly) = shape.as_shape::<ConvexPolygon<N>>() {
+        let points = poly.points().iter().map(|p| pos * p).collect::<Vec<_>>();
+        push_polygon(out, DebugLine::Outline, &points);
+    } else if let Some(polyline) = shape.as_shape::<Polyline<N>>() {
+        let points = polyline.points();
+        for edge in polyline.edges() {
+            push_line(out, DebugLine::Outline, pos * points[edge.indices.x], pos * points[edge.indices.y]);
+        }
+    }
+}
+
+// Push the outline of a vision cone on an object at pos.
+pub(crate) fn push_cone(out: &mut Vec<f64>, pos: &Isometry<N>, half_angle: N, range: N) {
+    let eye = pos * Point::origin();
+    let segments = ((half_angle / f64::consts::PI * DEBUG_CIRCLE_SEGMENTS as f64).ceil() as usize).max(1);
+    let points = (0..=segments).map(|i| {
+        let a = -half_angle + 2.0 * half_angle * (i as f64) / (segments as f64);
+        pos * Point::new(range * a.cos(), range * a.sin())
+    }).collect::<Vec<_>>();
+    push_line(out, DebugLine::Vision, eye, points[0]);
+    for pair in points.windows(2) {
+        push_line(out, DebugLine::Vision, pair[0], pair[1]);
+    }
+    push_line(out, DebugLine::Vision, points[segments], eye);
+}
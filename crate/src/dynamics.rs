@@ -0,0 +1,155 @@
+// A small impulse based rigid body solver for physics props (crates, barrels,
+// debris). Bodies are collision objects in the Dynamic group, with a Body
+// stored in their EntityData. Everything else is treated as infinitely heavy:
+// statics never move, and units / projectiles push bodies around with
+// whatever velocity try_move gave them this frame.
+//
+// Like the rest of the world, velocities are per frame rather than per
+// second, and update is one step.
+use std::f64;
+use ncollide2d::math::{Isometry, Point, Vector};
+use ncollide2d::shape::{Ball, Cuboid, ShapeHandle};
+use ncollide2d::bounding_volume;
+
+use crate::N;
+
+// How many times we run over the contacts each frame. More is stiffer but
+// slower.
+pub(crate) const SOLVER_ITERATIONS: usize = 4;
+// Overlap we leave alone, so resting contacts don't jitter.
+pub(crate) const PENETRATION_SLOP: N = 0.005;
+// How much of the remaining overlap we fix each frame.
+pub(crate) const PENETRATION_CORRECTION: N = 0.8;
+// Projectiles don't have a mass of their own. This is what they hit like.
+pub(crate) const PROJECTILE_MASS: N = 0.1;
+
+pub(crate) const DEFAULT_DENSITY: N = 1.0;
+pub(crate) const DEFAULT_RESTITUTION: N = 0.2;
+pub(crate) const DEFAULT_FRICTION: N = 0.5;
+// Fraction of velocity lost each frame to sliding along the floor. We're top
+// down, so without this everything would drift forever.
+pub(crate) const DEFAULT_DAMPING: N = 0.05;
+// Bodies slower than this (per frame, or radians per frame) stop dead, so
+// they settle instead of creeping along forever.
+pub(crate) const REST_SPEED: N = 0.0001;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Body {
+    pub vel: Vector<N>,
+    pub ang_vel: N,
+    // Zero for infinite mass.
+    pub inv_mass: N,
+    pub inv_inertia: N,
+    pub restitution: N,
+    pub friction: N,
+    pub damping: N,
+}
+
+// Shapes other than circles and boxes are treated like their bounding box.
+fn half_extents(shape: &ShapeHandle<N>) -> Vector<N> {
+    match shape.as_shape::<Cuboid<N>>() {
+        Some(cuboid) => cuboid.half_extents,
+        None => bounding_volume::aabb(shape.as_ref(), &Isometry::identity()).half_extents(),
+    }
+}
+
+fn area(shape: &ShapeHandle<N>) -> N {
+    if let Some(ball) = shape.as_shape::<Ball<N>>() {
+        f64::consts::PI * ball.radius * ball.radius
+    } else {
+        let he = half_extents(shape);
+        4.0 * he.x * he.y
+    }
+}
+
+// Moment of inertia of a solid shape with the given mass.
+fn inertia(shape: &ShapeHandle<N>, mass: N) -> N {
+    if let Some(ball) = shape.as_shape::<Ball<N>>() {
+        mass * ball.radius * ball.radius / 2.0
+    } else {
+        let he = half_extents(shape);
+        mass * (he.x * he.x + he.y * he.y) / 3.0
+    }
+}
+
+fn inv(x: N) -> N {
+    if x > 0.0 && x.is_finite() { 1.0 / x } else { 0.0 }
+}
+
+impl Body {
+    pub(crate) fn new(shape: &ShapeHandle<N>) -> Body {
+        let mut body = Body {
+            vel: Vector::zeros(),
+            ang_vel: 0.0,
+            inv_mass: 0.0,
+            inv_inertia: 0.0,
+            restitution: DEFAULT_RESTITUTION,
+            friction: DEFAULT_FRICTION,
+            damping: DEFAULT_DAMPING,
+        };
+        body.set_mass(shape, area(shape) * DEFAULT_DENSITY);
+        body
+    }
+
+    // A mass of 0 (or infinity) makes the body immovable.
+    pub(crate) fn set_mass(&mut self, shape: &ShapeHandle<N>, mass: N) {
+        self.inv_mass = inv(mass);
+        self.inv_inertia = inv(inertia(shape, mass));
+    }
+
+    pub(crate) fn apply_impulse(&mut self, impulse: Vector<N>, r: Vector<N>) {
+        self.vel += impulse * self.inv_mass;
+        self.ang_vel += r.perp(&impulse) * self.inv_inertia;
+    }
+}
+
+// One side of a contact. For anything which isn't a body the inverse masses
+// are zero and the velocity is whatever try_move did this frame.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ContactSide {
+    pub vel: Vector<N>,
+    pub ang_vel: N,
+    pub inv_mass: N,
+    pub inv_inertia: N,
+    // Offset from the center of mass to the contact point.
+    pub r: Vector<N>,
+}
+
+impl ContactSide {
+    pub(crate) fn point_vel(&self) -> Vector<N> {
+        self.vel + Vector::new(-self.ang_vel * self.r.y, self.ang_vel * self.r.x)
+    }
+
+    pub(crate) fn effective_inv_mass(&self, dir: Vector<N>) -> N {
+        let rn = self.r.perp(&dir);
+        self.inv_mass + rn * rn * self.inv_inertia
+    }
+}
+
+// Works out the impulse to apply to b at a contact. a gets the negation. The
+// normal points from a to b. Returns None if they're already separating.
+pub(crate) fn contact_impulse(a: &ContactSide, b: &ContactSide, normal: Vector<N>, restitution: N, friction: N) -> Option<Vector<N>> {
+    let rel = b.point_vel() - a.point_vel();
+    let vn = rel.dot(&normal);
+    if vn >= 0.0 { return None; }
+
+    let k = a.effective_inv_mass(normal) + b.effective_inv_mass(normal);
+    if k <= 0.0 { return None; }
+    let jn = -(1.0 + restitution) * vn / k;
+
+    let tangent = rel - normal * vn;
+    let jt = match tangent.try_normalize(1e-12) {
+        Some(t) => {
+            let kt = a.effective_inv_mass(t) + b.effective_inv_mass(t);
+            let jt = if kt > 0.0 { -rel.dot(&t) / kt } else { 0.0 };
+            t * jt.max(-friction * jn).min(friction * jn)
+        },
+        None => Vector::zeros(),
+    };
+
+    Some(normal * jn + jt)
+}
+
+pub(crate) fn midpoint(a: &Point<N>, b: &Point<N>) -> Point<N> {
+    Point::from((a.coords + b.coords) / 2.0)
+}
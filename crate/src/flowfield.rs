@@ -0,0 +1,225 @@
+// A flow field, for steering lots of agents towards one target without
+// pathfinding for each of them.
+//
+// The statics are rasterized into a grid of square cells, where any cell
+// which overlaps a static is blocked. Dijkstra's algorithm then spreads out
+// from the target's cell, giving every cell the cost of getting to the target
+// from there. An agent just heads for whichever neighbouring cell is cheapest.
+//
+// The grid only depends on the statics, so moving the target reuses it and
+// only reruns the search.
+use std::f64;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use ncollide2d::math::{Isometry, Point, Vector};
+use ncollide2d::shape::{Cuboid, ShapeHandle};
+use ncollide2d::bounding_volume::{self, BoundingVolume, AABB};
+use ncollide2d::query::{self, Proximity};
+
+use crate::N;
+
+// Empty cells left around the statics, so agents can get around the outside
+// of them.
+const FLOW_FIELD_MARGIN: usize = 2;
+// Refuse to build anything bigger than this. A tiny cell size over a big
+// level would eat all the memory.
+pub(crate) const MAX_FLOW_FIELD_CELLS: usize = 1 << 20;
+
+// The 8 neighbours of a cell, as (dx, dy, cost).
+const NEIGHBOURS: [(isize, isize, N); 8] = [
+    (1, 0, 1.0), (-1, 0, 1.0), (0, 1, 1.0), (0, -1, 1.0),
+    (1, 1, f64::consts::SQRT_2), (-1, 1, f64::consts::SQRT_2),
+    (1, -1, f64::consts::SQRT_2), (-1, -1, f64::consts::SQRT_2),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Open {
+    cost: N,
+    cell: usize,
+}
+
+impl Eq for Open {}
+
+// Reversed, so the heap pops the cheapest first.
+impl Ord for Open {
+    fn cmp(&self, other: &Open) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+            .then_with(|| other.cell.cmp(&self.cell))
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub(crate) struct FlowField {
+    // The corner of cell 0, which is the one with the lowest x and y.
+    origin: Point<N>,
+    pub cell_size: N,
+    width: usize,
+    height: usize,
+    blocked: Vec<bool>,
+    // See World::statics_version. When it changes the grid is out of date.
+    pub statics_version: u64,
+
+    target: Point<N>,
+    target_cell: usize,
+    // Cost to get to the target from each cell. Infinite if you can't.
+    costs: Vec<N>,
+}
+
+impl FlowField {
+    // Rasterizes the statics. Returns None if the grid would be too big. Call
+    // retarget to fill it in.
+    pub(crate) fn new<'a>(statics: &[(&'a ShapeHandle<N>, &'a Isometry<N>)], target: Point<N>, cell_size: N, statics_version: u64) -> Option<FlowField> {
+        if !cell_size.is_finite() || cell_size <= 0.0 { return None; }
+        let aabbs = statics.iter()
+            .map(|(shape, pos)| bounding_volume::aabb(shape.as_ref(), pos))
+            .collect::<Vec<_>>();
+        let bounds = aabbs.iter().fold(AABB::new(target, target), |acc, aabb| acc.merged(aabb));
+
+        let margin = Vector::repeat(cell_size * FLOW_FIELD_MARGIN as N);
+        let mins = bounds.mins - margin;
+        let maxs = bounds.maxs + margin;
+        let origin = Point::new((mins.x / cell_size).floor() * cell_size, (mins.y / cell_size).floor() * cell_size);
+        let width = ((maxs.x - origin.x) / cell_size).ceil() as usize + 1;
+        let height = ((maxs.y - origin.y) / cell_size).ceil() as usize + 1;
+        if width.saturating_mul(height) > MAX_FLOW_FIELD_CELLS { return None; }
+
+        let mut field = FlowField {
+            origin,
+            cell_size,
+            width,
+            height,
+            blocked: vec![false; width * height],
+            statics_version,
+            target,
+            target_cell: 0,
+            costs: Vec::new(),
+        };
+
+        // A hair smaller than the cell, so statics which only touch its edge
+        // don't block it.
+        let cell = Cuboid::new(Vector::repeat(cell_size / 2.0 * 0.999999));
+        for ((shape, pos), aabb) in statics.iter().zip(aabbs.iter()) {
+            let (x0, y0) = field.coords(&aabb.mins);
+            let (x1, y1) = field.coords(&aabb.maxs);
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    let i = y * width + x;
+                    if field.blocked[i] { continue; }
+                    let center = Isometry::new(field.center(i).coords, 0.0);
+                    let prox = query::proximity(&center, &cell, pos, shape.as_ref(), 0.0);
+                    field.blocked[i] = prox == Proximity::Intersecting;
+                }
+            }
+        }
+
+        Some(field)
+    }
+
+    // The cell containing p, clamped to the grid.
+    fn coords(&self, p: &Point<N>) -> (usize, usize) {
+        let x = ((p.x - self.origin.x) / self.cell_size).floor().max(0.0) as usize;
+        let y = ((p.y - self.origin.y) / self.cell_size).floor().max(0.0) as usize;
+        (x.min(self.width - 1), y.min(self.height - 1))
+    }
+
+    fn cell_of(&self, p: &Point<N>) -> Option<usize> {
+        if !self.covers(p) { return None; }
+        let (x, y) = self.coords(p);
+        Some(y * self.width + x)
+    }
+
+    fn center(&self, i: usize) -> Point<N> {
+        let (x, y) = (i % self.width, i / self.width);
+        self.origin + Vector::new(x as N + 0.5, y as N + 0.5) * self.cell_size
+    }
+
+    pub(crate) fn covers(&self, p: &Point<N>) -> bool {
+        let d = (p - self.origin) / self.cell_size;
+        d.x >= 0.0 && d.y >= 0.0 && d.x < self.width as N && d.y < self.height as N
+    }
+
+    // The cells you can step to from i. Diagonal steps can't cut the corner
+    // of a blocked cell.
+    fn steps(&self, i: usize) -> impl Iterator<Item=(usize, N)> + '_ {
+        let (x, y) = ((i % self.width) as isize, (i / self.width) as isize);
+        let free = move |x: isize, y: isize| {
+            x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+                && !self.blocked[y as usize * self.width + x as usize]
+        };
+        NEIGHBOURS.iter().filter_map(move |&(dx, dy, cost)| {
+            let ok = free(x + dx, y + dy) && (dx == 0 || dy == 0 || (free(x + dx, y) && free(x, y + dy)));
+            if ok { Some(((y + dy) as usize * self.width + (x + dx) as usize, cost)) } else { None }
+        })
+    }
+
+    // Moves the target, and returns how many cells the search visited. That's
+    // none if it's still in the same cell, or off the grid.
+    pub(crate) fn retarget(&mut self, target: Point<N>) -> usize {
+        let cell = match self.cell_of(&target) {
+            Some(cell) => cell,
+            None => return 0,
+        };
+        self.target = target;
+        if cell == self.target_cell && !self.costs.is_empty() { return 0; }
+        self.target_cell = cell;
+
+        let mut costs = vec![f64::INFINITY; self.width * self.height];
+        let mut open = BinaryHeap::new();
+        let mut visited = 0;
+        costs[cell] = 0.0;
+        open.push(Open { cost: 0.0, cell });
+        while let Some(Open { cost, cell }) = open.pop() {
+            if cost > costs[cell] { continue; }
+            visited += 1;
+            for (next, step) in self.steps(cell) {
+                if cost + step < costs[next] {
+                    costs[next] = cost + step;
+                    open.push(Open { cost: cost + step, cell: next });
+                }
+            }
+        }
+        self.costs = costs;
+        visited
+    }
+
+    // The way to go from p, as a unit vector. Zero at the target, and where
+    // the target can't be reached. Off the grid there's nothing in the way,
+    // so it points straight at the target.
+    pub(crate) fn direction(&self, p: &Point<N>) -> Vector<N> {
+        let towards = |q: Point<N>| (q - p).try_normalize(0.0).unwrap_or_else(Vector::zeros);
+        let cell = match self.cell_of(p) {
+            Some(cell) => cell,
+            None => return towards(self.target),
+        };
+        if cell == self.target_cell { return towards(self.target); }
+
+        // Agents up against a wall end up in blocked cells. Let them step out
+        // in any direction.
+        let best = if self.blocked[cell] {
+            let (x, y) = ((cell % self.width) as isize, (cell / self.width) as isize);
+            NEIGHBOURS.iter()
+                .map(|&(dx, dy, _)| (x + dx, y + dy))
+                .filter(|&(x, y)| x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height)
+                .map(|(x, y)| y as usize * self.width + x as usize)
+                .filter(|&i| self.costs[i].is_finite())
+                .min_by(|&a, &b| self.costs[a].partial_cmp(&self.costs[b]).unwrap_or(Ordering::Equal))
+        } else if self.costs[cell].is_finite() {
+            self.steps(cell)
+                .map(|(i, _)| i)
+                .min_by(|&a, &b| self.costs[a].partial_cmp(&self.costs[b]).unwrap_or(Ordering::Equal))
+        } else {
+            None
+        };
+
+        match best {
+            Some(i) if i == self.target_cell => towards(self.target),
+            Some(i) => towards(self.center(i)),
+            None => Vector::zeros(),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// A tiny FNV-1a hasher for World::state_hash. We can't use std's
+// DefaultHasher here because its algorithm isn't guaranteed to stay the same
+// between rust versions, and every client needs to agree on the hash.
+pub(crate) struct StateHasher(u64);
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+impl StateHasher {
+    pub(crate) fn new() -> StateHasher {
+        StateHasher(FNV_OFFSET)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub(crate) fn write_u32(&mut self, v: u32) {
+        self.write(&v.to_le_bytes());
+    }
+
+    pub(crate) fn write_u64(&mut self, v: u64) {
+        self.write(&v.to_le_bytes());
+    }
+
+    // Hashes the exact bits, so -0.0 and 0.0 (and different NaNs) differ.
+    pub(crate) fn write_f64(&mut self, v: f64) {
+        self.write_u64(v.to_bits());
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
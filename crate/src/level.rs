@@ -0,0 +1,127 @@
+// Levels are JSON documents which look like this:
+//
+//   {
+//     "version": 1,
+//     "objects": [
+//       { "shape": { "type": "box", "w": 1, "h": 5 }, "x": 6, "y": 3, "angle": 5 },
+//       { "shape": { "type": "circle", "radius": 1 }, "x": 2.5, "y": -3,
+//         "id": 12, "group": "static", "tags": ["pillar"] }
+//     ]
+//   }
+//
+// Each object has:
+//
+// - shape: { "type": "circle", "radius": r } or { "type": "box", "w": w, "h": h }
+// - x, y: the center of the object
+// - angle: in radians. Defaults to 0.
+// - group: "static", "unit", "projectile", "dynamic" or "trigger". Defaults to
+//   "static".
+// - speed: the maximum distance the object moves per frame. Only matters for
+//   units. Defaults to 0.
+// - id: the entity id reported in events. Defaults to the first_id passed to
+//   load_level plus the object's index in the list.
+// - tags: a list of strings for the game to interpret. Defaults to [].
+//
+// Any other fields are ignored, so the game can keep its own data (eg colors)
+// alongside.
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::N;
+use crate::world::CGroup;
+
+pub(crate) const LEVEL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum LevelShape {
+    Circle { radius: N },
+    Box { w: N, h: N },
+}
+
+fn default_group() -> CGroup { CGroup::Static }
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct LevelObject {
+    pub id: Option<u32>,
+    pub x: N,
+    pub y: N,
+    #[serde(default)]
+    pub angle: N,
+    pub shape: LevelShape,
+    #[serde(default = "default_group")]
+    pub group: CGroup,
+    #[serde(default)]
+    pub speed: N,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Level {
+    pub version: u32,
+    pub objects: Vec<LevelObject>,
+}
+
+// What load_level made for each object in the level, in the same order.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoadedObject {
+    pub handle: usize,
+    pub id: u32,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LevelError {
+    // Malformed JSON, or JSON which doesn't match the format above.
+    Parse(String),
+    UnsupportedVersion(u32),
+    // The object at this index makes no sense (eg a negative radius).
+    Invalid(usize, &'static str),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::Parse(e) => write!(f, "could not parse level: {}", e),
+            LevelError::UnsupportedVersion(v) => write!(f, "unsupported level version {}", v),
+            LevelError::Invalid(i, reason) => write!(f, "level object {}: {}", i, reason),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl LevelObject {
+    fn validate(&self) -> Result<(), &'static str> {
+        if !(self.x.is_finite() && self.y.is_finite() && self.angle.is_finite()) {
+            return Err("position must be finite");
+        }
+        match self.shape {
+            LevelShape::Circle { radius } if !(radius > 0.0 && radius.is_finite()) => {
+                return Err("radius must be positive");
+            },
+            LevelShape::Box { w, h } if !(w > 0.0 && h > 0.0 && w.is_finite() && h.is_finite()) => {
+                return Err("box size must be positive");
+            },
+            _ => {}
+        }
+        if !(self.speed >= 0.0 && self.speed.is_finite()) {
+            return Err("speed must not be negative");
+        }
+        Ok(())
+    }
+}
+
+// Parses and checks a whole level up front, so a bad level doesn't leave half
+// its objects in the world.
+pub(crate) fn parse_level(json: &str) -> Result<Level, LevelError> {
+    let level: Level = serde_json::from_str(json).map_err(|e| LevelError::Parse(e.to_string()))?;
+    if level.version != LEVEL_VERSION {
+        return Err(LevelError::UnsupportedVersion(level.version));
+    }
+    for (i, obj) in level.objects.iter().enumerate() {
+        obj.validate().map_err(|reason| LevelError::Invalid(i, reason))?;
+    }
+    Ok(level)
+}
@@ -0,0 +1,79 @@
+use ncollide2d::math::Vector;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::N;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Warn,
+    Info,
+    // Info + structured trace records for try_move. See TraceKind.
+    Trace,
+}
+
+// Each trace record is emitted as 7 floats:
+// [kind, entity id, x, y, vx, vy, value]. The meaning of value depends on the
+// kind of record.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    // A try_move call started. Value is the requested angular velocity.
+    Start,
+    // Velocity was zeroed because of a contact we're already touching.
+    Blocked,
+    // Velocity was projected along a surface. Value is the deflect sign (1 or -1).
+    Deflect,
+    // We deflected both ways - we're at the bottom of a V wall. Stop.
+    VWell,
+    // We'll hit something along the sweep. Value is the time of impact.
+    Impact,
+    // We gave up after too many iterations. Value is the iteration count.
+    Stuck,
+    // Final position. Value is the final angle.
+    Done,
+    // Turning all the way would hit something, so the turn was cut short.
+    // Value is the angular velocity allowed.
+    Turn,
+}
+
+// Log messages get buffered here instead of going straight to the console so
+// the JS side can decide what to do with them.
+pub(crate) struct Log {
+    pub(crate) level: LogLevel,
+    pub(crate) lines: Vec<(LogLevel, String)>,
+    pub(crate) trace: Vec<f64>,
+}
+
+impl Log {
+    pub(crate) fn new(level: LogLevel) -> Log {
+        Log { level, lines: Vec::new(), trace: Vec::new() }
+    }
+
+    pub(crate) fn enabled(&self, level: LogLevel) -> bool {
+        level <= self.level
+    }
+
+    pub(crate) fn trace(&mut self, kind: TraceKind, id: u32, pos: Vector<N>, vel: Vector<N>, value: f64) {
+        if self.enabled(LogLevel::Trace) {
+            self.trace.extend_from_slice(&[kind as u32 as f64, id as f64, pos.x, pos.y, vel.x, vel.y, value]);
+        }
+    }
+}
+
+// The format arguments are only evaluated if the log level is enabled.
+macro_rules! log_at {
+    ($log:expr, $level:expr, $($t:tt)*) => {
+        if $log.enabled($level) { $log.lines.push(($level, format!($($t)*))) }
+    }
+}
+
+macro_rules! log_info {
+    ($log:expr, $($t:tt)*) => (log_at!($log, $crate::log::LogLevel::Info, $($t)*))
+}
+
+macro_rules! log_warn {
+    ($log:expr, $($t:tt)*) => (log_at!($log, $crate::log::LogLevel::Warn, $($t)*))
+}
@@ -0,0 +1,35 @@
+// What a collider's surface is like to slide along. try_move uses the
+// material of whatever a unit runs into to decide how it slides: normally a
+// unit keeps the part of its velocity along the surface, slip lets it keep
+// more of its speed (so it skids along ice), and friction takes some of the
+// slide away (so it bogs down in mud). The ids of the materials touched are
+// reported back, eg to pick footstep or scrape sounds.
+use crate::N;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    // The game's own id for the material, reported by try_move.
+    pub id: u32,
+    // Fraction of the slide speed lost, from 0 to 1.
+    pub friction: N,
+    // From 0 (keep only the speed along the surface) to 1 (keep all of it).
+    pub slip: N,
+    // Which sound to play, for the game to look up.
+    pub sound: u32,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material { id: 0, friction: 0.0, slip: 0.0, sound: 0 }
+    }
+}
+
+impl Material {
+    // The speed to slide along a surface at, given the part of the velocity
+    // along it (vel_dot, signed) and the whole speed.
+    pub(crate) fn slide(&self, vel_dot: N, speed: N) -> N {
+        if vel_dot == 0.0 { return 0.0; }
+        let along = vel_dot.abs() + self.slip * (speed - vel_dot.abs());
+        vel_dot.signum() * along * (1.0 - self.friction)
+    }
+}
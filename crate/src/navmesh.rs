@@ -0,0 +1,671 @@
+// A navigation mesh covering the free space between static colliders, for
+// pathfinding.
+//
+// Every static is inflated by the agent radius into one or more convex
+// polygons, so an agent can go anywhere its center fits. The world is split
+// into square tiles, and each tile subtracts the obstacles overlapping it from
+// its square: the tile is cut into vertical slabs at every obstacle vertex and
+// crossing, the free space in each slab is a stack of trapezoids, and
+// trapezoids are merged left to right while the result stays convex. So the
+// mesh is made of convex polygons rather than triangles, which means fewer
+// nodes to search. triangles() fans them out for anything which wants
+// triangles.
+//
+// Polygons are linked wherever they share part of an axis aligned edge -
+// within a tile that's a slab line, and between tiles it's the tile border.
+//
+// Adding, moving or removing a static only marks the tiles it touches as
+// dirty. Those get rebuilt (and relinked to their neighbours) by rebuild,
+// which World calls in update and before handing the mesh out.
+use std::f64;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use ncollide2d::math::{Isometry, Point, Vector};
+use ncollide2d::shape::{Ball, ConvexPolygon, Cuboid, Polyline, ShapeHandle};
+use ncollide2d::bounding_volume::{self, BoundingVolume, AABB};
+
+use crate::N;
+
+pub(crate) const NAVMESH_TILE_SIZE: N = 16.0;
+// Refuse to cover more tiles than this. One static far away from the rest
+// would fill in every tile between them.
+pub(crate) const MAX_NAVMESH_TILES: usize = 1 << 14;
+// Circles and the rounded corners of inflated shapes are approximated by
+// segments which turn at most this much. The segments sit outside the true
+// curve, so agents never clip a corner.
+const ARC_STEP: N = f64::consts::FRAC_PI_4;
+const NAV_EPSILON: N = 0.0000001;
+
+pub type TileCoord = (i32, i32);
+
+// Identifies a polygon in the mesh. These are only valid until the tile they
+// point into is rebuilt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PolyRef {
+    pub(crate) tile: TileCoord,
+    pub(crate) index: usize,
+}
+
+// A link to a neighbouring polygon. The portal is the part of the edge they
+// share, with left and right as seen walking through it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NavLink {
+    pub poly: PolyRef,
+    pub left: Point<N>,
+    pub right: Point<N>,
+}
+
+#[derive(Debug, Clone)]
+struct NavPoly {
+    // Counter clockwise, and convex.
+    points: Vec<Point<N>>,
+    links: Vec<NavLink>,
+}
+
+// The inflated outline of one static.
+#[derive(Debug, Clone)]
+struct Obstacle {
+    polys: Vec<Vec<Point<N>>>,
+    aabb: AABB<N>,
+}
+
+pub struct NavMesh {
+    agent_radius: N,
+    // By collision object handle.
+    obstacles: BTreeMap<usize, Obstacle>,
+    tiles: BTreeMap<TileCoord, Vec<NavPoly>>,
+    // The tiles covering every obstacle, as of the last rebuild.
+    range: Option<(TileCoord, TileCoord)>,
+    dirty: BTreeSet<TileCoord>,
+    // Set when the last rebuild gave up because range was too big.
+    too_big: bool,
+}
+
+fn cross(a: Point<N>, b: Point<N>, c: Point<N>) -> N {
+    (b - a).perp(&(c - b))
+}
+
+fn tile_of(p: &Point<N>) -> TileCoord {
+    ((p.x / NAVMESH_TILE_SIZE).floor() as i32, (p.y / NAVMESH_TILE_SIZE).floor() as i32)
+}
+
+fn tile_rect(tile: TileCoord) -> AABB<N> {
+    AABB::new(
+        Point::new(tile.0 as N * NAVMESH_TILE_SIZE, tile.1 as N * NAVMESH_TILE_SIZE),
+        Point::new((tile.0 + 1) as N * NAVMESH_TILE_SIZE, (tile.1 + 1) as N * NAVMESH_TILE_SIZE),
+    )
+}
+
+fn tiles_in(aabb: &AABB<N>) -> (TileCoord, TileCoord) {
+    (tile_of(&aabb.mins), tile_of(&aabb.maxs))
+}
+
+fn in_range(range: &(TileCoord, TileCoord), t: &TileCoord) -> bool {
+    let ((x0, y0), (x1, y1)) = *range;
+    t.0 >= x0 && t.0 <= x1 && t.1 >= y0 && t.1 <= y1
+}
+
+fn tile_count(range: &(TileCoord, TileCoord)) -> u64 {
+    let ((x0, y0), (x1, y1)) = *range;
+    ((x1 as i64 - x0 as i64 + 1) as u64).saturating_mul((y1 as i64 - y0 as i64 + 1) as u64)
+}
+
+fn each_tile(range: &(TileCoord, TileCoord)) -> impl Iterator<Item=TileCoord> {
+    let ((x0, y0), (x1, y1)) = *range;
+    (x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| (x, y)))
+}
+
+fn neighbours(t: TileCoord) -> [TileCoord; 4] {
+    [(t.0 - 1, t.1), (t.0 + 1, t.1), (t.0, t.1 - 1), (t.0, t.1 + 1)]
+}
+
+// Counter clockwise, without collinear points. Fewer than 3 points come back
+// as they are (minus duplicates).
+fn convex_hull(points: &[Point<N>]) -> Vec<Point<N>> {
+    let mut ps = points.to_vec();
+    ps.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap_or(Ordering::Equal));
+    ps.dedup_by(|a, b| (*a - *b).norm() < NAV_EPSILON);
+    if ps.len() < 3 { return ps; }
+
+    let half = |ps: &mut dyn Iterator<Item=&Point<N>>| {
+        let mut out: Vec<Point<N>> = Vec::new();
+        for p in ps {
+            while out.len() >= 2 && cross(out[out.len() - 2], out[out.len() - 1], *p) <= 0.0 {
+                out.pop();
+            }
+            out.push(*p);
+        }
+        out.pop();
+        out
+    };
+    let mut hull = half(&mut ps.iter());
+    hull.extend(half(&mut ps.iter().rev()));
+    hull
+}
+
+// Grows the convex hull of the points by r. A single point becomes a circle
+// and two points a capsule, both made of straight segments.
+fn inflate_hull(points: &[Point<N>], r: N) -> Vec<Point<N>> {
+    let hull = convex_hull(points);
+    let n = hull.len();
+    if r <= NAV_EPSILON {
+        return if n >= 3 { hull } else { Vec::new() };
+    }
+
+    // The angle of the outward normal of the edge starting at i.
+    let normal = |i: usize| {
+        let d = hull[(i + 1) % n] - hull[i];
+        (-d.x).atan2(d.y)
+    };
+
+    let mut out = Vec::new();
+    for (i, v) in hull.iter().enumerate() {
+        let (from, mut to) = if n == 1 {
+            (0.0, 2.0 * f64::consts::PI)
+        } else {
+            (normal((i + n - 1) % n), normal(i))
+        };
+        while to < from { to += 2.0 * f64::consts::PI; }
+
+        let steps = ((to - from) / ARC_STEP).ceil().max(1.0);
+        let step = (to - from) / steps;
+        // Far enough out that the segments are tangent to the circle.
+        let d = r / (step / 2.0).cos();
+        for k in 0..steps as usize {
+            let a = from + (k as N + 0.5) * step;
+            out.push(v + Vector::new(a.cos(), a.sin()) * d);
+        }
+    }
+    out
+}
+
+// The convex pieces an agent of radius r can't put its center in. Unknown
+// shapes are treated like their bounding box.
+fn inflate(shape: &ShapeHandle<N>, pos: &Isometry<N>, r: N) -> Vec<Vec<Point<N>>> {
+    let polys = if let Some(ball) = shape.as_shape::<Ball<N>>() {
+        vec![inflate_hull(&[pos * Point::origin()], r + ball.radius)]
+    } else if let Some(cuboid) = shape.as_shape::<Cuboid<N>>() {
+        let he = cuboid.half_extents;
+        vec![inflate_hull(&[
+            pos * Point::new(-he.x, -he.y),
+            pos * Point::new(he.x, -he.y),
+            pos * Point::new(he.x, he.y),
+            pos * Point::new(-he.x, he.y),
+        ], r)]
+    } else if let Some(poly) = shape.as_shape::<ConvexPolygon<N>>() {
+        let points = poly.points().iter().map(|p| pos * p).collect::<Vec<_>>();
+        vec![inflate_hull(&points, r)]
+    } else if let Some(polyline) = shape.as_shape::<Polyline<N>>() {
+        // Each segment separately, so the inside of a hollow outline stays
+        // walkable.
+        let points = polyline.points();
+        polyline.edges().iter().map(|edge| {
+            inflate_hull(&[pos * points[edge.indices.x], pos * points[edge.indices.y]], r)
+        }).collect()
+    } else {
+        let aabb = bounding_volume::aabb(shape.as_ref(), pos);
+        let (mins, maxs) = (aabb.mins, aabb.maxs);
+        vec![inflate_hull(&[mins, Point::new(maxs.x, mins.y), maxs, Point::new(mins.x, maxs.y)], r)]
+    };
+    polys.into_iter().filter(|p| p.len() >= 3).collect()
+}
+
+// Sutherland-Hodgman. The polygon is convex, so the result is too.
+fn clip(poly: &[Point<N>], rect: &AABB<N>) -> Vec<Point<N>> {
+    let (mins, maxs) = (rect.mins, rect.maxs);
+    let planes = [(0, mins.x, true), (0, maxs.x, false), (1, mins.y, true), (1, maxs.y, false)];
+
+    let mut out = poly.to_vec();
+    for &(axis, bound, above) in planes.iter() {
+        let inside = |p: &Point<N>| if above { p[axis] >= bound } else { p[axis] <= bound };
+        let cut = |a: Point<N>, b: Point<N>| {
+            let t = (bound - a[axis]) / (b[axis] - a[axis]);
+            let mut p = a + (b - a) * t;
+            p[axis] = bound;
+            p
+        };
+
+        let input = std::mem::take(&mut out);
+        for (i, a) in input.iter().enumerate() {
+            let b = input[(i + 1) % input.len()];
+            match (inside(a), inside(&b)) {
+                (true, true) => out.push(b),
+                (true, false) => out.push(cut(*a, b)),
+                (false, true) => { out.push(cut(*a, b)); out.push(b); },
+                (false, false) => {},
+            }
+        }
+    }
+    out
+}
+
+fn area(poly: &[Point<N>]) -> N {
+    (0..poly.len()).map(|i| {
+        let (a, b) = (poly[i], poly[(i + 1) % poly.len()]);
+        a.x * b.y - b.x * a.y
+    }).sum::<N>() / 2.0
+}
+
+// Whether p is inside the (counter clockwise, convex) polygon by more than
+// margin. A negative margin lets points just outside count too.
+fn contains(poly: &[Point<N>], p: &Point<N>, margin: N) -> bool {
+    (0..poly.len()).all(|i| {
+        let (a, b) = (poly[i], poly[(i + 1) % poly.len()]);
+        (b - a).perp(&(p - a)) > margin * (b - a).norm()
+    })
+}
+
+// Drops repeated and collinear points.
+fn simplify(mut points: Vec<Point<N>>) -> Vec<Point<N>> {
+    loop {
+        let n = points.len();
+        if n < 3 { return points; }
+        let redundant = (0..n).find(|&i| {
+            let (a, b, c) = (points[(i + n - 1) % n], points[i], points[(i + 1) % n]);
+            (b - a).norm() < NAV_EPSILON || cross(a, b, c).abs() < NAV_EPSILON * (c - a).norm()
+        });
+        match redundant {
+            Some(i) => { points.remove(i); },
+            None => return points,
+        }
+    }
+}
+
+pub(crate) fn closest_on_segment(p: &Point<N>, a: Point<N>, b: Point<N>) -> Point<N> {
+    let d = b - a;
+    let len2 = d.norm_squared();
+    if len2 <= 0.0 { return a; }
+    a + d * ((p - a).dot(&d) / len2).clamp(0.0, 1.0)
+}
+
+fn is_convex(points: &[Point<N>]) -> bool {
+    let n = points.len();
+    (0..n).all(|i| cross(points[i], points[(i + 1) % n], points[(i + 2) % n]) > 0.0)
+}
+
+// A non vertical obstacle edge, or the top or bottom of the tile. a is the
+// left end.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    a: Point<N>,
+    b: Point<N>,
+}
+
+impl Segment {
+    fn y_at(&self, x: N) -> N {
+        if x <= self.a.x { self.a.y }
+        else if x >= self.b.x { self.b.y }
+        else { self.a.y + (self.b.y - self.a.y) * (x - self.a.x) / (self.b.x - self.a.x) }
+    }
+
+    // Where the two segments cross, if they do.
+    fn crossing(&self, other: &Segment) -> Option<N> {
+        let d1 = self.b - self.a;
+        let d2 = other.b - other.a;
+        let denom = d1.perp(&d2);
+        if denom.abs() < NAV_EPSILON { return None; }
+        let offset = other.a - self.a;
+        let t = offset.perp(&d2) / denom;
+        let u = offset.perp(&d1) / denom;
+        if t > 0.0 && t < 1.0 && u > 0.0 && u < 1.0 { Some(self.a.x + d1.x * t) } else { None }
+    }
+}
+
+// A piece of a slab between xa and xb, bounded by two segments. lo and hi are
+// (y at xa, y at xb) of the segments below and above.
+fn trapezoid(xa: N, xb: N, lo: (N, N), hi: (N, N)) -> Vec<Point<N>> {
+    simplify(vec![
+        Point::new(xa, lo.0),
+        Point::new(xb, lo.1),
+        Point::new(xb, hi.1),
+        Point::new(xa, hi.0),
+    ])
+}
+
+// Glues a trapezoid on to the right hand edge of a polygon, if the result is
+// convex.
+fn merge(poly: &[Point<N>], xa: N, xb: N, lo: (N, N), hi: (N, N)) -> Option<Vec<Point<N>>> {
+    let n = poly.len();
+    let near = |p: &Point<N>, y: N| (p.x - xa).abs() < NAV_EPSILON && (p.y - y).abs() < NAV_EPSILON;
+    let i = (0..n).find(|&i| near(&poly[i], lo.0) && near(&poly[(i + 1) % n], hi.0))?;
+
+    let mut points = poly[..=i].to_vec();
+    points.push(Point::new(xb, lo.1));
+    points.push(Point::new(xb, hi.1));
+    points.extend_from_slice(&poly[i + 1..]);
+    let points = simplify(points);
+    if is_convex(&points) { Some(points) } else { None }
+}
+
+// An axis aligned polygon edge, which might be shared with a neighbour.
+#[derive(Debug, Clone, Copy)]
+struct AxisEdge {
+    vertical: bool,
+    // x for vertical edges, y for horizontal ones.
+    coord: N,
+    // The extent along the edge, and whether it runs from lo to hi.
+    lo: N,
+    hi: N,
+    increasing: bool,
+    poly: PolyRef,
+}
+
+impl AxisEdge {
+    fn of(a: Point<N>, b: Point<N>, poly: PolyRef) -> Option<AxisEdge> {
+        let vertical = if (a.x - b.x).abs() < NAV_EPSILON { true }
+            else if (a.y - b.y).abs() < NAV_EPSILON { false }
+            else { return None; };
+        let (coord, from, to) = if vertical { (a.x, a.y, b.y) } else { (a.y, a.x, b.x) };
+        Some(AxisEdge { vertical, coord, lo: from.min(to), hi: from.max(to), increasing: to > from, poly })
+    }
+
+    fn point(&self, v: N) -> Point<N> {
+        if self.vertical { Point::new(self.coord, v) } else { Point::new(v, self.coord) }
+    }
+
+    fn key(&self) -> (bool, N) {
+        (self.vertical, self.coord)
+    }
+}
+
+impl NavMesh {
+    pub(crate) fn new(agent_radius: N) -> NavMesh {
+        NavMesh {
+            agent_radius,
+            obstacles: BTreeMap::new(),
+            tiles: BTreeMap::new(),
+            range: None,
+            dirty: BTreeSet::new(),
+            too_big: false,
+        }
+    }
+
+    pub fn agent_radius(&self) -> N {
+        self.agent_radius
+    }
+
+    pub(crate) fn too_big(&self) -> bool {
+        self.too_big
+    }
+
+    fn mark_dirty(&mut self, aabb: &AABB<N>) {
+        self.dirty.extend(each_tile(&tiles_in(aabb)));
+    }
+
+    // Adds or moves the obstacle for a static.
+    pub(crate) fn set_obstacle(&mut self, handle: usize, shape: &ShapeHandle<N>, pos: &Isometry<N>) {
+        self.remove_obstacle(handle);
+        let polys = inflate(shape, pos, self.agent_radius);
+        let mut points = polys.iter().flatten();
+        let first = match points.next() {
+            Some(p) => *p,
+            None => return,
+        };
+        let aabb = points.fold(AABB::new(first, first), |aabb, p| {
+            aabb.merged(&AABB::new(*p, *p))
+        });
+        self.mark_dirty(&aabb);
+        self.obstacles.insert(handle, Obstacle { polys, aabb });
+    }
+
+    pub(crate) fn remove_obstacle(&mut self, handle: usize) {
+        if let Some(o) = self.obstacles.remove(&handle) {
+            self.mark_dirty(&o.aabb);
+        }
+    }
+
+    // Rebuilds every dirty tile, and returns how many tiles were built. The
+    // mesh covers the tiles touched by the bounding box of all the
+    // obstacles, plus a ring of empty tiles around them. So the edge of the
+    // mesh is always open ground.
+    //
+    // Returns None, and empties the mesh, if that would be more than
+    // MAX_NAVMESH_TILES. It stays empty (and too_big) until the obstacles
+    // change.
+    pub(crate) fn rebuild(&mut self) -> Option<usize> {
+        let range = self.obstacles.values()
+            .map(|o| o.aabb)
+            .fold(None, |acc: Option<AABB<N>>, aabb| Some(match acc {
+                Some(acc) => acc.merged(&aabb),
+                None => aabb,
+            }))
+            .map(|aabb| {
+                let ((x0, y0), (x1, y1)) = tiles_in(&aabb);
+                ((x0.saturating_sub(1), y0.saturating_sub(1)), (x1.saturating_add(1), y1.saturating_add(1)))
+            });
+        if self.dirty.is_empty() && range == self.range { return Some(0); }
+
+        if range.is_some_and(|r| tile_count(&r) > MAX_NAVMESH_TILES as u64) {
+            self.tiles.clear();
+            self.dirty.clear();
+            self.range = range;
+            self.too_big = true;
+            return None;
+        }
+        self.too_big = false;
+
+        let mut build = std::mem::take(&mut self.dirty);
+        let mut relink = BTreeSet::new();
+
+        let stale = self.tiles.keys()
+            .filter(|t| !range.is_some_and(|r| in_range(&r, t)))
+            .cloned()
+            .collect::<Vec<_>>();
+        for t in stale {
+            self.tiles.remove(&t);
+            relink.extend(neighbours(t).iter());
+        }
+        if let Some(r) = range {
+            build.retain(|t| in_range(&r, t));
+            build.extend(each_tile(&r).filter(|t| !self.tiles.contains_key(t)));
+        } else {
+            build.clear();
+        }
+        self.range = range;
+
+        for t in build.iter() {
+            let polys = self.build_tile(*t);
+            self.tiles.insert(*t, polys);
+            relink.insert(*t);
+            relink.extend(neighbours(*t).iter());
+        }
+        for t in relink {
+            if self.tiles.contains_key(&t) { self.link_tile(t); }
+        }
+        Some(build.len())
+    }
+
+    fn build_tile(&self, tile: TileCoord) -> Vec<NavPoly> {
+        let rect = tile_rect(tile);
+        let (x0, y0, x1, y1) = (rect.mins.x, rect.mins.y, rect.maxs.x, rect.maxs.y);
+
+        let obstacles = self.obstacles.values()
+            .filter(|o| o.aabb.intersects(&rect))
+            .flat_map(|o| o.polys.iter())
+            .map(|p| clip(p, &rect))
+            .filter(|p| p.len() >= 3 && area(p) > NAV_EPSILON)
+            .collect::<Vec<_>>();
+
+        let mut segments = vec![
+            Segment { a: Point::new(x0, y0), b: Point::new(x1, y0) },
+            Segment { a: Point::new(x0, y1), b: Point::new(x1, y1) },
+        ];
+        let mut xs = vec![x0, x1];
+        for poly in obstacles.iter() {
+            for (i, a) in poly.iter().enumerate() {
+                let b = poly[(i + 1) % poly.len()];
+                xs.push(a.x);
+                if (b.x - a.x).abs() > NAV_EPSILON {
+                    segments.push(if a.x < b.x { Segment { a: *a, b } } else { Segment { a: b, b: *a } });
+                }
+            }
+        }
+        for (i, s) in segments.iter().enumerate() {
+            xs.extend(segments[i + 1..].iter().filter_map(|t| s.crossing(t)));
+        }
+        for x in xs.iter_mut() { *x = x.max(x0).min(x1); }
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        xs.dedup_by(|a, b| *a - *b < NAV_EPSILON);
+        *xs.last_mut().unwrap() = x1;
+
+        let mut polys: Vec<Vec<Point<N>>> = Vec::new();
+        // The right hand edges of the polygons which reach the current slab
+        // line, as (polygon, bottom, top).
+        let mut open: Vec<(usize, N, N)> = Vec::new();
+
+        for slab in xs.windows(2) {
+            let (xa, xb) = (slab[0], slab[1]);
+            let mut spans = segments.iter()
+                .filter(|s| s.a.x <= xa + NAV_EPSILON && s.b.x >= xb - NAV_EPSILON)
+                .map(|s| (s.y_at(xa).max(y0).min(y1), s.y_at(xb).max(y0).min(y1)))
+                .collect::<Vec<_>>();
+            // Nothing crosses inside a slab, so this orders them everywhere.
+            spans.sort_by(|a, b| (a.0 + a.1).partial_cmp(&(b.0 + b.1)).unwrap_or(Ordering::Equal));
+            spans.dedup_by(|a, b| (a.0 - b.0).abs() < NAV_EPSILON && (a.1 - b.1).abs() < NAV_EPSILON);
+
+            let mut next_open = Vec::new();
+            for pair in spans.windows(2) {
+                let (lo, hi) = (pair[0], pair[1]);
+                if hi.0 - lo.0 < NAV_EPSILON && hi.1 - lo.1 < NAV_EPSILON { continue; }
+                let mid = Point::new((xa + xb) / 2.0, (lo.0 + lo.1 + hi.0 + hi.1) / 4.0);
+                if obstacles.iter().any(|o| contains(o, &mid, NAV_EPSILON)) { continue; }
+
+                let merged = open.iter()
+                    .find(|(_, bottom, top)| (bottom - lo.0).abs() < NAV_EPSILON && (top - hi.0).abs() < NAV_EPSILON)
+                    .and_then(|&(i, _, _)| merge(&polys[i], xa, xb, lo, hi).map(|p| (i, p)));
+                let i = match merged {
+                    Some((i, points)) => { polys[i] = points; i },
+                    None => { polys.push(trapezoid(xa, xb, lo, hi)); polys.len() - 1 },
+                };
+                if hi.1 - lo.1 > NAV_EPSILON { next_open.push((i, lo.1, hi.1)); }
+            }
+            open = next_open;
+        }
+
+        polys.into_iter()
+            .filter(|p| p.len() >= 3)
+            .map(|points| NavPoly { points, links: Vec::new() })
+            .collect()
+    }
+
+    fn edges(&self, tile: TileCoord) -> Vec<AxisEdge> {
+        let mut edges = Vec::new();
+        if let Some(polys) = self.tiles.get(&tile) {
+            for (index, poly) in polys.iter().enumerate() {
+                let n = poly.points.len();
+                edges.extend((0..n).filter_map(|i| {
+                    AxisEdge::of(poly.points[i], poly.points[(i + 1) % n], PolyRef { tile, index })
+                }));
+            }
+        }
+        edges
+    }
+
+    // Recomputes the links out of every polygon in the tile.
+    fn link_tile(&mut self, tile: TileCoord) {
+        let mine = self.edges(tile);
+        let mut others = mine.clone();
+        for t in neighbours(tile).iter() {
+            others.extend(self.edges(*t));
+        }
+        others.sort_by(|a, b| a.key().partial_cmp(&b.key()).unwrap_or(Ordering::Equal));
+
+        let polys = self.tiles.get_mut(&tile).unwrap();
+        for poly in polys.iter_mut() { poly.links.clear(); }
+
+        for e in mine.iter() {
+            let start = others.partition_point(|o| o.key() < (e.vertical, e.coord - NAV_EPSILON));
+            for o in others[start..].iter().take_while(|o| o.key() <= (e.vertical, e.coord + NAV_EPSILON)) {
+                if o.poly == e.poly || o.increasing == e.increasing { continue; }
+                let (lo, hi) = (e.lo.max(o.lo), e.hi.min(o.hi));
+                if hi - lo < NAV_EPSILON { continue; }
+
+                // Walking out through the edge, its end is on the left.
+                let (left, right) = if e.increasing { (hi, lo) } else { (lo, hi) };
+                polys[e.poly.index].links.push(NavLink {
+                    poly: o.poly,
+                    left: e.point(left),
+                    right: e.point(right),
+                });
+            }
+        }
+    }
+
+    pub fn polygon_count(&self) -> usize {
+        self.tiles.values().map(|polys| polys.len()).sum()
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.tiles.len()
+    }
+
+    // Every polygon, tile by tile.
+    pub fn polygons(&self) -> impl Iterator<Item=(PolyRef, &[Point<N>])> + '_ {
+        self.tiles.iter().flat_map(|(tile, polys)| {
+            polys.iter().enumerate().map(move |(index, poly)| (PolyRef { tile: *tile, index }, &poly.points[..]))
+        })
+    }
+
+    // Counter clockwise.
+    pub fn polygon(&self, r: PolyRef) -> &[Point<N>] {
+        &self.tiles[&r.tile][r.index].points
+    }
+
+    pub fn links(&self, r: PolyRef) -> &[NavLink] {
+        &self.tiles[&r.tile][r.index].links
+    }
+
+    // The polygon containing p, or None if an agent can't stand there.
+    pub fn find_polygon(&self, p: &Point<N>) -> Option<PolyRef> {
+        let tile = tile_of(p);
+        self.tiles.get(&tile)?.iter()
+            .position(|poly| contains(&poly.points, p, -NAV_EPSILON))
+            .map(|index| PolyRef { tile, index })
+    }
+
+    // Whether p is in the area the mesh covers. Outside it there's nothing in
+    // the way.
+    pub fn covers(&self, p: &Point<N>) -> bool {
+        self.range.is_some_and(|r| in_range(&r, &tile_of(p)))
+    }
+
+    // The closest point to p an agent can stand on, looking at most max_dist
+    // away. Points outside the mesh are pulled straight on to its edge.
+    pub fn nearest(&self, p: &Point<N>, max_dist: N) -> Option<(PolyRef, Point<N>)> {
+        let ((x0, y0), (x1, y1)) = self.range?;
+        let (mins, maxs) = (tile_rect((x0, y0)).mins.coords, tile_rect((x1, y1)).maxs.coords);
+        let inset = NAV_EPSILON * 10.0;
+        let p = Point::new(
+            p.x.max(mins.x + inset).min(maxs.x - inset),
+            p.y.max(mins.y + inset).min(maxs.y - inset),
+        );
+        if let Some(r) = self.find_polygon(&p) { return Some((r, p)); }
+
+        let area = AABB::new(p - Vector::repeat(max_dist), p + Vector::repeat(max_dist));
+        let mut best: Option<(N, PolyRef, Point<N>)> = None;
+        for tile in each_tile(&tiles_in(&area)) {
+            let polys = match self.tiles.get(&tile) {
+                Some(polys) => polys,
+                None => continue,
+            };
+            for (index, poly) in polys.iter().enumerate() {
+                let n = poly.points.len();
+                for i in 0..n {
+                    let q = closest_on_segment(&p, poly.points[i], poly.points[(i + 1) % n]);
+                    let d = (q - p).norm();
+                    if d <= max_dist && best.is_none_or(|(bd, _, _)| d < bd) {
+                        best = Some((d, PolyRef { tile, index }, q));
+                    }
+                }
+            }
+        }
+        best.map(|(_, r, q)| (r, q))
+    }
+
+    // The polygons split into triangles, counter clockwise.
+    pub fn triangles(&self) -> Vec<[Point<N>; 3]> {
+        self.polygons().flat_map(|(_, points)| {
+            (1..points.len() - 1).map(move |i| [points[0], points[i], points[i + 1]])
+        }).collect()
+    }
+}
@@ -0,0 +1,152 @@
+// Pathfinding over the navigation mesh. A* finds a chain of polygons from the
+// start to the goal, then the funnel algorithm pulls the path tight through
+// the portals between them, so it only turns at corners.
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use ncollide2d::math::Point;
+
+use crate::N;
+use crate::navmesh::{closest_on_segment, NavLink, NavMesh, PolyRef};
+
+// An open polygon in the A* search. Ordered so the heap pops the lowest
+// estimate first, and ties go to the lowest polygon.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Open {
+    estimate: N,
+    poly: PolyRef,
+}
+
+impl Eq for Open {}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Open) -> Ordering {
+        other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+            .then_with(|| other.poly.cmp(&self.poly))
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+struct Visit {
+    cost: N,
+    // Where we entered the polygon.
+    pos: Point<N>,
+    from: Option<(PolyRef, NavLink)>,
+}
+
+// The portals crossed going from start to goal, or None if there's no way
+// through. Each polygon is costed from the closest point on the portal we
+// entered it by.
+fn portals(nav: &NavMesh, start: (PolyRef, Point<N>), goal: (PolyRef, Point<N>)) -> Option<Vec<NavLink>> {
+    let mut visits = HashMap::new();
+    let mut open = BinaryHeap::new();
+    visits.insert(start.0, Visit { cost: 0.0, pos: start.1, from: None });
+    open.push(Open { estimate: (goal.1 - start.1).norm(), poly: start.0 });
+
+    while let Some(Open { estimate, poly }) = open.pop() {
+        if poly == goal.0 { break; }
+        let (cost, pos) = {
+            let v = &visits[&poly];
+            (v.cost, v.pos)
+        };
+        // Already found a better way here.
+        if estimate > cost + (goal.1 - pos).norm() { continue; }
+
+        for link in nav.links(poly) {
+            let next = closest_on_segment(&pos, link.left, link.right);
+            let next_cost = cost + (next - pos).norm();
+            if visits.get(&link.poly).is_some_and(|v| v.cost <= next_cost) { continue; }
+            visits.insert(link.poly, Visit { cost: next_cost, pos: next, from: Some((poly, *link)) });
+            open.push(Open { estimate: next_cost + (goal.1 - next).norm(), poly: link.poly });
+        }
+    }
+
+    let mut links = Vec::new();
+    let mut poly = goal.0;
+    while poly != start.0 {
+        let (prev, link) = visits.get(&poly)?.from?;
+        links.push(link);
+        poly = prev;
+    }
+    links.reverse();
+    Some(links)
+}
+
+fn cross(a: Point<N>, b: Point<N>, c: Point<N>) -> N {
+    (b - a).perp(&(c - a))
+}
+
+// The "simple stupid funnel algorithm". The funnel starts at the apex and
+// opens out to the left and right ends of a portal. Each portal can only
+// narrow it; once a side would cross over the other, the corner it crosses
+// becomes the new apex.
+fn string_pull(start: Point<N>, goal: Point<N>, links: &[NavLink]) -> Vec<Point<N>> {
+    let mut portals = vec![(start, start)];
+    portals.extend(links.iter().map(|l| (l.left, l.right)));
+    portals.push((goal, goal));
+
+    let mut path = vec![start];
+    let (mut apex, mut left, mut right) = (start, start, start);
+    let (mut left_i, mut right_i) = (0, 0);
+
+    let mut i = 1;
+    while i < portals.len() {
+        let (l, r) = portals[i];
+
+        if cross(apex, right, r) >= 0.0 {
+            if apex == right || cross(apex, left, r) < 0.0 {
+                right = r;
+                right_i = i;
+            } else {
+                // The right side crossed the left. Turn the corner there.
+                path.push(left);
+                apex = left;
+                right = apex;
+                right_i = left_i;
+                i = left_i + 1;
+                continue;
+            }
+        }
+
+        if cross(apex, left, l) <= 0.0 {
+            if apex == left || cross(apex, right, l) > 0.0 {
+                left = l;
+                left_i = i;
+            } else {
+                path.push(right);
+                apex = right;
+                left = apex;
+                left_i = right_i;
+                i = right_i + 1;
+                continue;
+            }
+        }
+        i += 1;
+    }
+
+    path.push(goal);
+    path.dedup();
+    path
+}
+
+// Waypoints from start to goal, including both ends. Starts and goals which
+// aren't quite on the mesh (eg a unit up against a wall) are snapped to the
+// closest point within snap_dist. Returns None if either end is too far off
+// the mesh, or there's no way between them.
+pub(crate) fn find_path(nav: &NavMesh, start: Point<N>, goal: Point<N>, snap_dist: N) -> Option<Vec<Point<N>>> {
+    if nav.polygon_count() == 0 { return Some(vec![start, goal]); }
+
+    let from = nav.nearest(&start, snap_dist)?;
+    let to = nav.nearest(&goal, snap_dist)?;
+    let links = portals(nav, from, to)?;
+    let mut path = string_pull(from.1, to.1, &links);
+
+    // Anything outside the mesh is open ground, so walk straight there.
+    if !nav.covers(&start) { path.insert(0, start); }
+    if !nav.covers(&goal) { path.push(goal); }
+    Some(path)
+}
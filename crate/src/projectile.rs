@@ -0,0 +1,66 @@
+// Projectiles which bounce. Normal projectiles are moved with try_move, which
+// stops them dead at the first solid thing they hit. A launched projectile is
+// instead moved by update: each frame it sweeps along its velocity, reflects
+// off anything solid it hits, and stops at the first unit. What happened is
+// reported through projectile_events either way.
+use ncollide2d::math::{Point, Vector};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::N;
+
+// After a bounce the projectile is backed off the surface by this much, so
+// the next sweep doesn't start touching it.
+pub(crate) const BOUNCE_GAP: N = 0.001;
+// Most bounces we'll follow in a single frame. Only matters for projectiles
+// which are rattling around in a tight corner.
+pub(crate) const MAX_BOUNCES_PER_FRAME: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Ricochet {
+    // Per frame, like everything else.
+    pub vel: Vector<N>,
+    // 1 bounces off at full speed, 0 slides along the wall.
+    pub restitution: N,
+    pub max_bounces: u32,
+    pub bounces: u32,
+}
+
+impl Ricochet {
+    // Reflects the velocity off a surface. The normal points into the
+    // surface.
+    pub(crate) fn reflect(&mut self, normal: Vector<N>) {
+        let vn = self.vel.dot(&normal);
+        if vn > 0.0 {
+            self.vel -= normal * (vn * (1.0 + self.restitution));
+        }
+    }
+}
+
+// Each projectile event is emitted as 7 floats by the wasm bindings:
+// [kind, projectile id, other id, x, y, nx, ny].
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectileEventKind {
+    // Bounced off a static or dynamic object and kept going.
+    Bounce,
+    // Ran into a unit. Launched projectiles stop there, but ones moved with
+    // try_move carry on through.
+    Hit,
+    // Ran into something solid and stopped - either a launched projectile
+    // which has used up its bounces, or one moved with try_move.
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProjectileEvent {
+    pub kind: ProjectileEventKind,
+    pub projectile: u32,
+    // The entity id of whatever the projectile ran into.
+    pub other: u32,
+    // Where it touched, on the surface of the other object.
+    pub point: Point<N>,
+    // The surface normal at that point, pointing back out towards the
+    // projectile.
+    pub normal: Vector<N>,
+}
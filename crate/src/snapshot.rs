@@ -0,0 +1,509 @@
+// The binary format used by World::snapshot / World::restore. Everything is
+// little endian:
+//
+//   magic         b"CWSN"
+//   version       u16 (SNAPSHOT_VERSION)
+//   flags         u8 (bit 0: deterministic)
+//   knockback     f64 - the fraction of knockback units lose each frame
+//   slots         u32 - how many handles have ever been allocated
+//   free handles  u32 count, then u32 each, oldest first
+//   objects       u32 count, then for each object:
+//     handle u32, id u32, group u8
+//     shape         u8 tag, then
+//                     0 = ball: f64 radius
+//                     1 = cuboid: f64 half extents x, y
+//                     2 = convex polygon: u32 count, then f64 x, y for each
+//                         point (counter clockwise)
+//                     3 = polyline: u32 count, then f64 x, y for each point,
+//                         then u32 count, then u32 a, b for each edge
+//     position      f64 x, y, then the rotation as f64 cos, sin (unit length)
+//     groups        u32 membership, whitelist, blacklist masks
+//     query type    u8 tag (0 = contacts, 1 = proximity), then two f64s
+//                   (linear and angular prediction, or margin and 0)
+//     body          only for the dynamic group (3): f64 velocity x, y,
+//                   angular velocity, inverse mass, inverse inertia,
+//                   restitution, friction, damping
+//     ricochet      only for the projectile group (2): u8 1 if launched, then
+//                   f64 velocity x, y, restitution, u32 max bounces, bounces
+//     knockback     only for the unit group (1): f64 velocity x, y
+//     vision        u8 1 if it has a vision cone, then f64 half angle, range,
+//                   then u32 count, then u32 handle for each unit it can see
+//                   (in increasing order)
+//     one way       only for the static group (0): u8 1 if it's a one way
+//                   wall, then f64 normal x, y
+//     material      u32 id, f64 friction, slip, u32 sound
+//
+// Bump SNAPSHOT_VERSION whenever this changes. Snapshots from other versions
+// are rejected rather than guessed at.
+use std::fmt;
+use nalgebra::Point2;
+use ncollide2d::shape::{Ball, ConvexPolygon, Cuboid, Polyline, ShapeHandle};
+use ncollide2d::math::{Point, Vector};
+use ncollide2d::pipeline::{CollisionGroups, GeometricQueryType};
+
+use crate::N;
+use crate::dynamics::Body;
+use crate::material::Material;
+use crate::projectile::Ricochet;
+use crate::vision::VisionCone;
+use crate::world::CGroup;
+
+pub(crate) const SNAPSHOT_MAGIC: &[u8; 4] = b"CWSN";
+pub(crate) const SNAPSHOT_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    BadTag(&'static str, u8),
+    TrailingBytes,
+    // A polygon or polyline which doesn't make sense.
+    BadShape,
+    // The handles in the snapshot don't fit together (eg two objects in one
+    // slot).
+    BadHandles,
+    // A position which isn't finite, or a rotation which isn't unit length.
+    BadPosition,
+    // Taking a snapshot of an object whose shape none of the make_*
+    // functions could have made. Has the entity id.
+    UnsupportedShape(u32),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a world snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::BadTag(what, tag) => write!(f, "unknown {} tag {}", what, tag),
+            SnapshotError::TrailingBytes => write!(f, "unexpected data after the end of the snapshot"),
+            SnapshotError::BadShape => write!(f, "snapshot contains an invalid shape"),
+            SnapshotError::BadHandles => write!(f, "snapshot handles are inconsistent"),
+            SnapshotError::BadPosition => write!(f, "snapshot contains an invalid position"),
+            SnapshotError::UnsupportedShape(id) => write!(f, "can't snapshot the shape of entity {}", id),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ShapeRecord {
+    Ball(N),
+    Cuboid(N, N),
+    ConvexPolygon(Vec<[N; 2]>),
+    Polyline(Vec<[N; 2]>, Vec<[u32; 2]>),
+}
+
+impl ShapeRecord {
+    // Only the shapes the make_* functions create are supported. id is just
+    // for the error.
+    pub(crate) fn from_shape(shape: &ShapeHandle<N>, id: u32) -> Result<ShapeRecord, SnapshotError> {
+        Ok(if let Some(ball) = shape.as_shape::<Ball<N>>() {
+            ShapeRecord::Ball(ball.radius)
+        } else if let Some(cuboid) = shape.as_shape::<Cuboid<N>>() {
+            let e = cuboid.half_extents;
+            ShapeRecord::Cuboid(e.x, e.y)
+        } else if let Some(poly) = shape.as_shape::<ConvexPolygon<N>>() {
+            ShapeRecord::ConvexPolygon(poly.points().iter().map(|p| [p.x, p.y]).collect())
+        } else if let Some(polyline) = shape.as_shape::<Polyline<N>>() {
+            ShapeRecord::Polyline(
+                polyline.points().iter().map(|p| [p.x, p.y]).collect(),
+                polyline.edges().iter().map(|e| [e.indices.x as u32, e.indices.y as u32]).collect()
+            )
+        } else {
+            return Err(SnapshotError::UnsupportedShape(id));
+        })
+    }
+
+    // The points are already in the order ncollide keeps them in, so this
+    // rebuilds exactly the same shape.
+    pub(crate) fn to_shape(&self) -> Result<ShapeHandle<N>, SnapshotError> {
+        let points = |ps: &[[N; 2]]| ps.iter().map(|p| Point::new(p[0], p[1])).collect::<Vec<_>>();
+        Ok(match self {
+            ShapeRecord::Ball(r) => ShapeHandle::new(Ball::new(*r)),
+            ShapeRecord::Cuboid(x, y) => ShapeHandle::new(Cuboid::new(Vector::new(*x, *y))),
+            ShapeRecord::ConvexPolygon(ps) => ShapeHandle::new(
+                ConvexPolygon::try_new(points(ps)).ok_or(SnapshotError::BadShape)?
+            ),
+            ShapeRecord::Polyline(ps, edges) => {
+                if edges.iter().any(|e| e[0] as usize >= ps.len() || e[1] as usize >= ps.len()) {
+                    return Err(SnapshotError::BadShape);
+                }
+                let edges = edges.iter().map(|e| Point2::new(e[0] as usize, e[1] as usize)).collect();
+                ShapeHandle::new(Polyline::new(points(ps), Some(edges)))
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum QueryRecord {
+    Contacts(N, N),
+    Proximity(N),
+}
+
+impl QueryRecord {
+    pub(crate) fn from_query(q: GeometricQueryType<N>) -> QueryRecord {
+        match q {
+            GeometricQueryType::Contacts(l, a) => QueryRecord::Contacts(l, a),
+            GeometricQueryType::Proximity(m) => QueryRecord::Proximity(m),
+        }
+    }
+
+    pub(crate) fn to_query(self) -> GeometricQueryType<N> {
+        match self {
+            QueryRecord::Contacts(l, a) => GeometricQueryType::Contacts(l, a),
+            QueryRecord::Proximity(m) => GeometricQueryType::Proximity(m),
+        }
+    }
+}
+
+// CollisionGroups doesn't expose its masks, so we rebuild them a group at a
+// time. Bit 31 of the whitelist is the self interaction flag, same as
+// ncollide uses internally.
+const SELF_INTERACTION: u32 = 1 << 31;
+
+pub(crate) fn groups_to_masks(g: &CollisionGroups) -> [u32; 3] {
+    let mut masks = [0; 3];
+    for i in 0..=CollisionGroups::max_group_id() {
+        if g.is_member_of(i) { masks[0] |= 1 << i; }
+        if g.is_group_whitelisted(i) { masks[1] |= 1 << i; }
+        if g.is_group_blacklisted(i) { masks[2] |= 1 << i; }
+    }
+    if g.can_interact_with_self() { masks[1] |= SELF_INTERACTION; }
+    masks
+}
+
+pub(crate) fn masks_to_groups(masks: [u32; 3]) -> CollisionGroups {
+    let list = |mask: u32| (0..=CollisionGroups::max_group_id())
+        .filter(|i| mask & (1 << i) != 0)
+        .collect::<Vec<_>>();
+    let mut g = CollisionGroups::new()
+        .with_membership(&list(masks[0]))
+        .with_whitelist(&list(masks[1]))
+        .with_blacklist(&list(masks[2]));
+    if masks[1] & SELF_INTERACTION != 0 {
+        g.enable_self_interaction();
+    } else {
+        g.disable_self_interaction();
+    }
+    g
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ObjectRecord {
+    pub handle: u32,
+    pub id: u32,
+    pub e_type: CGroup,
+    pub shape: ShapeRecord,
+    // x, y, cos, sin
+    pub pos: [N; 4],
+    // membership, whitelist, blacklist
+    pub groups: [u32; 3],
+    pub query: QueryRecord,
+    pub body: Option<Body>,
+    pub ricochet: Option<Ricochet>,
+    pub knockback: Vector<N>,
+    pub vision: Option<VisionCone>,
+    pub one_way: Option<Vector<N>>,
+    pub material: Material,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotData {
+    pub deterministic: bool,
+    pub knockback_decay: N,
+    pub slots: u32,
+    pub free_handles: Vec<u32>,
+    pub objects: Vec<ObjectRecord>,
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) { self.0.push(v); }
+    fn u16(&mut self, v: u16) { self.0.extend_from_slice(&v.to_le_bytes()); }
+    fn u32(&mut self, v: u32) { self.0.extend_from_slice(&v.to_le_bytes()); }
+    fn f64(&mut self, v: f64) { self.0.extend_from_slice(&v.to_le_bytes()); }
+
+    fn points(&mut self, ps: &[[N; 2]]) {
+        self.u32(ps.len() as u32);
+        for p in ps.iter() { self.f64(p[0]); self.f64(p[1]); }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < n { return Err(SnapshotError::Truncated); }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        let mut b = [0; 2];
+        b.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(b))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn f64(&mut self) -> Result<f64, SnapshotError> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(b))
+    }
+
+    // Reads a count and makes sure there are at least that many items of
+    // min_size left, so a corrupt count can't make us allocate the world.
+    fn count(&mut self, min_size: usize) -> Result<usize, SnapshotError> {
+        let n = self.u32()? as usize;
+        if n.saturating_mul(min_size) > self.0.len() { return Err(SnapshotError::Truncated); }
+        Ok(n)
+    }
+
+    fn points(&mut self) -> Result<Vec<[N; 2]>, SnapshotError> {
+        let n = self.count(16)?;
+        (0..n).map(|_| Ok([self.f64()?, self.f64()?])).collect()
+    }
+}
+
+fn group_tag(g: CGroup) -> u8 {
+    match g {
+        CGroup::Static => 0,
+        CGroup::Unit => 1,
+        CGroup::Projectile => 2,
+        CGroup::Dynamic => 3,
+        CGroup::Trigger => 4,
+    }
+}
+
+fn group_from_tag(tag: u8) -> Result<CGroup, SnapshotError> {
+    match tag {
+        0 => Ok(CGroup::Static),
+        1 => Ok(CGroup::Unit),
+        2 => Ok(CGroup::Projectile),
+        3 => Ok(CGroup::Dynamic),
+        4 => Ok(CGroup::Trigger),
+        _ => Err(SnapshotError::BadTag("group", tag)),
+    }
+}
+
+// The smallest possible encoded object, for sanity checking counts.
+const MIN_OBJECT_SIZE: usize = 4 + 4 + 1 + 1 + 8 + 8 * 4 + 4 * 3 + 1 + 8 * 2;
+// How far a rotation's length can be from 1 before it's rejected. Anything
+// nalgebra made is much closer than this.
+const ROTATION_TOLERANCE: N = 1e-6;
+
+impl SnapshotData {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::new());
+        w.0.extend_from_slice(SNAPSHOT_MAGIC);
+        w.u16(SNAPSHOT_VERSION);
+        w.u8(if self.deterministic { 1 } else { 0 });
+        w.f64(self.knockback_decay);
+        w.u32(self.slots);
+
+        w.u32(self.free_handles.len() as u32);
+        for h in self.free_handles.iter() { w.u32(*h); }
+
+        w.u32(self.objects.len() as u32);
+        for o in self.objects.iter() {
+            w.u32(o.handle);
+            w.u32(o.id);
+            w.u8(group_tag(o.e_type));
+            match &o.shape {
+                ShapeRecord::Ball(r) => { w.u8(0); w.f64(*r); },
+                ShapeRecord::Cuboid(x, y) => { w.u8(1); w.f64(*x); w.f64(*y); },
+                ShapeRecord::ConvexPolygon(ps) => { w.u8(2); w.points(ps); },
+                ShapeRecord::Polyline(ps, edges) => {
+                    w.u8(3);
+                    w.points(ps);
+                    w.u32(edges.len() as u32);
+                    for e in edges.iter() { w.u32(e[0]); w.u32(e[1]); }
+                },
+            }
+            for v in o.pos.iter() { w.f64(*v); }
+            for g in o.groups.iter() { w.u32(*g); }
+            match o.query {
+                QueryRecord::Contacts(l, a) => { w.u8(0); w.f64(l); w.f64(a); },
+                QueryRecord::Proximity(m) => { w.u8(1); w.f64(m); w.f64(0.0); },
+            }
+            if o.e_type == CGroup::Dynamic {
+                let b = o.body.expect("dynamic object without a body");
+                for v in [b.vel.x, b.vel.y, b.ang_vel, b.inv_mass, b.inv_inertia, b.restitution, b.friction, b.damping].iter() {
+                    w.f64(*v);
+                }
+            }
+            if o.e_type == CGroup::Projectile {
+                match o.ricochet {
+                    Some(r) => {
+                        w.u8(1);
+                        w.f64(r.vel.x); w.f64(r.vel.y); w.f64(r.restitution);
+                        w.u32(r.max_bounces); w.u32(r.bounces);
+                    },
+                    None => w.u8(0),
+                }
+            }
+            if o.e_type == CGroup::Unit {
+                w.f64(o.knockback.x);
+                w.f64(o.knockback.y);
+            }
+            match &o.vision {
+                Some(v) => {
+                    w.u8(1);
+                    w.f64(v.half_angle); w.f64(v.range);
+                    w.u32(v.seen.len() as u32);
+                    for h in v.seen.iter() { w.u32(*h as u32); }
+                },
+                None => w.u8(0),
+            }
+            if o.e_type == CGroup::Static {
+                match o.one_way {
+                    Some(n) => {
+                        w.u8(1);
+                        w.f64(n.x); w.f64(n.y);
+                    },
+                    None => w.u8(0),
+                }
+            }
+            w.u32(o.material.id);
+            w.f64(o.material.friction); w.f64(o.material.slip);
+            w.u32(o.material.sound);
+        }
+
+        w.0
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Result<SnapshotData, SnapshotError> {
+        let mut r = Reader(bytes);
+        if r.take(4).map_err(|_| SnapshotError::BadMagic)? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let mut data = SnapshotData {
+            deterministic: r.u8()? & 1 != 0,
+            knockback_decay: r.f64()?,
+            slots: r.u32()?,
+            ..SnapshotData::default()
+        };
+
+        let n = r.count(4)?;
+        for _ in 0..n { data.free_handles.push(r.u32()?); }
+
+        let n = r.count(MIN_OBJECT_SIZE)?;
+        for _ in 0..n {
+            let handle = r.u32()?;
+            let id = r.u32()?;
+            let e_type = group_from_tag(r.u8()?)?;
+            let shape = match r.u8()? {
+                0 => ShapeRecord::Ball(r.f64()?),
+                1 => ShapeRecord::Cuboid(r.f64()?, r.f64()?),
+                2 => ShapeRecord::ConvexPolygon(r.points()?),
+                3 => {
+                    let ps = r.points()?;
+                    let n = r.count(8)?;
+                    let edges = (0..n).map(|_| Ok([r.u32()?, r.u32()?])).collect::<Result<_, SnapshotError>>()?;
+                    ShapeRecord::Polyline(ps, edges)
+                },
+                tag => return Err(SnapshotError::BadTag("shape", tag)),
+            };
+            let pos = [r.f64()?, r.f64()?, r.f64()?, r.f64()?];
+            // Restore trusts the rotation to be a unit complex.
+            let [_, _, re, im] = pos;
+            if !pos.iter().all(|v| v.is_finite()) || ((re * re + im * im).sqrt() - 1.0).abs() > ROTATION_TOLERANCE {
+                return Err(SnapshotError::BadPosition);
+            }
+            let groups = [r.u32()?, r.u32()?, r.u32()?];
+            let query = match r.u8()? {
+                0 => QueryRecord::Contacts(r.f64()?, r.f64()?),
+                1 => { let m = r.f64()?; r.f64()?; QueryRecord::Proximity(m) },
+                tag => return Err(SnapshotError::BadTag("query type", tag)),
+            };
+            let body = if e_type == CGroup::Dynamic {
+                Some(Body {
+                    vel: Vector::new(r.f64()?, r.f64()?),
+                    ang_vel: r.f64()?,
+                    inv_mass: r.f64()?,
+                    inv_inertia: r.f64()?,
+                    restitution: r.f64()?,
+                    friction: r.f64()?,
+                    damping: r.f64()?,
+                })
+            } else { None };
+            let ricochet = if e_type == CGroup::Projectile {
+                match r.u8()? {
+                    0 => None,
+                    1 => Some(Ricochet {
+                        vel: Vector::new(r.f64()?, r.f64()?),
+                        restitution: r.f64()?,
+                        max_bounces: r.u32()?,
+                        bounces: r.u32()?,
+                    }),
+                    tag => return Err(SnapshotError::BadTag("ricochet", tag)),
+                }
+            } else { None };
+            let knockback = if e_type == CGroup::Unit {
+                Vector::new(r.f64()?, r.f64()?)
+            } else { Vector::zeros() };
+            let vision = match r.u8()? {
+                0 => None,
+                1 => {
+                    let (half_angle, range) = (r.f64()?, r.f64()?);
+                    let n = r.count(4)?;
+                    let seen = (0..n).map(|_| Ok(r.u32()? as usize)).collect::<Result<_, SnapshotError>>()?;
+                    Some(VisionCone { half_angle, range, seen })
+                },
+                tag => return Err(SnapshotError::BadTag("vision", tag)),
+            };
+            let one_way = if e_type == CGroup::Static {
+                match r.u8()? {
+                    0 => None,
+                    1 => Some(Vector::new(r.f64()?, r.f64()?)),
+                    tag => return Err(SnapshotError::BadTag("one way", tag)),
+                }
+            } else { None };
+            let material = Material { id: r.u32()?, friction: r.f64()?, slip: r.f64()?, sound: r.u32()? };
+            data.objects.push(ObjectRecord { handle, id, e_type, shape, pos, groups, query, body, ricochet, knockback, vision, one_way, material });
+        }
+
+        if !r.0.is_empty() { return Err(SnapshotError::TrailingBytes); }
+
+        // Every slot must be exactly one of an object or a free handle.
+        if data.slots as usize != data.objects.len() + data.free_handles.len() {
+            return Err(SnapshotError::BadHandles);
+        }
+        let mut seen = vec![false; data.slots as usize];
+        let handles = data.objects.iter().map(|o| o.handle).chain(data.free_handles.iter().cloned());
+        for h in handles {
+            match seen.get_mut(h as usize) {
+                Some(s) if !*s => *s = true,
+                _ => return Err(SnapshotError::BadHandles),
+            }
+        }
+        // And vision cones can only see objects which exist. They look
+        // themselves up with binary_search, so they have to be in order too.
+        let is_object = |h: usize| data.objects.iter().any(|o| o.handle as usize == h);
+        let bad_seen = |seen: &[usize]| !seen.iter().all(|h| is_object(*h)) || seen.windows(2).any(|w| w[0] >= w[1]);
+        if data.objects.iter().filter_map(|o| o.vision.as_ref()).any(|v| bad_seen(&v.seen)) {
+            return Err(SnapshotError::BadHandles);
+        }
+        Ok(data)
+    }
+}
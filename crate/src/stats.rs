@@ -0,0 +1,61 @@
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+// Counters describing how much work the collision engine did. These are
+// accumulated over a frame (from the first try_move through to update) and
+// then reset.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub static_objects: u32,
+    pub unit_objects: u32,
+    pub projectile_objects: u32,
+    pub dynamic_objects: u32,
+    pub trigger_objects: u32,
+
+    // Potential pairs found by the broad phase, and how many of those
+    // actually have contact points.
+    pub broad_phase_pairs: u32,
+    pub contact_manifolds: u32,
+
+    pub toi_queries: u32,
+    // Contact queries made checking whether turns hit anything, and turns
+    // which got cut short.
+    pub turn_queries: u32,
+    pub blocked_turns: u32,
+    pub try_move_calls: u32,
+    pub try_move_iterations: u32,
+    pub max_try_move_iterations: u32,
+    // How many times try_move gave up after hitting the iteration limit.
+    pub stuck: u32,
+
+    // Navigation mesh tiles rebuilt because statics changed.
+    pub navmesh_tiles_rebuilt: u32,
+    // Times build_flow_field had to rasterize the statics, and cells its
+    // searches visited.
+    pub flow_field_rebuilds: u32,
+    pub flow_field_cells_visited: u32,
+    // Neighbours (units, bodies and statics) avoid steered around.
+    pub avoidance_neighbours: u32,
+    // Rays cast for line of sight and visibility polygons.
+    pub visibility_rays: u32,
+    // Units carried or pushed along by move_static.
+    pub carried_units: u32,
+
+    pub update_ms: f64,
+}
+
+// std::time isn't available on wasm32-unknown-unknown (it panics), so there
+// the wasm bindings plug in performance.now() instead.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn default_clock() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64() * 1000.0)
+        .unwrap_or(0.0)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn default_clock() -> f64 {
+    0.0
+}
@@ -0,0 +1,479 @@
+// Importer for maps made in the Tiled editor (https://www.mapeditor.org/),
+// saved in its JSON format. One tile width is one world unit, and like us Tiled
+// has y pointing down.
+//
+// Colliders come from two places:
+//
+// - Tile layers. Every tile in a layer with a bool property "collision" set to
+//   true is solid, as is any tile whose tileset gives it a bool property
+//   "collides" set to true. Solid tiles get merged into as few boxes as we can
+//   manage. Only embedded tilesets are read, so tiles from external ones can
+//   only go in "collision" layers. Layers need to be saved as CSV (not
+//   base64). Infinite maps aren't supported, and neither are tiles with their
+//   own collision shapes (made in Tiled's collision editor).
+// - Object layers. Rectangles become boxes, ellipses become circles (or a
+//   polygon approximation if they're squashed), polygons become convex
+//   polygons and polylines become polylines. Concave (or self-intersecting)
+//   polygons aren't supported - split them up into convex ones. Points, text
+//   and tile objects don't get colliders.
+//
+// Maps which use anything unsupported are rejected rather than imported with
+// colliders missing.
+//
+// All colliders are static, unless an object has a string property "group"
+// ("static", "unit", "projectile", "dynamic" or "trigger"). Objects with a bool
+// property "collide" set to false don't get a collider at all - use this for
+// spawn points and markers. Every object (with or without a collider) is reported
+// back along with its properties so the game can find them.
+use std::collections::BTreeMap;
+use std::f64;
+use std::fmt;
+use ncollide2d::math::{Isometry, Point, Vector};
+use ncollide2d::shape::ShapeHandle;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::N;
+use crate::level::LoadedObject;
+use crate::world::{CGroup, make_box, make_circle, make_convex_polygon, make_polyline};
+
+// Tiled keeps flip and rotation flags in the top bits of each tile id.
+const GID_MASK: u32 = 0x0fff_ffff;
+const ELLIPSE_SEGMENTS: usize = 16;
+
+#[derive(Debug, Clone, Deserialize)]
+struct Property {
+    name: String,
+    value: Value,
+}
+
+fn props_to_map(props: &[Property]) -> BTreeMap<String, Value> {
+    props.iter().map(|p| (p.name.clone(), p.value.clone())).collect()
+}
+
+fn bool_prop(props: &[Property], name: &str) -> Option<bool> {
+    props.iter().find(|p| p.name == name).and_then(|p| p.value.as_bool())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TilePoint {
+    x: N,
+    y: N,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum TileData {
+    Csv(Vec<u32>),
+    Encoded(serde::de::IgnoredAny),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TiledObjectDef {
+    #[serde(default)]
+    name: String,
+    // Tiled 1.9 renamed type to class.
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    x: N,
+    y: N,
+    #[serde(default)]
+    width: N,
+    #[serde(default)]
+    height: N,
+    #[serde(default)]
+    rotation: N,
+    #[serde(default)]
+    ellipse: bool,
+    #[serde(default)]
+    point: bool,
+    polygon: Option<Vec<TilePoint>>,
+    polyline: Option<Vec<TilePoint>>,
+    gid: Option<u32>,
+    text: Option<Value>,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Layer {
+    Tilelayer {
+        #[serde(default)]
+        name: String,
+        width: usize,
+        height: usize,
+        data: Option<TileData>,
+        encoding: Option<String>,
+        // Only infinite maps have these.
+        chunks: Option<serde::de::IgnoredAny>,
+        #[serde(default)]
+        offsetx: N,
+        #[serde(default)]
+        offsety: N,
+        #[serde(default)]
+        properties: Vec<Property>,
+    },
+    Objectgroup {
+        #[serde(default)]
+        name: String,
+        objects: Vec<TiledObjectDef>,
+        #[serde(default)]
+        offsetx: N,
+        #[serde(default)]
+        offsety: N,
+    },
+    Group {
+        layers: Vec<Layer>,
+        #[serde(default)]
+        offsetx: N,
+        #[serde(default)]
+        offsety: N,
+    },
+    // Image layers and anything newer.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TileShapes {
+    #[serde(default)]
+    objects: Vec<serde::de::IgnoredAny>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TileDef {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<Property>,
+    // The tile's collision shapes.
+    objectgroup: Option<TileShapes>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Tileset {
+    firstgid: u32,
+    // Set for tilesets saved in their own file.
+    source: Option<String>,
+    #[serde(default)]
+    tiles: Vec<TileDef>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Map {
+    tilewidth: N,
+    tileheight: N,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<Layer>,
+    #[serde(default)]
+    tilesets: Vec<Tileset>,
+}
+
+// An object from one of the map's object layers. Positions are in world
+// units. x and y are where Tiled puts the object's origin - the top left of
+// rectangles and ellipses, and the first point of polygons.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TiledObject {
+    pub name: String,
+    // The object's type (or class, in newer versions of Tiled).
+    pub kind: String,
+    pub layer: String,
+    pub x: N,
+    pub y: N,
+    pub width: N,
+    pub height: N,
+    pub angle: N,
+    // None if the object didn't get a collider.
+    pub collider: Option<LoadedObject>,
+    pub properties: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TiledMap {
+    // The merged boxes from the tile layers. Tagged with the layer name.
+    pub tiles: Vec<LoadedObject>,
+    pub objects: Vec<TiledObject>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TiledError {
+    Parse(String),
+    // The map uses a feature we don't read. See the top of this file.
+    Unsupported(&'static str),
+    // Something is wrong with the object with this name.
+    Invalid(String, &'static str),
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TiledError::Parse(e) => write!(f, "could not parse tiled map: {}", e),
+            TiledError::Unsupported(what) => write!(f, "unsupported tiled map: {}", what),
+            TiledError::Invalid(name, reason) => write!(f, "tiled object {:?}: {}", name, reason),
+        }
+    }
+}
+
+impl std::error::Error for TiledError {}
+
+// A collider we're going to add, once we know the whole map is ok.
+pub(crate) struct PlannedCollider {
+    pub pos: Isometry<N>,
+    pub shape: ShapeHandle<N>,
+    pub group: CGroup,
+    pub tags: Vec<String>,
+}
+
+pub(crate) struct PlannedObject {
+    pub object: TiledObject,
+    // Index into the colliders list.
+    pub collider: Option<usize>,
+}
+
+pub(crate) struct Plan {
+    pub colliders: Vec<PlannedCollider>,
+    pub tile_colliders: usize,
+    pub objects: Vec<PlannedObject>,
+}
+
+struct Importer<'a> {
+    map: &'a Map,
+    // World units per pixel.
+    scale: N,
+    tiles: Vec<PlannedCollider>,
+    colliders: Vec<PlannedCollider>,
+    objects: Vec<PlannedObject>,
+}
+
+impl<'a> Importer<'a> {
+    fn tile_collides(&self, gid: u32) -> Result<bool, TiledError> {
+        let tileset = match self.map.tilesets.iter().filter(|ts| ts.firstgid <= gid).max_by_key(|ts| ts.firstgid) {
+            Some(ts) => ts,
+            None => return Ok(false),
+        };
+        if tileset.source.is_some() {
+            return Err(TiledError::Unsupported("external tilesets"));
+        }
+        let tile = match tileset.tiles.iter().find(|t| t.id == gid - tileset.firstgid) {
+            Some(t) => t,
+            None => return Ok(false),
+        };
+        if tile.objectgroup.as_ref().is_some_and(|g| !g.objects.is_empty()) {
+            return Err(TiledError::Unsupported("tiles with their own collision shapes"));
+        }
+        Ok(bool_prop(&tile.properties, "collides").unwrap_or(false))
+    }
+
+    fn layer(&mut self, layer: &Layer, offset: Vector<N>) -> Result<(), TiledError> {
+        match layer {
+            Layer::Tilelayer { name, width, height, data, encoding, chunks, offsetx, offsety, properties } => {
+                if chunks.is_some() {
+                    return Err(TiledError::Unsupported("infinite maps"));
+                }
+                if encoding.as_ref().is_some_and(|e| e != "csv") {
+                    return Err(TiledError::Unsupported("tile layers must be saved as CSV"));
+                }
+                let data = match data {
+                    Some(TileData::Csv(data)) => data,
+                    Some(TileData::Encoded(_)) => return Err(TiledError::Unsupported("tile layers must be saved as CSV")),
+                    None => return Err(TiledError::Unsupported("tile layer has no data")),
+                };
+                if data.len() != width * height {
+                    return Err(TiledError::Parse(format!("layer {:?} has the wrong amount of tile data", name)));
+                }
+                // Tiles in a solid layer are solid whatever their tileset
+                // says.
+                let all_solid = bool_prop(properties, "collision").unwrap_or(false);
+                let solid = data.iter().map(|gid| {
+                    let gid = gid & GID_MASK;
+                    Ok(gid != 0 && (all_solid || self.tile_collides(gid)?))
+                }).collect::<Result<Vec<_>, _>>()?;
+
+                let offset = offset + Vector::new(*offsetx, *offsety) * self.scale;
+                self.tile_layer(name, &solid, *width, *height, offset);
+            },
+            Layer::Objectgroup { name, objects, offsetx, offsety } => {
+                let offset = offset + Vector::new(*offsetx, *offsety) * self.scale;
+                for obj in objects.iter() {
+                    self.object(name, obj, offset)?;
+                }
+            },
+            Layer::Group { layers, offsetx, offsety } => {
+                let offset = offset + Vector::new(*offsetx, *offsety) * self.scale;
+                for layer in layers.iter() {
+                    self.layer(layer, offset)?;
+                }
+            },
+            Layer::Other => {},
+        }
+        Ok(())
+    }
+
+    // Greedily merge solid tiles into rectangles. Runs along a row first, then
+    // grow each run down for as long as the rows below match.
+    fn tile_layer(&mut self, name: &str, solid: &[bool], width: usize, height: usize, offset: Vector<N>) {
+        let tw = self.map.tilewidth * self.scale;
+        let th = self.map.tileheight * self.scale;
+        let mut used = vec![false; solid.len()];
+        let free = |used: &[bool], x: usize, y: usize| solid[y * width + x] && !used[y * width + x];
+
+        for y in 0..height {
+            let mut x = 0;
+            while x < width {
+                if !free(&used, x, y) { x += 1; continue; }
+
+                let mut w = 1;
+                while x + w < width && free(&used, x + w, y) { w += 1; }
+                let mut h = 1;
+                while y + h < height && (x..x + w).all(|xx| free(&used, xx, y + h)) { h += 1; }
+
+                for yy in y..y + h {
+                    for xx in x..x + w { used[yy * width + xx] = true; }
+                }
+
+                let (wf, hf) = (w as N * tw, h as N * th);
+                let center = offset + Vector::new(x as N * tw + wf / 2.0, y as N * th + hf / 2.0);
+                self.tiles.push(PlannedCollider {
+                    pos: Isometry::new(center, 0.0),
+                    shape: make_box(wf, hf),
+                    group: CGroup::Static,
+                    tags: vec![name.to_string()],
+                });
+                x += w;
+            }
+        }
+    }
+
+    fn object(&mut self, layer: &str, obj: &TiledObjectDef, offset: Vector<N>) -> Result<(), TiledError> {
+        let invalid = |reason| TiledError::Invalid(obj.name.clone(), reason);
+        let s = self.scale;
+        let origin = offset + Vector::new(obj.x, obj.y) * s;
+        let angle = obj.rotation.to_radians();
+        let (w, h) = (obj.width * s, obj.height * s);
+        if !(origin.x.is_finite() && origin.y.is_finite() && angle.is_finite() && w.is_finite() && h.is_finite()) {
+            return Err(invalid("position must be finite"));
+        }
+
+        let group = match obj.properties.iter().find(|p| p.name == "group") {
+            Some(p) => serde_json::from_value::<CGroup>(p.value.clone()).map_err(|_| invalid("unknown group"))?,
+            None => CGroup::Static,
+        };
+        let collide = bool_prop(&obj.properties, "collide").unwrap_or(true);
+
+        // Rectangles and ellipses are positioned by their top left corner,
+        // and rotate around it.
+        let rot = Isometry::new(Vector::zeros(), angle);
+        let center = origin + rot * Vector::new(w / 2.0, h / 2.0);
+        let points = |ps: &[TilePoint]| ps.iter().map(|p| Point::new(p.x * s, p.y * s)).collect::<Vec<_>>();
+
+        let shape = if !collide || obj.point || obj.gid.is_some() || obj.text.is_some() {
+            None
+        } else if let Some(ps) = &obj.polygon {
+            let ps = points(ps);
+            if ps.len() < 3 { return Err(invalid("polygon needs at least 3 points")); }
+            if !is_convex(&ps) { return Err(invalid("polygon is concave")); }
+            let shape = make_convex_polygon(&ps).ok_or_else(|| invalid("polygon is too small"))?;
+            Some((Isometry::new(origin, angle), shape))
+        } else if let Some(ps) = &obj.polyline {
+            if ps.len() < 2 { return Err(invalid("polyline needs at least 2 points")); }
+            Some((Isometry::new(origin, angle), make_polyline(points(ps), false)))
+        } else if w <= 0.0 || h <= 0.0 {
+            // Tiled makes these if you click without dragging. Treat them
+            // like points.
+            None
+        } else if obj.ellipse {
+            let shape = if (w - h).abs() < 1e-9 {
+                make_circle(w / 2.0)
+            } else {
+                let ps = (0..ELLIPSE_SEGMENTS).map(|i| {
+                    let a = (i as N) * f64::consts::PI * 2.0 / (ELLIPSE_SEGMENTS as N);
+                    Point::new(w / 2.0 * a.cos(), h / 2.0 * a.sin())
+                }).collect::<Vec<_>>();
+                make_convex_polygon(&ps).ok_or_else(|| invalid("ellipse is too small"))?
+            };
+            Some((Isometry::new(center, angle), shape))
+        } else {
+            Some((Isometry::new(center, angle), make_box(w, h)))
+        };
+
+        let kind = if obj.class.is_empty() { obj.kind.clone() } else { obj.class.clone() };
+        let collider = shape.map(|(pos, shape)| {
+            let mut tags = vec![layer.to_string()];
+            if !kind.is_empty() { tags.push(kind.clone()); }
+            self.colliders.push(PlannedCollider { pos, shape, group, tags });
+            self.colliders.len() - 1
+        });
+
+        self.objects.push(PlannedObject {
+            object: TiledObject {
+                name: obj.name.clone(),
+                kind,
+                layer: layer.to_string(),
+                x: origin.x, y: origin.y,
+                width: w, height: h,
+                angle,
+                collider: None,
+                properties: props_to_map(&obj.properties),
+            },
+            collider,
+        });
+        Ok(())
+    }
+}
+
+// Every corner has to turn the same way, and all the way round only once -
+// a star turns the same way at every point but goes round twice.
+fn is_convex(ps: &[Point<N>]) -> bool {
+    let n = ps.len();
+    let mut sign = 0.0;
+    let mut turning = 0.0;
+    for i in 0..n {
+        let (a, b, c) = (ps[i], ps[(i + 1) % n], ps[(i + 2) % n]);
+        let (ab, bc) = (b - a, c - b);
+        let cross = ab.perp(&bc);
+        turning += cross.atan2(ab.dot(&bc));
+        if cross.abs() < 1e-12 { continue; }
+        if sign == 0.0 {
+            sign = cross.signum();
+        } else if cross.signum() != sign {
+            return false;
+        }
+    }
+    (turning.abs() - 2.0 * f64::consts::PI).abs() < 1e-6
+}
+
+// Works out everything the map will add to the world without touching it, so
+// a bad map doesn't leave half its colliders behind.
+pub(crate) fn plan_tiled(json: &str) -> Result<Plan, TiledError> {
+    let map: Map = serde_json::from_str(json).map_err(|e| TiledError::Parse(e.to_string()))?;
+    if map.infinite {
+        return Err(TiledError::Unsupported("infinite maps"));
+    }
+    if !(map.tilewidth > 0.0 && map.tileheight > 0.0) {
+        return Err(TiledError::Parse("tile size must be positive".to_string()));
+    }
+
+    let mut importer = Importer {
+        map: &map,
+        scale: 1.0 / map.tilewidth,
+        tiles: Vec::new(),
+        colliders: Vec::new(),
+        objects: Vec::new(),
+    };
+    for layer in map.layers.iter() {
+        importer.layer(layer, Vector::zeros())?;
+    }
+
+    // Tiles first, so object collider indexes need shifting along.
+    let tile_colliders = importer.tiles.len();
+    let mut colliders = importer.tiles;
+    colliders.append(&mut importer.colliders);
+    for obj in importer.objects.iter_mut() {
+        obj.collider = obj.collider.map(|i| i + tile_colliders);
+    }
+
+    Ok(Plan { colliders, tile_colliders, objects: importer.objects })
+}
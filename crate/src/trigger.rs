@@ -0,0 +1,25 @@
+// Trigger volumes, for doors, checkpoints, damage zones and pickups. Triggers
+// are added like anything else, but never block or push anything. They watch
+// for objects in whichever groups they're set to detect (units, by default),
+// and each update reports the ones which came into or went out of them.
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+// Each trigger event is emitted as 3 floats by the wasm bindings:
+// [kind, trigger id, other id].
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerEventKind {
+    Enter,
+    // Left the trigger. Objects which are removed just disappear, without
+    // one of these.
+    Exit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TriggerEvent {
+    pub kind: TriggerEventKind,
+    pub trigger: u32,
+    // The entity id of whatever went in or out.
+    pub other: u32,
+}
@@ -0,0 +1,129 @@
+// What can be seen from a point, for fog of war and lighting.
+//
+// The visible region is found by casting rays out from the point and joining
+// up where they stop. Between two rays which hit the same straight edge that's
+// exact, so rays go at every corner of every occluder (and a hair either side,
+// to see past it), and wherever an edge crosses the edge of the view. A ring
+// of evenly spaced rays rounds off the rest.
+use std::f64;
+use std::cmp::Ordering;
+use ncollide2d::math::{Isometry, Point, Vector};
+use ncollide2d::shape::{Ball, ConvexPolygon, Cuboid, Polyline, ShapeHandle};
+use ncollide2d::bounding_volume;
+use ncollide2d::query::Ray;
+
+use crate::N;
+
+// Rays around the edge of the view, and across each ball.
+const VISIBILITY_SEGMENTS: usize = 64;
+const VISIBILITY_BALL_SEGMENTS: usize = 8;
+// How far either side of a corner to look, in radians.
+const VISIBILITY_NUDGE: N = 0.0001;
+
+// The edges of a shape, as segments. Unknown shapes are treated like their
+// bounding box.
+fn edges(shape: &ShapeHandle<N>, pos: &Isometry<N>) -> Vec<(Point<N>, Point<N>)> {
+    let loop_of = |points: Vec<Point<N>>| (0..points.len())
+        .map(|i| (points[i], points[(i + 1) % points.len()]))
+        .collect::<Vec<_>>();
+
+    if let Some(cuboid) = shape.as_shape::<Cuboid<N>>() {
+        let he = cuboid.half_extents;
+        loop_of(vec![
+            pos * Point::new(-he.x, -he.y),
+            pos * Point::new(he.x, -he.y),
+            pos * Point::new(he.x, he.y),
+            pos * Point::new(-he.x, he.y),
+        ])
+    } else if let Some(poly) = shape.as_shape::<ConvexPolygon<N>>() {
+        loop_of(poly.points().iter().map(|p| pos * p).collect())
+    } else if let Some(polyline) = shape.as_shape::<Polyline<N>>() {
+        let points = polyline.points();
+        polyline.edges().iter()
+            .map(|edge| (pos * points[edge.indices.x], pos * points[edge.indices.y]))
+            .collect()
+    } else {
+        let aabb = bounding_volume::aabb(shape.as_ref(), pos);
+        let (mins, maxs) = (aabb.mins, aabb.maxs);
+        loop_of(vec![mins, Point::new(maxs.x, mins.y), maxs, Point::new(mins.x, maxs.y)])
+    }
+}
+
+// Where the segment from a to b crosses the circle around the origin, as
+// fractions along it.
+fn circle_crossings(a: Vector<N>, b: Vector<N>, radius: N) -> Vec<N> {
+    let d = b - a;
+    let (qa, qb, qc) = (d.norm_squared(), 2.0 * a.dot(&d), a.norm_squared() - radius * radius);
+    let discriminant = qb * qb - 4.0 * qa * qc;
+    if qa == 0.0 || discriminant < 0.0 { return Vec::new(); }
+    let sqrt_disc = discriminant.sqrt();
+    [(-qb - sqrt_disc) / (2.0 * qa), (-qb + sqrt_disc) / (2.0 * qa)].iter()
+        .cloned()
+        .filter(|t| (0.0..=1.0).contains(t))
+        .collect()
+}
+
+// The angles worth casting a ray at to catch the corners of a shape.
+fn angles(shape: &ShapeHandle<N>, pos: &Isometry<N>, origin: Point<N>, radius: N, out: &mut Vec<N>) {
+    let angle_of = |v: Vector<N>| v.y.atan2(v.x);
+
+    if let Some(ball) = shape.as_shape::<Ball<N>>() {
+        // From one side of it to the other.
+        let to_center = pos.translation.vector - origin.coords;
+        let dist = to_center.norm();
+        if dist <= ball.radius { return; }
+        let (mid, half) = (angle_of(to_center), (ball.radius / dist).asin());
+        for i in 0..=VISIBILITY_BALL_SEGMENTS {
+            out.push(mid - half + 2.0 * half * i as N / VISIBILITY_BALL_SEGMENTS as N);
+        }
+        out.push(mid - half - VISIBILITY_NUDGE);
+        out.push(mid + half + VISIBILITY_NUDGE);
+        return;
+    }
+
+    for (a, b) in edges(shape, pos) {
+        let (a, b) = (a - origin, b - origin);
+        if a.norm() <= radius {
+            let angle = angle_of(a);
+            out.extend_from_slice(&[angle - VISIBILITY_NUDGE, angle, angle + VISIBILITY_NUDGE]);
+        }
+        for t in circle_crossings(a, b, radius) {
+            out.push(angle_of(a + (b - a) * t));
+        }
+    }
+}
+
+// The region visible from origin out to radius, as a polygon going
+// anticlockwise from the negative x axis. Occluders origin is inside of (like
+// the unit doing the looking) are ignored. Also returns how many rays it took.
+pub(crate) fn visibility_polygon(origin: Point<N>, radius: N, occluders: &[(&ShapeHandle<N>, &Isometry<N>)]) -> (Vec<Point<N>>, usize) {
+    let occluders = occluders.iter()
+        .filter(|(shape, pos)| !shape.as_point_query().is_some_and(|q| q.contains_point(pos, &origin)))
+        .collect::<Vec<_>>();
+
+    let mut rays = (0..VISIBILITY_SEGMENTS)
+        .map(|i| -f64::consts::PI + 2.0 * f64::consts::PI * i as N / VISIBILITY_SEGMENTS as N)
+        .collect::<Vec<_>>();
+    for (shape, pos) in occluders.iter() {
+        angles(shape, pos, origin, radius, &mut rays);
+    }
+    // Into [-pi, pi), so they sort in order around the circle.
+    for angle in rays.iter_mut() {
+        *angle -= 2.0 * f64::consts::PI * ((*angle + f64::consts::PI) / (2.0 * f64::consts::PI)).floor();
+    }
+    rays.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    rays.dedup();
+
+    let mut polygon = Vec::<Point<N>>::with_capacity(rays.len());
+    for &angle in rays.iter() {
+        let ray = Ray::new(origin, Vector::new(angle.cos(), angle.sin()));
+        let toi = occluders.iter()
+            .filter_map(|(shape, pos)| shape.as_ray_cast().and_then(|r| r.toi_with_ray(pos, &ray, radius, true)))
+            .fold(radius, N::min);
+        let p = ray.point_at(toi);
+        if polygon.last().is_none_or(|last| (p - last).norm() > 1e-9) {
+            polygon.push(p);
+        }
+    }
+    (polygon, rays.len())
+}
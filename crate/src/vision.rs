@@ -0,0 +1,52 @@
+// Vision cones, so enemies can notice units. A cone sits on an object, points
+// the way it faces (along its local x axis) and turns with it. Each update
+// works out which units are inside it and not hidden behind a static, and
+// reports the ones which came into or went out of view as vision events.
+use ncollide2d::math::{Isometry, Point};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::N;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VisionCone {
+    // Half the cone's angle, in radians.
+    pub half_angle: N,
+    pub range: N,
+    // Handles of the units it could see as of the last update, sorted.
+    pub seen: Vec<usize>,
+}
+
+impl VisionCone {
+    // Whether any part of a circle is inside the cone, given where the cone's
+    // owner is.
+    pub(crate) fn contains(&self, eye: &Isometry<N>, center: Point<N>, radius: N) -> bool {
+        let offset = eye.inverse_transform_point(&center).coords;
+        let dist = offset.norm();
+        if dist <= radius { return true; }
+        if dist - radius > self.range { return false; }
+        // The angle from the middle of the cone, widened by how much of the
+        // view the circle takes up.
+        let angle = offset.y.atan2(offset.x).abs();
+        angle <= self.half_angle + (radius / dist).asin()
+    }
+}
+
+// Each vision event is emitted as 3 floats by the wasm bindings:
+// [kind, watcher id, unit id].
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisionEventKind {
+    // The unit came into view.
+    Spotted,
+    // The unit went out of view, behind a static or out of the cone.
+    Lost,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VisionEvent {
+    pub kind: VisionEventKind,
+    // The entity id of the object the cone is on.
+    pub watcher: u32,
+    pub unit: u32,
+}
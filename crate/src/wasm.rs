@@ -0,0 +1,325 @@
+// This is a thin layer exposing the collision world to javascript. Everything
+// gets flattened into typed arrays on the way out.
+use wasm_bindgen::prelude::*;
+use ncollide2d::shape::ShapeHandle;
+use ncollide2d::math::Vector;
+
+use crate::N;
+use crate::log::LogLevel;
+use crate::stats::Stats;
+use crate::world::{self, CGroup};
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = performance)]
+    fn now() -> f64;
+}
+
+// #[wasm_bindgen(start)]
+#[wasm_bindgen]
+pub fn init() {
+    // Adds 30k to bundle size
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
+}
+
+#[wasm_bindgen]
+pub struct LocalShapeHandle(ShapeHandle<N>);
+
+#[wasm_bindgen]
+pub fn make_circle(r: f64) -> LocalShapeHandle {
+    LocalShapeHandle(world::make_circle(r))
+}
+
+#[wasm_bindgen]
+pub fn make_box(w: f64, h: f64) -> LocalShapeHandle {
+    LocalShapeHandle(world::make_box(w, h))
+}
+
+#[wasm_bindgen]
+pub struct World(world::World);
+
+#[wasm_bindgen]
+impl World {
+    pub fn new() -> World {
+        let mut w = world::World::new();
+        w.set_clock(now);
+        World(w)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn add(&mut self, id: u32, x: f64, y: f64, a: f64, shape: LocalShapeHandle, cgroup: CGroup, linear_speed: f64) -> usize {
+        self.0.add(id, x, y, a, shape.0, cgroup, linear_speed)
+    }
+
+    // Returns [handle, id] pairs, one for each object in the level in order.
+    // Tags aren't returned - the level is JSON, so read them from that.
+    pub fn load_level(&mut self, json: &str, first_id: u32) -> Result<Box<[u32]>, JsValue> {
+        let objects = self.0.load_level(json, first_id).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let mut result = Vec::<u32>::new();
+        for obj in objects {
+            result.push(obj.handle as u32);
+            result.push(obj.id);
+        }
+        Ok(result.into_boxed_slice())
+    }
+
+    // Returns the imported TiledMap as JSON, since it's full of strings and
+    // property maps.
+    pub fn load_tiled(&mut self, json: &str, first_id: u32) -> Result<String, JsValue> {
+        let map = self.0.load_tiled(json, first_id).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(serde_json::to_string(&map).unwrap())
+    }
+
+    pub fn remove(&mut self, handle: usize) {
+        self.0.remove(handle)
+    }
+
+    pub fn set_position(&mut self, handle: usize, x: f64, y: f64, a: f64) {
+        self.0.set_position(handle, x, y, a)
+    }
+
+    // Returns [x, y, angle], then the ids of the materials it touched.
+    pub fn try_move(&mut self, handle: usize, vx: f64, vy: f64, va: f64) -> Box<[f64]> {
+        let (pos, materials) = self.0.try_move_with_materials(handle, vx, vy, va);
+        let mut result = vec![pos.translation.x, pos.translation.y, pos.rotation.angle()];
+        result.extend(materials.iter().map(|m| *m as f64));
+        result.into_boxed_slice()
+    }
+
+    pub fn set_material(&mut self, handle: usize, id: u32, friction: f64, slip: f64, sound: u32) {
+        self.0.set_material(handle, id, friction, slip, sound)
+    }
+
+    // Returns [x, y, angle], or an empty array if there's no such object.
+    pub fn move_static(&mut self, handle: usize, dx: f64, dy: f64, da: f64) -> Box<[f64]> {
+        match self.0.move_static(handle, dx, dy, da) {
+            Some(pos) => vec![pos.translation.x, pos.translation.y, pos.rotation.angle()].into_boxed_slice(),
+            None => Box::new([]),
+        }
+    }
+
+    // Returns [id, x, y, angle] for each object which got pushed out of a wall
+    // or moved by the physics.
+    pub fn update(&mut self) -> Box<[f64]> {
+        let mut result = Vec::<f64>::new();
+        for (id, pos) in self.0.update() {
+            result.push(id as f64);
+            result.push(pos.translation.x);
+            result.push(pos.translation.y);
+            result.push(pos.rotation.angle());
+        }
+        result.into_boxed_slice()
+    }
+
+    pub fn apply_impulse(&mut self, handle: usize, ix: f64, iy: f64) {
+        self.0.apply_impulse(handle, ix, iy)
+    }
+
+    pub fn set_knockback_decay(&mut self, decay: f64) {
+        self.0.set_knockback_decay(decay)
+    }
+
+    pub fn set_body(&mut self, handle: usize, mass: f64, restitution: f64, friction: f64, damping: f64) {
+        self.0.set_body(handle, mass, restitution, friction, damping)
+    }
+
+    pub fn apply_impulse_at(&mut self, handle: usize, ix: f64, iy: f64, px: f64, py: f64) {
+        self.0.apply_impulse_at(handle, ix, iy, px, py)
+    }
+
+    // Returns [vx, vy, va], or an empty array if the object isn't a dynamic
+    // body.
+    pub fn body_velocity(&self, handle: usize) -> Box<[f64]> {
+        match self.0.body_velocity(handle) {
+            Some((v, va)) => vec![v.x, v.y, va].into_boxed_slice(),
+            None => Box::new([]),
+        }
+    }
+
+    // Returns [id1, id2] pairs.
+    pub fn proximity_events(&self) -> Box<[u32]> {
+        let mut result = Vec::<u32>::new();
+        for (id1, id2) in self.0.proximity_events() {
+            result.push(id1);
+            result.push(id2);
+        }
+        result.into_boxed_slice()
+    }
+
+    pub fn launch(&mut self, handle: usize, vx: f64, vy: f64, restitution: f64, max_bounces: u32) {
+        self.0.launch(handle, vx, vy, restitution, max_bounces)
+    }
+
+    pub fn enable_navmesh(&mut self, agent_radius: f64) {
+        self.0.enable_navmesh(agent_radius)
+    }
+
+    // Returns [x1, y1, x2, y2, x3, y3] for each triangle in the navigation
+    // mesh, or an empty array if there isn't one for that radius.
+    pub fn navmesh_triangles(&mut self, agent_radius: f64) -> Box<[f64]> {
+        let mut result = Vec::<f64>::new();
+        if let Some(navmesh) = self.0.navmesh(agent_radius) {
+            for [a, b, c] in navmesh.triangles() {
+                result.extend_from_slice(&[a.x, a.y, b.x, b.y, c.x, c.y]);
+            }
+        }
+        result.into_boxed_slice()
+    }
+
+    // Returns [x, y] pairs, from the start to the goal. Empty if there's no
+    // way there.
+    pub fn find_path(&mut self, start_x: f64, start_y: f64, goal_x: f64, goal_y: f64, agent_radius: f64) -> Box<[f64]> {
+        let mut result = Vec::<f64>::new();
+        for p in self.0.find_path(start_x, start_y, goal_x, goal_y, agent_radius).unwrap_or_default() {
+            result.push(p.x);
+            result.push(p.y);
+        }
+        result.into_boxed_slice()
+    }
+
+    pub fn build_flow_field(&mut self, target_x: f64, target_y: f64, cell_size: f64) {
+        self.0.build_flow_field(target_x, target_y, cell_size)
+    }
+
+    // Takes [x, y] pairs, and returns a [dx, dy] direction for each. Batched
+    // so a swarm only crosses into wasm once.
+    pub fn flow_directions(&self, positions: &[f64]) -> Box<[f64]> {
+        let mut result = Vec::<f64>::with_capacity(positions.len());
+        for p in positions.chunks_exact(2) {
+            let d = self.0.flow_direction(p[0], p[1]);
+            result.push(d.x);
+            result.push(d.y);
+        }
+        result.into_boxed_slice()
+    }
+
+    // Takes [handle, vx, vy] triples of preferred velocities, and returns a
+    // [vx, vy] velocity for each which steers clear of everything else.
+    pub fn avoid(&mut self, preferred: &[f64]) -> Box<[f64]> {
+        let preferred = preferred.chunks_exact(3)
+            .map(|p| (p[0] as usize, Vector::new(p[1], p[2])))
+            .collect::<Vec<_>>();
+        let mut result = Vec::<f64>::with_capacity(preferred.len() * 2);
+        for v in self.0.avoid(&preferred) {
+            result.push(v.x);
+            result.push(v.y);
+        }
+        result.into_boxed_slice()
+    }
+
+    pub fn line_of_sight(&mut self, handle_a: usize, handle_b: usize) -> bool {
+        self.0.line_of_sight(handle_a, handle_b)
+    }
+
+    // Returns [x, y] pairs going anticlockwise around (x, y). occluder_mask
+    // has bit 1 << group set for each CGroup which blocks the view.
+    pub fn visibility_polygon(&mut self, x: f64, y: f64, radius: f64, occluder_mask: u32) -> Box<[f64]> {
+        let mut result = Vec::<f64>::new();
+        for p in self.0.visibility_polygon(x, y, radius, occluder_mask) {
+            result.push(p.x);
+            result.push(p.y);
+        }
+        result.into_boxed_slice()
+    }
+
+    // Returns [kind, projectile id, other id, x, y, nx, ny] for each event.
+    // See ProjectileEventKind.
+    pub fn projectile_events(&self) -> Box<[f64]> {
+        let mut result = Vec::<f64>::new();
+        for e in self.0.projectile_events() {
+            result.extend_from_slice(&[e.kind as u32 as f64, e.projectile as f64, e.other as f64,
+                e.point.x, e.point.y, e.normal.x, e.normal.y]);
+        }
+        result.into_boxed_slice()
+    }
+
+    pub fn set_vision(&mut self, handle: usize, angle: f64, range: f64) {
+        self.0.set_vision(handle, angle, range)
+    }
+
+    pub fn clear_vision(&mut self, handle: usize) {
+        self.0.clear_vision(handle)
+    }
+
+    // Returns [kind, watcher id, unit id] for each event. See
+    // VisionEventKind.
+    pub fn vision_events(&self) -> Box<[f64]> {
+        let mut result = Vec::<f64>::new();
+        for e in self.0.vision_events() {
+            result.extend_from_slice(&[e.kind as u32 as f64, e.watcher as f64, e.unit as f64]);
+        }
+        result.into_boxed_slice()
+    }
+
+    pub fn set_one_way(&mut self, handle: usize, nx: f64, ny: f64) {
+        self.0.set_one_way(handle, nx, ny)
+    }
+
+    pub fn clear_one_way(&mut self, handle: usize) {
+        self.0.clear_one_way(handle)
+    }
+
+    // mask has bit 1 << group set for each CGroup the trigger should detect.
+    pub fn set_trigger_groups(&mut self, handle: usize, mask: u32) {
+        self.0.set_trigger_groups(handle, mask)
+    }
+
+    // Returns [kind, trigger id, other id] for each event. See
+    // TriggerEventKind.
+    pub fn trigger_events(&self) -> Box<[f64]> {
+        let mut result = Vec::<f64>::new();
+        for e in self.0.trigger_events() {
+            result.extend_from_slice(&[e.kind as u32 as f64, e.trigger as f64, e.other as f64]);
+        }
+        result.into_boxed_slice()
+    }
+
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.0.set_deterministic(deterministic)
+    }
+
+    pub fn state_hash(&self) -> u64 {
+        self.0.state_hash()
+    }
+
+    pub fn snapshot(&self) -> Result<Box<[u8]>, JsValue> {
+        self.0.snapshot().map(|bytes| bytes.into_boxed_slice()).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        self.0.restore(bytes).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.0.stats()
+    }
+
+    pub fn debug_lines(&self) -> Box<[f64]> {
+        self.0.debug_lines().into_boxed_slice()
+    }
+
+    pub fn print_events(&mut self) {
+        self.0.print_events()
+    }
+
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.0.set_log_level(level)
+    }
+
+    // Returns and clears the buffered log messages, one per line. Each line is
+    // prefixed with its level ("warn: " or "info: ").
+    pub fn drain_log(&mut self) -> String {
+        let mut out = String::new();
+        for (level, line) in self.0.drain_log() {
+            out.push_str(if level == LogLevel::Warn { "warn: " } else { "info: " });
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn drain_trace(&mut self) -> Box<[f64]> {
+        self.0.drain_trace().into_boxed_slice()
+    }
+}
@@ -0,0 +1,2007 @@
+use std::f64;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use ncollide2d::pipeline::*;
+// ncollide now calls its handle type this.
+use ncollide2d::pipeline::CollisionObjectSlabHandle as CollisionObjectHandle;
+use ncollide2d::shape::*;
+use ncollide2d::math::*;
+use ncollide2d::query;
+use ncollide2d::bounding_volume::{self, BoundingVolume};
+use std::convert::From;
+use nalgebra::{Complex, Point2, UnitComplex};
+use serde::Deserialize;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::N;
+use crate::avoidance::{self, Neighbour, AVOID_STATIC_TIME_HORIZON, AVOID_TIME_HORIZON};
+use crate::debug::*;
+use crate::dynamics::*;
+use crate::hash::StateHasher;
+use crate::level::*;
+use crate::flowfield::FlowField;
+use crate::navmesh::NavMesh;
+use crate::pathfind;
+use crate::projectile::*;
+use crate::tiled::*;
+use crate::trigger::*;
+use crate::material::Material;
+use crate::visibility;
+use crate::vision::*;
+use crate::snapshot::*;
+use crate::log::*;
+use crate::stats::{Stats, default_clock};
+
+pub fn make_circle(r: f64) -> ShapeHandle<N> {
+    ShapeHandle::new(Ball::new(r))
+}
+
+pub fn make_box(w: f64, h: f64) -> ShapeHandle<N> {
+    ShapeHandle::new(Cuboid::new(Vector::new(w/2.0, h/2.0)))
+}
+
+// Returns None if the points don't make a proper polygon (eg they're all in a
+// line). The points don't need to be in any particular order.
+pub fn make_convex_polygon(points: &[Point<N>]) -> Option<ShapeHandle<N>> {
+    ConvexPolygon::try_from_points(points).map(ShapeHandle::new)
+}
+
+// A chain of line segments, joining the last point back to the first if
+// closed is set. This is hollow - it's only useful for static walls.
+pub fn make_polyline(points: Vec<Point<N>>, closed: bool) -> ShapeHandle<N> {
+    let n = points.len();
+    let mut edges = (1..n).map(|i| Point2::new(i - 1, i)).collect::<Vec<_>>();
+    if closed && n > 2 { edges.push(Point2::new(n - 1, 0)); }
+    ShapeHandle::new(Polyline::new(points, Some(edges)))
+}
+
+pub(crate) fn v_perp(v: Vector<N>) -> Vector<N> {
+    Vector::new(v.y, -v.x)
+}
+
+pub(crate) fn v_d2(v: Vector<N>) -> N {
+    v.x*v.x + v.y*v.y
+}
+
+// When two shapes moving at the given velocities first touch, in frames.
+// None if they never do.
+fn toi(m1: &Isometry<N>, v1: &Vector<N>, g1: &dyn Shape<N>, m2: &Isometry<N>, v2: &Vector<N>, g2: &dyn Shape<N>) -> Option<N> {
+    query::time_of_impact(&query::DefaultTOIDispatcher, m1, v1, g1, m2, v2, g2, N::MAX, 0.0)
+        .ok()
+        .flatten()
+        .map(|t| t.toi)
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CGroup {
+    Static,
+    Unit,
+    Projectile,
+    // Physics props, moved by the solver in update. See dynamics.rs.
+    Dynamic,
+    // Sensors which never block anything, and report what goes in and out.
+    // See trigger.rs.
+    Trigger,
+}
+
+// This stores data thats associated with each collision object on the rust side.
+#[derive(Debug)]
+struct EntityData {
+    id: u32,
+    e_type: CGroup,
+    // Only set for objects in the Dynamic group.
+    body: Option<Body>,
+    // Only set for projectiles which have been launched, until they stop.
+    ricochet: Option<Ricochet>,
+    // Extra velocity from apply_impulse, added to whatever try_move is asked
+    // for. Only used for units.
+    knockback: Vector<N>,
+    // Set by set_vision.
+    vision: Option<VisionCone>,
+    // Set by set_one_way. Only used for statics.
+    one_way: Option<Vector<N>>,
+    // Set by set_material.
+    material: Material,
+}
+
+fn push_sweep(sweeps: &mut Vec<f64>, from: Vector<N>, to: Vector<N>) {
+    sweeps.extend_from_slice(&[from.x, from.y, to.x, to.y]);
+}
+
+pub struct World {
+    world: CollisionWorld<f64, EntityData>,
+
+    static_groups: CollisionGroups,
+    unit_groups: CollisionGroups,
+    projectile_groups: CollisionGroups,
+    dynamic_groups: CollisionGroups,
+    trigger_groups: CollisionGroups,
+    // Launched projectiles do their own collision detection, so they only
+    // interact with triggers which are looking for them.
+    launched_groups: CollisionGroups,
+
+    // Paths taken by try_move, as [x1, y1, x2, y2] segments. These are
+    // collected over a frame and handed to last_sweeps when update is called.
+    sweeps: Vec<f64>,
+    last_sweeps: Vec<f64>,
+
+    // How far each object moved in try_move this frame. Dynamic bodies treat
+    // this as the velocity of anything they touch.
+    moves: HashMap<usize, Vector<N>>,
+
+    // Fraction of a unit's knockback lost each frame.
+    knockback_decay: N,
+
+    // What projectiles ran into. try_move collects these over a frame, and
+    // update adds the launched projectiles' events and moves them over.
+    swept_hits: Vec<ProjectileEvent>,
+    projectile_events: Vec<ProjectileEvent>,
+    // Units going in and out of view of vision cones, found by the last
+    // update.
+    vision_events: Vec<VisionEvent>,
+    // Objects going in and out of triggers, found by the last update.
+    trigger_events: Vec<TriggerEvent>,
+
+    frame_stats: Stats,
+    last_stats: Stats,
+    // Returns the current time in milliseconds. Only used for profiling.
+    clock: fn() -> f64,
+
+    log: Log,
+
+    // When set, anything which iterates over contacts does so in handle order
+    // rather than whatever order ncollide's interaction graph hands them to
+    // us. See set_deterministic.
+    deterministic: bool,
+
+    // Handles which have been removed and not reused yet, oldest first. The
+    // slab in ncollide reuses them last in, first out, and restore needs to
+    // recreate that so new objects get the same handles after a rollback.
+    free_handles: Vec<usize>,
+
+    // One per agent radius, kept in sync with the statics. See
+    // enable_navmesh.
+    navmeshes: Vec<NavMesh>,
+
+    // Units move_static carried or pushed this frame. update reports them.
+    carried: Vec<CollisionObjectHandle>,
+
+    // Bumped whenever a static is added, moved or removed, so the flow field
+    // knows to rasterize them again.
+    statics_version: u64,
+    flow_field: Option<FlowField>,
+}
+
+const STATIC_GROUP: usize = 0;
+const UNIT_GROUP: usize = 1;
+const PROJECTILES_GROUP: usize = 2;
+const DYNAMIC_GROUP: usize = 3;
+const TRIGGER_GROUP: usize = 4;
+// What sight queries are in. Nothing blacklists it.
+const QUERY_GROUP: usize = 5;
+const EPSILON: f64 = 0.000001;
+// How far something can sink into a one way wall from its solid side and
+// still be pushed back out. Anything deeper came through from behind.
+pub(crate) const ONE_WAY_SLOP: N = 0.01;
+// How far update lets things overlap before pushing them apart. try_move can
+// leave units this far inside what they touch, and shoving that out by the
+// full gap can wedge a unit into whatever is on its other side.
+pub(crate) const OVERLAP_SLOP: N = 0.0001;
+// Units closer than this to a static which moves get carried along with it.
+// A bit more than the gap update leaves when it pushes things out.
+pub(crate) const CARRY_MARGIN: N = 0.02;
+// Bisection steps when working out how far a turn can go, so it's accurate
+// to va / 2^TURN_STEPS.
+pub(crate) const TURN_STEPS: usize = 10;
+pub(crate) const DEFAULT_KNOCKBACK_DECAY: f64 = 0.1;
+
+impl Default for World {
+    fn default() -> World {
+        World::new()
+    }
+}
+
+impl World {
+    pub fn new() -> World {
+        World {
+            world: CollisionWorld::<_, _>::new(0.02),
+            static_groups: CollisionGroups::new()
+                .with_membership(&[STATIC_GROUP])
+                .with_blacklist(&[STATIC_GROUP]),
+            unit_groups: CollisionGroups::new()
+                .with_membership(&[UNIT_GROUP])
+                .with_blacklist(&[UNIT_GROUP]), // Players don't self-collide.
+            projectile_groups: CollisionGroups::new()
+                .with_membership(&[PROJECTILES_GROUP])
+                .with_whitelist(&[STATIC_GROUP, UNIT_GROUP, DYNAMIC_GROUP, TRIGGER_GROUP]),
+            dynamic_groups: CollisionGroups::new()
+                .with_membership(&[DYNAMIC_GROUP]),
+            trigger_groups: World::detect_groups(1 << UNIT_GROUP),
+            launched_groups: CollisionGroups::new()
+                .with_membership(&[PROJECTILES_GROUP])
+                .with_whitelist(&[TRIGGER_GROUP]),
+            sweeps: Vec::new(),
+            last_sweeps: Vec::new(),
+            moves: HashMap::new(),
+            knockback_decay: DEFAULT_KNOCKBACK_DECAY,
+            swept_hits: Vec::new(),
+            projectile_events: Vec::new(),
+            vision_events: Vec::new(),
+            trigger_events: Vec::new(),
+            frame_stats: Stats::default(),
+            last_stats: Stats::default(),
+            clock: default_clock,
+            log: Log::new(LogLevel::Info),
+            deterministic: false,
+            free_handles: Vec::new(),
+            navmeshes: Vec::new(),
+            carried: Vec::new(),
+            statics_version: 0,
+            flow_field: None,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn add(&mut self, id: u32, x: f64, y: f64, a: f64, shape: ShapeHandle<N>, cgroup: CGroup, linear_speed: f64) -> usize {
+        let pos = Isometry::new(Vector::new(x, y), a);
+
+        let cg = match cgroup {
+            CGroup::Static => self.static_groups,
+            CGroup::Unit => self.unit_groups,
+            CGroup::Projectile => self.projectile_groups,
+            CGroup::Dynamic => self.dynamic_groups,
+            CGroup::Trigger => self.trigger_groups,
+        };
+        let prox = match cgroup {
+            // CGroup::Static => GeometricQueryType::Proximity(0.0),
+            CGroup::Static => GeometricQueryType::Contacts(0.0, 0.0),
+            // CGroup::Unit => GeometricQueryType::Contacts(linear_speed, 0.0),
+            CGroup::Unit | CGroup::Dynamic => GeometricQueryType::Contacts(linear_speed, linear_speed),
+            CGroup::Projectile => GeometricQueryType::Proximity(0.0), // We don't care how a bullet hits you.
+            // Proximity pairs don't have contacts, so nothing slides off them.
+            CGroup::Trigger => GeometricQueryType::Proximity(0.0),
+        };
+
+        let body = if cgroup == CGroup::Dynamic { Some(Body::new(&shape)) } else { None };
+        let nav_shape = if cgroup == CGroup::Static { Some(shape.clone()) } else { None };
+        let (handle, _) = self.world.add(
+            pos,
+            shape,
+            cg,
+            prox,
+            EntityData { id, e_type: cgroup, body, ricochet: None, knockback: Vector::zeros(), vision: None, one_way: None, material: Material::default() }
+        );
+
+        let handle = handle.0;
+        if self.free_handles.last() == Some(&handle) {
+            self.free_handles.pop();
+        }
+        if let Some(shape) = nav_shape {
+            self.statics_version += 1;
+            for navmesh in self.navmeshes.iter_mut() {
+                navmesh.set_obstacle(handle, &shape, &pos);
+            }
+        }
+        handle
+    }
+
+    // Adds every object in a level. See level.rs for the format. Objects
+    // without an id are numbered from first_id. Nothing is added unless the
+    // whole level is valid.
+    pub fn load_level(&mut self, json: &str, first_id: u32) -> Result<Vec<LoadedObject>, LevelError> {
+        let level = parse_level(json)?;
+
+        Ok(level.objects.into_iter().enumerate().map(|(i, obj)| {
+            let id = obj.id.unwrap_or(first_id + i as u32);
+            let shape = match obj.shape {
+                LevelShape::Circle { radius } => make_circle(radius),
+                LevelShape::Box { w, h } => make_box(w, h),
+            };
+            let handle = self.add(id, obj.x, obj.y, obj.angle, shape, obj.group, obj.speed);
+            LoadedObject { handle, id, tags: obj.tags }
+        }).collect())
+    }
+
+    // Adds the colliders from a map made in Tiled. See tiled.rs for what
+    // becomes what. Colliders are numbered from first_id. Nothing is added
+    // unless the whole map can be imported.
+    pub fn load_tiled(&mut self, json: &str, first_id: u32) -> Result<TiledMap, TiledError> {
+        let plan = plan_tiled(json)?;
+
+        let mut loaded = plan.colliders.into_iter().enumerate().map(|(i, c)| {
+            let id = first_id + i as u32;
+            let handle = self.add(id, c.pos.translation.x, c.pos.translation.y, c.pos.rotation.angle(), c.shape, c.group, 0.0);
+            Some(LoadedObject { handle, id, tags: c.tags })
+        }).collect::<Vec<_>>();
+
+        let objects = plan.objects.into_iter().map(|o| TiledObject {
+            collider: o.collider.and_then(|i| loaded[i].take()),
+            ..o.object
+        }).collect();
+
+        Ok(TiledMap {
+            tiles: loaded.drain(..plan.tile_colliders).map(Option::unwrap).collect(),
+            objects,
+        })
+    }
+
+    pub fn remove(&mut self, handle: usize) {
+        // TODO: world.remove takes an array. It might make sense to pass an
+        // array of removed entities from javascript.
+        // Stale handles are ignored, or a double remove would put the handle
+        // on free_handles twice.
+        let is_static = match self.world.collision_object(CollisionObjectHandle(handle)) {
+            Some(co) => co.data().e_type == CGroup::Static,
+            None => return,
+        };
+        if is_static {
+            self.statics_version += 1;
+        }
+        self.world.remove(&[CollisionObjectHandle(handle)]);
+        self.free_handles.push(handle);
+        // Nobody can see it any more, but it's gone so there's no need to say
+        // so.
+        let watchers = self.world.collision_objects()
+            .filter(|(_, co)| co.data().vision.is_some())
+            .map(|(h, _)| h)
+            .collect::<Vec<_>>();
+        for h in watchers {
+            let vision = self.world.get_mut(h).unwrap().data_mut().vision.as_mut().unwrap();
+            vision.seen.retain(|seen| *seen != handle);
+        }
+        for navmesh in self.navmeshes.iter_mut() {
+            navmesh.remove_obstacle(handle);
+        }
+    }
+
+    pub fn set_position(&mut self, handle: usize, x: f64, y: f64, a: f64) {
+        let pos = Isometry::new(Vector::new(x, y), a);
+        let handle = CollisionObjectHandle(handle);
+        // Stale handles are ignored.
+        if let Some(co) = self.world.collision_object(handle) {
+            let nav_shape = if co.data().e_type == CGroup::Static { Some(co.shape().clone()) } else { None };
+            if let Some(co) = self.world.get_mut(handle) { co.set_position(pos); }
+            if let Some(shape) = nav_shape {
+                self.statics_version += 1;
+                for navmesh in self.navmeshes.iter_mut() {
+                    navmesh.set_obstacle(handle.0, &shape, &pos);
+                }
+            }
+        }
+    }
+
+    // Moves a static by (dx, dy) and turns it by da, for moving platforms and
+    // doors. Units touching it are carried along, turning with it, and units
+    // in the way of (dx, dy) are pushed ahead of it. Those get reported by the
+    // next update, and might get pushed out of other walls there. The turn
+    // isn't swept, so units it swings into are only pushed out by update -
+    // keep da small, or a thin door can swing straight through a unit.
+    // Dynamic bodies are left to the solver, which sees the platform moving.
+    // Call this before the frame's try_moves, since try_move treats statics as
+    // still. Returns where the static ended up, which is where it was for
+    // anything else, or None if there's no such object.
+    pub fn move_static(&mut self, handle: usize, dx: f64, dy: f64, da: f64) -> Option<Isometry<N>> {
+        let h = CollisionObjectHandle(handle);
+        let co = self.world.collision_object(h)?;
+        let from = *co.position();
+        if co.data().e_type != CGroup::Static { return Some(from); }
+        let delta = Vector::new(dx, dy);
+        let to = Isometry::new(from.translation.vector + delta, from.rotation.angle() + da);
+        let shape = co.shape().clone();
+        let one_way = co.data().one_way;
+
+        // Work out who gets moved before anything does. The broad phase might
+        // be out of date by now, so just look at every unit.
+        let reach = bounding_volume::aabb(shape.as_ref(), &from)
+            .merged(&bounding_volume::aabb(shape.as_ref(), &to))
+            .loosened(CARRY_MARGIN);
+        let mut moved = self.world.collision_objects()
+            .filter(|(_, u)| u.data().e_type == CGroup::Unit)
+            .filter(|(_, u)| bounding_volume::aabb(u.shape().as_ref(), u.position()).intersects(&reach))
+            .filter_map(|(u_handle, u)| {
+                let (u_pos, u_shape) = (u.position(), u.shape().as_ref());
+                if query::distance(&from, shape.as_ref(), u_pos, u_shape) <= CARRY_MARGIN {
+                    // Touching, unless it's partway through a one way wall.
+                    let riding = query::contact(u_pos, u_shape, &from, shape.as_ref(), CARRY_MARGIN)
+                        .is_none_or(|c| !self.passes_through(h, c.normal.into_inner(), c.depth));
+                    if riding { Some((u_handle, None)) } else { None }
+                } else if one_way.is_some_and(|facing| delta.dot(&facing) <= 0.0) {
+                    // Moving its open side first.
+                    None
+                } else {
+                    toi(&from, &delta, shape.as_ref(), u_pos, &Vector::zeros(), u_shape)
+                        .filter(|t| *t < 1.0)
+                        .map(|t| (u_handle, Some(t)))
+                }
+            })
+            .collect::<Vec<_>>();
+        moved.sort_by_key(|(u, _)| u.0);
+
+        self.set_position(handle, to.translation.vector.x, to.translation.vector.y, to.rotation.angle());
+        *self.moves.entry(handle).or_insert_with(Vector::zeros) += delta;
+
+        let carry = to * from.inverse();
+        for (u, toi) in moved {
+            let pos = *self.world.collision_object(u).unwrap().position();
+            let pos = match toi {
+                // Carried, wherever it is on the static.
+                None => carry * pos,
+                // Pushed by whatever of the move is left after they meet.
+                Some(t) => Translation::from(delta * (1.0 - t)) * pos,
+            };
+            if let Some(co) = self.world.get_mut(u) { co.set_position(pos); }
+            self.carried.push(u);
+            self.frame_stats.carried_units += 1;
+        }
+        Some(to)
+    }
+
+    // Starts keeping a navigation mesh of everywhere an agent of the given
+    // radius fits between the statics. See navmesh.rs. Each radius gets its
+    // own mesh, so stick to a few sizes. Does nothing if there's already a
+    // mesh for this radius, or if the radius is negative or not finite.
+    pub fn enable_navmesh(&mut self, agent_radius: f64) {
+        if !(agent_radius >= 0.0 && agent_radius.is_finite()) { return; }
+        if self.navmesh_index(agent_radius).is_some() { return; }
+        let mut navmesh = NavMesh::new(agent_radius);
+        for (h, co) in self.world.collision_objects() {
+            if co.data().e_type == CGroup::Static {
+                navmesh.set_obstacle(h.0, co.shape(), co.position());
+            }
+        }
+        self.navmeshes.push(navmesh);
+        self.rebuild_navmeshes();
+    }
+
+    fn navmesh_index(&self, agent_radius: f64) -> Option<usize> {
+        self.navmeshes.iter().position(|n| n.agent_radius() == agent_radius)
+    }
+
+    fn rebuild_navmeshes(&mut self) {
+        for navmesh in self.navmeshes.iter_mut() {
+            match navmesh.rebuild() {
+                Some(tiles) => self.frame_stats.navmesh_tiles_rebuilt += tiles as u32,
+                None => log_warn!(self.log, "The statics are too spread out for a navmesh of radius {}", navmesh.agent_radius()),
+            }
+        }
+    }
+
+    // The navigation mesh for agents of this radius, brought up to date with
+    // any statics which have changed. None until enable_navmesh is called,
+    // and while the statics are too spread out to mesh.
+    pub fn navmesh(&mut self, agent_radius: f64) -> Option<&NavMesh> {
+        self.rebuild_navmeshes();
+        let i = self.navmesh_index(agent_radius)?;
+        Some(&self.navmeshes[i]).filter(|navmesh| !navmesh.too_big())
+    }
+
+    // Returns waypoints from start to goal (both included) which an agent of
+    // the given radius can follow without running into any statics, or None
+    // if it can't get there. The path only turns at corners. Ends slightly off
+    // the mesh, like a unit pressed against a wall, are moved to the closest
+    // point on it. See pathfind.rs.
+    //
+    // Only radii passed to enable_navmesh have a mesh. Anything else finds
+    // nothing.
+    pub fn find_path(&mut self, start_x: f64, start_y: f64, goal_x: f64, goal_y: f64, agent_radius: f64) -> Option<Vec<Point<N>>> {
+        let navmesh = self.navmesh(agent_radius)?;
+        pathfind::find_path(navmesh, Point::new(start_x, start_y), Point::new(goal_x, goal_y), agent_radius)
+    }
+
+    // Points the flow field at a new target. The statics are only rasterized
+    // again (into cells of cell_size) if they've changed, the cell size has
+    // changed or the target has gone off the edge of the grid. Otherwise this
+    // just redoes the search, and not even that if the target is still in the
+    // same cell. So call it whenever the target moves. See flowfield.rs.
+    pub fn build_flow_field(&mut self, target_x: f64, target_y: f64, cell_size: f64) {
+        if !(target_x.is_finite() && target_y.is_finite()) {
+            log_warn!(self.log, "Can't point the flow field at ({}, {})", target_x, target_y);
+            return;
+        }
+        let target = Point::new(target_x, target_y);
+        if let Some(field) = &mut self.flow_field {
+            if field.cell_size == cell_size && field.statics_version == self.statics_version && field.covers(&target) {
+                self.frame_stats.flow_field_cells_visited += field.retarget(target) as u32;
+                return;
+            }
+        }
+
+        let statics = self.world.collision_objects()
+            .filter(|(_, co)| co.data().e_type == CGroup::Static)
+            .map(|(_, co)| (co.shape(), co.position()))
+            .collect::<Vec<_>>();
+        self.flow_field = FlowField::new(&statics, target, cell_size, self.statics_version);
+        match &mut self.flow_field {
+            Some(field) => {
+                self.frame_stats.flow_field_rebuilds += 1;
+                self.frame_stats.flow_field_cells_visited += field.retarget(target) as u32;
+            },
+            None => log_warn!(self.log, "Can't build a flow field with cells of {}", cell_size),
+        }
+    }
+
+    // Which way to go from (x, y) to get to the flow field's target, as a unit
+    // vector. Zero at the target, where the target can't be reached, or if
+    // there's no flow field.
+    pub fn flow_direction(&self, x: f64, y: f64) -> Vector<N> {
+        match &self.flow_field {
+            Some(field) => field.direction(&Point::new(x, y)),
+            None => Vector::zeros(),
+        }
+    }
+
+    // Adjusts units' preferred velocities (per frame, as you'd pass to
+    // try_move) so they steer around each other, dynamic bodies and statics
+    // instead of walking into them. Takes (handle, velocity) pairs and returns
+    // the new velocities in the same order. Call it once a frame with every
+    // unit that's about to move, just before their try_moves. See
+    // avoidance.rs.
+    //
+    // Units in the same call dodge each other. Units which aren't are assumed
+    // to carry on as they moved this frame (or stand still), and bodies as
+    // they're going. Units never go faster than they wanted to. Anything which
+    // isn't a unit, or doesn't exist, gets its velocity back unchanged.
+    pub fn avoid(&mut self, preferred: &[(usize, Vector<N>)]) -> Vec<Vector<N>> {
+        let agents = preferred.iter()
+            .filter(|(h, _)| self.world.collision_object(CollisionObjectHandle(*h)).is_some_and(|co| co.data().e_type == CGroup::Unit))
+            .cloned()
+            .collect::<HashMap<_, _>>();
+        let fastest = agents.values().map(|v| v.norm()).fold(0.0, N::max);
+
+        let mut result = Vec::with_capacity(preferred.len());
+        for &(handle, pref) in preferred {
+            let co = match self.world.collision_object(CollisionObjectHandle(handle)) {
+                Some(co) if agents.contains_key(&handle) => co,
+                _ => {
+                    result.push(pref);
+                    continue;
+                },
+            };
+            let pos = Point::from(co.position().translation.vector);
+            let radius = avoidance::bounding_radius(co.shape());
+            let speed = pref.norm();
+            let ball = Ball::new(radius);
+            let ball_pos = Isometry::new(pos.coords, 0.0);
+
+            // Nothing further than this can get in the way within the time
+            // horizon.
+            let reach = radius + (speed + fastest) * AVOID_TIME_HORIZON;
+            let aabb = bounding_volume::AABB::new(pos - Vector::repeat(reach), pos + Vector::repeat(reach));
+            // Anything a projectile could hit is something to avoid.
+            let mut others = self.world.interferences_with_aabb(&aabb, &self.projectile_groups)
+                .filter(|(h, other)| h.0 != handle && other.data().e_type != CGroup::Trigger)
+                .collect::<Vec<_>>();
+            others.sort_by_key(|(h, _)| h.0);
+
+            let mut static_lines = Vec::new();
+            let mut agent_lines = Vec::new();
+            for (other_handle, other) in others {
+                let data = other.data();
+                if data.e_type == CGroup::Static {
+                    let margin = speed * AVOID_STATIC_TIME_HORIZON;
+                    match query::closest_points(&ball_pos, &ball, other.position(), other.shape().as_ref(), margin) {
+                        query::ClosestPoints::WithinMargin(a, b) => {
+                            if let Some(normal) = (a - b).try_normalize(0.0) {
+                                static_lines.push(avoidance::static_line(normal, (a - b).norm()));
+                            }
+                        },
+                        query::ClosestPoints::Intersecting => {
+                            let contact = query::contact(&ball_pos, &ball, other.position(), other.shape().as_ref(), 0.0);
+                            if let Some(c) = contact {
+                                static_lines.push(avoidance::static_line(-c.normal.into_inner(), 0.0));
+                            }
+                        },
+                        query::ClosestPoints::Disjoint => {},
+                    }
+                    continue;
+                }
+
+                let h = other_handle.0;
+                let (vel, responsibility) = match (agents.get(&h), data.body) {
+                    (Some(v), _) => (*v, 0.5),
+                    (None, Some(body)) => (body.vel, 1.0),
+                    (None, None) => (self.moves.get(&h).cloned().unwrap_or_else(Vector::zeros), 1.0),
+                };
+                let neighbour = Neighbour {
+                    pos: Point::from(other.position().translation.vector),
+                    vel,
+                    radius: avoidance::bounding_radius(other.shape()),
+                    responsibility,
+                };
+                agent_lines.push(avoidance::agent_line(pos, pref, radius, &neighbour));
+            }
+
+            self.frame_stats.avoidance_neighbours += (static_lines.len() + agent_lines.len()) as u32;
+            let static_count = static_lines.len();
+            static_lines.extend(agent_lines);
+            result.push(avoidance::solve(&static_lines, static_count, speed, pref));
+        }
+        result
+    }
+
+    // Groups for queries which only care about the objects in mask, which
+    // has bit 1 << group set for each CGroup. Launched projectiles never
+    // match.
+    fn mask_groups(mask: u32) -> CollisionGroups {
+        CollisionGroups::new().with_membership(&[QUERY_GROUP]).with_whitelist(&World::mask_list(mask))
+    }
+
+    fn mask_list(mask: u32) -> Vec<usize> {
+        [STATIC_GROUP, UNIT_GROUP, PROJECTILES_GROUP, DYNAMIC_GROUP, TRIGGER_GROUP].iter()
+            .cloned()
+            .filter(|g| mask & (1 << g) != 0)
+            .collect()
+    }
+
+    // Groups for a trigger which detects the objects in mask (like
+    // mask_groups). Launched projectiles count as projectiles here.
+    fn detect_groups(mask: u32) -> CollisionGroups {
+        CollisionGroups::new().with_membership(&[TRIGGER_GROUP]).with_whitelist(&World::mask_list(mask))
+    }
+
+    // Sets which groups a trigger detects, as bit 1 << group for each CGroup.
+    // Defaults to just units. Projectiles include launched ones. Does nothing to anything other than a trigger.
+    pub fn set_trigger_groups(&mut self, handle: usize, mask: u32) {
+        let handle = CollisionObjectHandle(handle);
+        if self.world.collision_object(handle).is_some_and(|co| co.data().e_type == CGroup::Trigger) {
+            if let Some(co) = self.world.get_mut(handle) { co.set_collision_groups(World::detect_groups(mask)); }
+        }
+    }
+
+    // Objects which went into or out of a trigger in the last update, ordered
+    // by trigger then other handle.
+    pub fn trigger_events(&self) -> Vec<TriggerEvent> {
+        self.trigger_events.clone()
+    }
+
+    // Picks the trigger events out of the collision world's proximity events.
+    fn collect_trigger_events(&mut self) {
+        let mut events = Vec::new();
+        for evt in self.world.proximity_events().iter() {
+            let kind = if evt.new_status == query::Proximity::Intersecting {
+                TriggerEventKind::Enter
+            } else if evt.prev_status == query::Proximity::Intersecting {
+                TriggerEventKind::Exit
+            } else {
+                continue;
+            };
+            let (c1, c2) = match (self.world.collision_object(evt.collider1), self.world.collision_object(evt.collider2)) {
+                (Some(c1), Some(c2)) => ((evt.collider1, c1), (evt.collider2, c2)),
+                _ => continue,
+            };
+            // Both ways round, in case they're both triggers.
+            for ((trigger_handle, trigger), (other_handle, other)) in [(c1, c2), (c2, c1)].iter() {
+                if trigger.data().e_type == CGroup::Trigger {
+                    let event = TriggerEvent { kind, trigger: trigger.data().id, other: other.data().id };
+                    events.push(((trigger_handle.0, other_handle.0), event));
+                }
+            }
+        }
+        events.sort_by_key(|(handles, _)| *handles);
+        self.trigger_events = events.into_iter().map(|(_, e)| e).collect();
+    }
+
+    // Whether there's a clear line between the centers of two objects. Only
+    // statics get in the way. False if either doesn't exist.
+    pub fn line_of_sight(&mut self, handle_a: usize, handle_b: usize) -> bool {
+        let (a, b) = match (self.world.collision_object(CollisionObjectHandle(handle_a)), self.world.collision_object(CollisionObjectHandle(handle_b))) {
+            (Some(a), Some(b)) => (Point::from(a.position().translation.vector), Point::from(b.position().translation.vector)),
+            _ => return false,
+        };
+        self.frame_stats.visibility_rays += 1;
+        self.clear_line(a, b, handle_a, handle_b)
+    }
+
+    // Whether there are no statics between a and b, other than the two
+    // skipped objects.
+    fn clear_line(&self, a: Point<N>, b: Point<N>, skip_a: usize, skip_b: usize) -> bool {
+        let ray = query::Ray::new(a, b - a);
+        let groups = World::mask_groups(1 << STATIC_GROUP);
+        !self.world.interferences_with_ray(&ray, 1.0, &groups)
+            .any(|(h, _, hit)| hit.toi < 1.0 && h.0 != skip_a && h.0 != skip_b)
+    }
+
+    // The region visible from (x, y) out to radius, as a polygon going
+    // anticlockwise. Anything in occluder_mask (bit 1 << group for each
+    // CGroup) blocks the view, unless (x, y) is inside it. Draw it as a
+    // triangle fan from (x, y). See visibility.rs.
+    pub fn visibility_polygon(&mut self, x: f64, y: f64, radius: f64, occluder_mask: u32) -> Vec<Point<N>> {
+        let origin = Point::new(x, y);
+        let aabb = bounding_volume::AABB::new(origin - Vector::repeat(radius), origin + Vector::repeat(radius));
+        let groups = World::mask_groups(occluder_mask);
+        let mut occluders = self.world.interferences_with_aabb(&aabb, &groups).collect::<Vec<_>>();
+        occluders.sort_by_key(|(h, _)| h.0);
+        let occluders = occluders.iter()
+            .map(|(_, co)| (co.shape(), co.position()))
+            .collect::<Vec<_>>();
+
+        let (polygon, rays) = visibility::visibility_polygon(origin, radius, &occluders);
+        self.frame_stats.visibility_rays += rays as u32;
+        polygon
+    }
+
+    // Puts a vision cone on an object, facing the same way it does. angle is
+    // how wide the cone is, in radians. Which units it can see is worked out
+    // by update, and changes come back from vision_events. Setting it again
+    // changes the cone but remembers what it could already see. See
+    // vision.rs.
+    pub fn set_vision(&mut self, handle: usize, angle: f64, range: f64) {
+        if let Some(co) = self.world.get_mut(CollisionObjectHandle(handle)) {
+            let data = co.data_mut();
+            let seen = data.vision.take().map(|v| v.seen).unwrap_or_default();
+            data.vision = Some(VisionCone { half_angle: angle / 2.0, range, seen });
+        }
+    }
+
+    // Takes an object's vision cone away, without any Lost events.
+    pub fn clear_vision(&mut self, handle: usize) {
+        if let Some(co) = self.world.get_mut(CollisionObjectHandle(handle)) {
+            co.data_mut().vision = None;
+        }
+    }
+
+    // Units which came into or went out of view in the last update, ordered
+    // by watcher then unit handle.
+    pub fn vision_events(&self) -> Vec<VisionEvent> {
+        self.vision_events.clone()
+    }
+
+    // Works out what every vision cone can see now, and reports what changed.
+    fn update_vision(&mut self) {
+        let units = self.world.collision_objects()
+            .filter(|(_, co)| co.data().e_type == CGroup::Unit)
+            .map(|(h, co)| (h.0, Point::from(co.position().translation.vector), avoidance::bounding_radius(co.shape())))
+            .collect::<Vec<_>>();
+        let mut watchers = self.world.collision_objects()
+            .filter(|(_, co)| co.data().vision.is_some())
+            .map(|(h, _)| h)
+            .collect::<Vec<_>>();
+        watchers.sort_by_key(|h| h.0);
+
+        self.vision_events.clear();
+        for h in watchers {
+            let co = self.world.collision_object(h).unwrap();
+            let (pos, vision) = (*co.position(), co.data().vision.clone().unwrap());
+            let eye = Point::from(pos.translation.vector);
+            let mut seen = Vec::new();
+            for &(unit, center, radius) in units.iter() {
+                if unit == h.0 || !vision.contains(&pos, center, radius) { continue; }
+                self.frame_stats.visibility_rays += 1;
+                if self.clear_line(eye, center, h.0, unit) { seen.push(unit); }
+            }
+            seen.sort();
+
+            let watcher = co.data().id;
+            let id = |handle: usize| self.world.collision_object(CollisionObjectHandle(handle)).unwrap().data().id;
+            let mut events = seen.iter()
+                .filter(|u| vision.seen.binary_search(u).is_err())
+                .map(|&u| (u, VisionEvent { kind: VisionEventKind::Spotted, watcher, unit: id(u) }))
+                .chain(vision.seen.iter()
+                    .filter(|u| seen.binary_search(u).is_err())
+                    .map(|&u| (u, VisionEvent { kind: VisionEventKind::Lost, watcher, unit: id(u) })))
+                .collect::<Vec<_>>();
+            events.sort_by_key(|(u, _)| *u);
+            self.vision_events.extend(events.into_iter().map(|(_, e)| e));
+
+            self.world.get_mut(h).unwrap().data_mut().vision.as_mut().unwrap().seen = seen;
+        }
+    }
+
+    // Makes a static only block things on one side of it, like a ledge you can
+    // jump up through or a door you can't go back through. (nx, ny) points out
+    // of the solid side; things moving the other way pass straight through.
+    // Only units moving with try_move get through - projectiles and dynamic
+    // bodies still treat it as solid.
+    pub fn set_one_way(&mut self, handle: usize, nx: f64, ny: f64) {
+        let normal = Vector::new(nx, ny);
+        if normal.norm() < EPSILON { return; }
+        if let Some(co) = self.world.get_mut(CollisionObjectHandle(handle)) {
+            if co.data().e_type == CGroup::Static {
+                co.data_mut().one_way = Some(normal.normalize());
+            }
+        }
+    }
+
+    // Makes a one way static block from both sides again.
+    pub fn clear_one_way(&mut self, handle: usize) {
+        if let Some(co) = self.world.get_mut(CollisionObjectHandle(handle)) {
+            co.data_mut().one_way = None;
+        }
+    }
+
+    fn one_way(&self, handle: CollisionObjectHandle) -> Option<Vector<N>> {
+        self.world.collision_object(handle).and_then(|co| co.data().one_way)
+    }
+
+    // Whether a contact with another object should be ignored, because it's a
+    // one way wall we're on the wrong side of (or already partway through).
+    // normal points from us into the other object.
+    fn passes_through(&self, other: CollisionObjectHandle, normal: Vector<N>, depth: N) -> bool {
+        self.one_way(other).is_some_and(|facing| normal.dot(&facing) > -EPSILON || depth > ONE_WAY_SLOP)
+    }
+
+    // Sets what sliding along an object is like. See material.rs.
+    pub fn set_material(&mut self, handle: usize, id: u32, friction: f64, slip: f64, sound: u32) {
+        if let Some(co) = self.world.get_mut(CollisionObjectHandle(handle)) {
+            co.data_mut().material = Material { id, friction: friction.clamp(0.0, 1.0), slip: slip.clamp(0.0, 1.0), sound };
+        }
+    }
+
+    pub fn material(&self, handle: usize) -> Option<Material> {
+        self.world.collision_object(CollisionObjectHandle(handle)).map(|co| co.data().material)
+    }
+
+    fn slide_vel(&self, other: CollisionObjectHandle, tangent: Vector<N>, vel_dot: N, orig_vel: Vector<N>) -> Vector<N> {
+        let material = self.world.collision_object(other).unwrap().data().material;
+        tangent * material.slide(vel_dot, orig_vel.norm())
+    }
+
+    fn touch(&self, touched: &mut Vec<u32>, other: CollisionObjectHandle) {
+        let id = self.world.collision_object(other).unwrap().data().material.id;
+        if !touched.contains(&id) { touched.push(id); }
+    }
+
+    // How much of a turn by va an object can make before it swings into
+    // something, as a fraction. Anything it already overlaps only blocks it
+    // from going in further. Balls can always turn.
+    fn sweep_turn(&mut self, handle: CollisionObjectHandle, shape: &ShapeHandle<N>, from: &Isometry<N>, va: N) -> N {
+        if va == 0.0 || shape.is_shape::<Ball<N>>() { return 1.0; }
+        // The ends of a long shape can swing well outside the contacts found
+        // by the last update, so look for anything within reach of a full
+        // turn.
+        let sphere = bounding_volume::bounding_sphere(shape.as_ref(), from);
+        let (center, radius) = (*sphere.center(), sphere.radius());
+        let reach = bounding_volume::AABB::new(center - Vector::repeat(radius), center + Vector::repeat(radius));
+        let groups = *self.world.collision_object(handle).unwrap().collision_groups();
+        let mut others = self.world.interferences_with_aabb(&reach, &groups)
+            .filter(|(h, _)| *h != handle)
+            .filter(|(_, co)| matches!(co.query_type(), GeometricQueryType::Contacts(..)))
+            .map(|(h, _)| h)
+            .collect::<Vec<_>>();
+        others.sort_by_key(|h| h.0);
+
+        let blockers = others.iter().map(|h| {
+            let co = self.world.collision_object(*h).unwrap();
+            let depth = query::contact(from, shape.as_ref(), co.position(), co.shape().as_ref(), 0.0)
+                .map_or(0.0, |c| c.depth.max(0.0));
+            (*h, *co.position(), co.shape().clone(), depth)
+        }).collect::<Vec<_>>();
+
+        let mut queries = 0;
+        let mut overlaps = |turn: N| {
+            let turned = Isometry::new(from.translation.vector, from.rotation.angle() + va * turn);
+            blockers.iter().any(|(h, pos2, shape2, allowed)| {
+                queries += 1;
+                query::contact(&turned, shape.as_ref(), pos2, shape2.as_ref(), 0.0)
+                    .is_some_and(|c| c.depth > *allowed && !self.passes_through(*h, c.normal.into_inner(), c.depth))
+            })
+        };
+        let result = if !overlaps(1.0) { 1.0 } else {
+            // Find the most we can turn by bisection.
+            let (mut lo, mut hi) = (0.0, 1.0);
+            for _ in 0..TURN_STEPS {
+                let mid = (lo + hi) / 2.0;
+                if overlaps(mid) { hi = mid; } else { lo = mid; }
+            }
+            lo
+        };
+        self.frame_stats.turn_queries += queries;
+        result
+    }
+
+    pub fn try_move(&mut self, handle: usize, vx: f64, vy: f64, va: f64) -> Isometry<N> {
+        self.try_move_with_materials(handle, vx, vy, va).0
+    }
+
+    // try_move, which also returns the ids of the materials of everything the
+    // object touched or slid along on the way, in the order it got to them.
+    // Stale handles don't move anything, and get the identity back.
+    pub fn try_move_with_materials(&mut self, handle: usize, vx: f64, vy: f64, va: f64) -> (Isometry<N>, Vec<u32>) {
+        // console_log!("try move {} {} {}", handle, vx, vy);
+        self.frame_stats.try_move_calls += 1;
+        let handle = CollisionObjectHandle(handle);
+        let co = match self.world.collision_object(handle) {
+            Some(co) => co,
+            None => return (Isometry::identity(), Vec::new()),
+        };
+        // Knockback goes on top of whatever the unit is trying to do, and
+        // slides along walls the same way.
+        let orig_vel = Vector::new(vx, vy) + co.data().knockback;
+        let mut vel = orig_vel;
+        let id = co.data().id;
+        let from = *co.position();
+        let mut pos = Isometry::new(from.translation.vector, from.rotation.angle() + va);
+        self.log.trace(TraceKind::Start, id, pos.translation.vector, orig_vel, va);
+        let start = pos.translation.vector;
+
+        // Cloned (it's just an Arc) so we can push dynamic bodies around as we
+        // go.
+        let shape = co.shape().clone();
+
+        if co.data().e_type == CGroup::Projectile {
+            let pos = self.sweep_projectile(handle, id, &shape, pos, orig_vel);
+            return (self.finish_move(handle, id, start, pos, orig_vel), Vec::new());
+        }
+
+        let mut t_remaining = 1.0;
+        let mut touched = Vec::new();
+
+        let mut deflect_sign: Option<bool> = None;
+        
+        let mut max_neg_vdot = 0.0;
+        let mut min_pos_vdot = 0.0;
+
+        // The turn happens first, and gets cut short if it would swing us
+        // into something.
+        let turn = self.sweep_turn(handle, &shape, &from, va);
+        if turn < 1.0 {
+            pos = Isometry::new(from.translation.vector, from.rotation.angle() + va * turn);
+            self.frame_stats.blocked_turns += 1;
+            self.log.trace(TraceKind::Turn, id, pos.translation.vector, vel, va * turn);
+        }
+
+        // We need to run through the proximities a few times. This is a bit
+        // inefficient - the list will almost always only have one element.
+        // As always, it'd be nice to have a vec-ish type which has a hot
+        // path for 1 element.
+        let other_handles = self.world.contacts_with(handle, false).map(|iter| iter.map(|(h1, h2, _alg, manifold)| {
+            let h_other = if h1 == handle {h2} else {h1};
+            // The manifold's normal points from h1 to h2, and we might be
+            // either of them.
+            let m = if h1 == handle {1.0} else {-1.0};
+            let contact = manifold.deepest_contact().map(|c| (
+                c.contact.normal.into_inner() * m,
+                c.contact.depth,
+                if m > 0.0 {c.contact.world2} else {c.contact.world1}
+            ));
+            // console_log!("contact {:?}", contact);
+            (h_other, contact)
+        }).collect::<Vec<_>>());
+
+        if let Some(mut other_handles) = other_handles {
+            if self.deterministic {
+                other_handles.sort_by_key(|(h, _)| h.0);
+            }
+            // Turning makes the contacts from the last update stale, so work
+            // them out again for the way we face now.
+            if pos.rotation != from.rotation {
+                let limit = self.world.collision_object(handle).unwrap().query_type().query_limit();
+                for (h, contact) in other_handles.iter_mut() {
+                    let co2 = self.world.collision_object(*h).unwrap();
+                    *contact = query::contact(&pos, shape.as_ref(), co2.position(), co2.shape().as_ref(), limit + co2.query_type().query_limit())
+                        .map(|c| (c.normal.into_inner(), c.depth, c.world2));
+                }
+            }
+            // One way walls we're touching from behind don't count at all.
+            other_handles.retain(|(h, contact)| !contact.is_some_and(|(normal, depth, _)|
+                depth > -EPSILON && self.passes_through(*h, normal, depth)));
+
+            // I also really wish I didn't need to do this. We need to tag off
+            // which edges we've collided with.
+            let mut marked = Vec::with_capacity(other_handles.len());
+            marked.resize(other_handles.len(), false);
+
+            // if other_handles.len() > 0 {
+            //     console_log!("velocity {}", orig_vel.norm());
+            //     console_log!("checking against other handles {:?}", other_handles);
+            //     for (h, _) in other_handles.iter() {
+            //         let co2 = self.world.collision_object(*h).unwrap();
+            //         let pos2 = co2.position();
+            //         let shape2 = co2.shape();
+            //         console_log!("distance to {}: {:?}", h.0, query::distance(
+            //             &pos, shape.as_ref(),
+            //             pos2, shape2.as_ref()
+            //         ));
+
+            //     }
+            // }
+
+            // First we'll go through and pre-process all the existing contacts.
+            // TODO: Clean this up - move this code above.
+            for (other_handle, contact) in other_handles.iter() {
+                if let Some((normal, depth, _point)) = contact {
+                    if *depth > -EPSILON {
+                        self.touch(&mut touched, *other_handle);
+                        let tangent = v_perp(*normal);
+                        if normal.dot(&orig_vel) <= 0.0 { // same as &vel here.
+                            // Moving away.
+                            let vel_dot = -tangent.dot(&orig_vel);
+                            if vel_dot > 0.0 { min_pos_vdot = vel_dot.max(min_pos_vdot); }
+                            else { max_neg_vdot = vel_dot.min(max_neg_vdot); }
+                            // console_log!("moving away from {} +:{} -:{}", other_handle.0, min_pos_vdot, max_neg_vdot);
+                        }
+                    }
+                }
+            }
+            for (other_handle, contact) in other_handles.iter() {
+                if let Some((normal, depth, point)) = contact {
+                    if *depth > -EPSILON {
+                        // The normal points in to the object.
+                        // console_log!("2: normal {:?} depth {}", normal, depth);
+
+                        let tangent = v_perp(*normal);
+                        let vel_dot = tangent.dot(&orig_vel);
+                        
+                        // We're moving toward the object.
+                        // (Sliding parallel to a wall we touch doesn't count.)
+                        if normal.dot(&vel) > EPSILON { // vel or orig_vel??
+                            // console_log!("moving toward {}", normal.dot(&vel));
+                            self.push_body(*other_handle, *point, *normal, orig_vel);
+                            if vel_dot > 0.0 && vel_dot < min_pos_vdot {
+                            // if vel_dot > 0.0 && vel_dot > -min_pos_vdot {
+                                // console_log!("x {} {}", vel_dot, min_pos_vdot);
+                                vel = Vector::zeros();
+                                self.log.trace(TraceKind::Blocked, id, pos.translation.vector, vel, 0.0);
+                                break;
+                            } else if vel_dot < 0.0 && vel_dot > max_neg_vdot {
+                            // } else if vel_dot < 0.0 && vel_dot < -max_neg_vdot {
+                                // console_log!("y {} {}", vel_dot, min_pos_vdot);
+                                vel = Vector::zeros();
+                                self.log.trace(TraceKind::Blocked, id, pos.translation.vector, vel, 0.0);
+                                break;
+                            } else {
+                                vel = self.slide_vel(*other_handle, tangent, vel_dot, orig_vel);
+                                // console_log!("->    : pos {:?} vel {:?} t {:?}", pos, vel, t_remaining);
+                                self.log.trace(TraceKind::Deflect, id, pos.translation.vector, vel, vel_dot.signum());
+
+                                // console_log!("deflect_sign {:?} {}", deflect_sign, vel_dot);
+                                if let Some(deflect_sign) = deflect_sign {
+                                    if deflect_sign != (vel_dot < 0.0) {
+                                        // We've hit the bottom of a V wall. Stop.
+                                        // console_log!("bottom of v well. Stopping movement");
+                                        vel = Vector::zeros();
+                                        self.log.trace(TraceKind::VWell, id, pos.translation.vector, vel, 0.0);
+                                        break;
+                                    }
+                                } else {
+                                    deflect_sign = Some(vel_dot < 0.0);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+
+
+            let mut iterations = 0;
+
+            // TODO: Consider also adding a max iteration count here.
+            while t_remaining > 0.001 && v_d2(vel) > EPSILON { // And non-zero velocity?
+                iterations += 1;
+                // 1. Find the first object we collide with.
+                let mut first_collide = None;
+                let mut collide_at = t_remaining;
+                let mut idx = 0;
+
+                for (i, (other_handle, contact)) in other_handles.iter().enumerate() {
+                // for (other_handle, contact) in other_handles.iter() {
+                    if marked[i] { continue; }
+                    // We can slide along things we're already touching (those
+                    // were looked at above), but a corner can still swing into
+                    // another side of them. So sweep from a little way back
+                    // off them, which hits before we sink in as far as update
+                    // lets things overlap. If that's still inside (contacts
+                    // can understate how deep we are) there's no telling, so
+                    // leave it to the checks above as before.
+                    let (from, touching) = match contact {
+                        Some((normal, depth, _point)) if *depth >= -EPSILON => {
+                            let back = normal * (depth.max(0.0) + OVERLAP_SLOP / 2.0);
+                            (Isometry::from_parts(Translation::from(pos.translation.vector - back), pos.rotation), true)
+                        },
+                        _ => (pos, false),
+                    };
+                    // We can't hit the solid side of a one way wall moving
+                    // the way it faces.
+                    if self.one_way(*other_handle).is_some_and(|facing| vel.dot(&facing) >= 0.0) { continue; }
+
+                    let co2 = self.world.collision_object(*other_handle).unwrap();
+                    let pos2 = co2.position();
+                    let shape2 = co2.shape();
+
+                    // For now, everything we might collide with is static. So
+                    // we'll predict off that assumption.
+                    self.frame_stats.toi_queries += 1;
+                    if let Some(time) = toi(
+                        &from, &vel, shape.as_ref(),
+                        pos2, &Vector::zeros(), shape2.as_ref())
+                    {
+                        if time < collide_at && !(touching && time <= 0.0) {
+                            collide_at = time;
+                            // The contact we're touching it at says nothing
+                            // about the side we'd hit.
+                            first_collide = Some((other_handle, if touching { None } else { *contact }));
+                            idx = i;
+                        }
+                    }
+                }
+                // console_log!("first collide {:?} at {}", first_collide, collide_at);
+
+                match first_collide {
+                    None => {
+                        // Great! No collision. We can just move forward by the requested amount.
+                        // (vel is per frame, and we might have used some of
+                        // the frame up getting here.)
+                        // pos = pos * Isometry::new(vel, 0.0);
+                        let from = pos.translation.vector;
+                        pos.append_translation_mut(&Translation::from(vel * t_remaining));
+                        push_sweep(&mut self.sweeps, from, pos.translation.vector);
+                        break;
+                    },
+                    Some((other_handle, contact)) => {
+                        // other_handles[idx] = other_handles.pop().unwrap();
+                        marked[idx] = true;
+
+                        let other_handle = *other_handle;
+                        let co2 = self.world.collision_object(other_handle).unwrap();
+                        let pos2 = *co2.position();
+                        let shape2 = co2.shape();
+                        // We're going to hit this object. First we need the
+                        // collision normal. Sadly time_of_impact doesn't return
+                        // it, so we use the contact manifold from the last
+                        // update. That can be missing if we rotated or moved
+                        // further than the prediction distance, in which case
+                        // recalculate the contact at the point of impact.
+
+                        // Also for some reason the final parameter of contact
+                        // is ignored in some situations - hence * 1.001 to make
+                        // sure we intersect.
+                        let contact = contact.or_else(|| query::contact(
+                            &(Isometry::new(vel * (collide_at * 1.001), 0.0) * pos), shape.as_ref(),
+                            &pos2, shape2.as_ref(),
+                            0.01
+                        ).map(|c| (c.normal.into_inner(), c.depth, c.world2)));
+
+                        // Coming at a one way wall from the side or behind.
+                        if contact.is_some_and(|(normal, _, _)| self.passes_through(other_handle, normal, 0.0)) {
+                            continue;
+                        }
+
+                        if let Some((normal, _depth, point)) = contact
+                        {
+                            // Let the object move forward to this point. Trim t_remaining. Project velocity.
+                            // console_log!("before: pos {:?} vel {:?} t {} ct {} norm {:?} depth {}", pos.translation, vel, t_remaining, collide_at, normal, depth);
+
+                            // Move to the contact
+                            let delta_pos = vel * collide_at; //- contact.normal.as_ref() * 0.001;
+                            let from = pos.translation.vector;
+                            pos.append_translation_mut(&Translation::from(delta_pos));
+                            push_sweep(&mut self.sweeps, from, pos.translation.vector);
+                            t_remaining -= collide_at;
+                            self.log.trace(TraceKind::Impact, id, pos.translation.vector, vel, collide_at);
+                            self.push_body(other_handle, point, normal, vel);
+                            self.touch(&mut touched, other_handle);
+
+                            // Figure out where to go from here
+                            let tangent = v_perp(normal);
+                            let vel_dot = tangent.dot(&orig_vel);
+
+                            if (vel_dot > 0.0 && vel_dot < min_pos_vdot) || (vel_dot < 0.0 && vel_dot > max_neg_vdot) {
+                                vel = Vector::zeros();
+                                self.log.trace(TraceKind::Blocked, id, pos.translation.vector, vel, 0.0);
+                            } else {
+                                vel = self.slide_vel(other_handle, tangent, vel_dot, orig_vel);
+                                self.log.trace(TraceKind::Deflect, id, pos.translation.vector, vel, vel_dot.signum());
+                            }
+                            // console_log!("->    : pos {:?} vel {:?} t {:?}", pos.translation, vel, t_remaining);
+
+                            if let Some(deflect_sign) = deflect_sign {
+                                if deflect_sign != (vel_dot < 0.0) {
+                                    // We've hit the bottom of a V wall. Stop.
+                                    // console_log!("bottom of v well. Stopping movement");
+                                    self.log.trace(TraceKind::VWell, id, pos.translation.vector, vel, 0.0);
+                                    break;
+                                    // vel = Vector::zeros();
+                                }
+                            } else {
+                                deflect_sign = Some(vel_dot < 0.0);
+                            }
+                        } else {
+                            // We can't tell which way to slide, so the safest
+                            // thing to do is stop where we are.
+                            log_warn!(self.log, "No contact found. pos {:?} vel {:?} pos2 {:?} t {}",
+                                pos.translation, vel, pos2.translation, collide_at);
+                            break;
+                        }
+                    }
+                }
+                // console_log!("t_remaining -> {}", t_remaining);
+
+                // if other_handles.len() > 0 {
+                //     console_log!("v {:?} -> ({:?})", vel, pos);
+                // }
+
+                if iterations > 20 {
+                    // This can happen if two objects are epsilon apart - we jitter forever between them, trying to move.
+                    // console_log!("pos {:?} v {:?} t {} collide_at {}", pos.translation, vel, t_remaining, collide_at);
+                    // panic!("Cannot figure out a good object position")
+                    log_warn!(self.log, "Stuck - cannot figure out a good object position (entity {})", id);
+                    self.log.trace(TraceKind::Stuck, id, pos.translation.vector, vel, iterations as f64);
+                    self.frame_stats.stuck += 1;
+                    break
+                }
+
+                // marked[idx] = true;
+            }
+
+            self.frame_stats.try_move_iterations += iterations;
+            self.frame_stats.max_try_move_iterations = self.frame_stats.max_try_move_iterations.max(iterations);
+        } else {
+            // We never seem to get here. Should be fine, but not tested.
+            log_info!(self.log, "try_move found no contact list for entity {}", id);
+            let from = pos.translation.vector;
+            pos.append_translation_mut(&Translation::from(vel));
+            push_sweep(&mut self.sweeps, from, pos.translation.vector);
+        }
+
+        // TODO: Return the normal
+        (self.finish_move(handle, id, start, pos, vel), touched)
+    }
+
+    fn finish_move(&mut self, handle: CollisionObjectHandle, id: u32, start: Vector<N>, pos: Isometry<N>, vel: Vector<N>) -> Isometry<N> {
+        *self.moves.entry(handle.0).or_insert_with(Vector::zeros) += pos.translation.vector - start;
+        if let Some(co) = self.world.get_mut(handle) { co.set_position(pos); }
+        self.log.trace(TraceKind::Done, id, pos.translation.vector, vel, pos.rotation.angle());
+        pos
+    }
+
+    // Projectiles are small and fast, so rather than relying on proximity at
+    // the end of the frame we sweep them along the whole move. They fly
+    // through units but stop at the first static or dynamic object. Every
+    // unit gone through and then the thing we stopped at are reported, in
+    // order, in projectile_events after the next update.
+    fn sweep_projectile(&mut self, handle: CollisionObjectHandle, id: u32, shape: &ShapeHandle<N>, mut pos: Isometry<N>, vel: Vector<N>) -> Isometry<N> {
+        let hits = self.sweep_hits(shape, &pos, vel, handle);
+        let is_unit = |w: &Self, h: CollisionObjectHandle| w.world.collision_object(h).unwrap().data().e_type == CGroup::Unit;
+        let stop = hits.iter().copied().find(|(_, h)| !is_unit(self, *h));
+        let t_stop = stop.map_or(1.0, |(t, _)| t);
+
+        // Every unit passed on the way to the stop gets hit. Units we were
+        // already overlapping don't count - it was probably just fired from
+        // one of them.
+        for &(t, other) in hits.iter().take_while(|(t, _)| *t <= t_stop) {
+            let co = self.world.collision_object(other).unwrap();
+            let unit = co.data().e_type == CGroup::Unit;
+            if unit && t == 0.0 && query::contact(&pos, shape.as_ref(), co.position(), co.shape().as_ref(), 0.0).is_some() {
+                continue;
+            }
+            let other_id = co.data().id;
+            let at = Isometry::from_parts(Translation::from(pos.translation.vector + vel * t), pos.rotation);
+            let (normal, point) = self.hit_contact(shape, &at, vel, other);
+            let kind = if unit { ProjectileEventKind::Hit } else { ProjectileEventKind::Stop };
+            self.swept_hits.push(ProjectileEvent { kind, projectile: id, other: other_id, point, normal: -normal });
+        }
+
+        let from = pos.translation.vector;
+        pos.append_translation_mut(&Translation::from(vel * t_stop));
+        push_sweep(&mut self.sweeps, from, pos.translation.vector);
+
+        // Knock dynamic bodies along the projectile's path.
+        if let Some((_, other)) = stop {
+            let point = self.hit_contact(shape, &pos, vel, other).1;
+            let r = self.contact_side(other, point).r;
+            if let Some(body) = self.body_mut(other) {
+                body.apply_impulse(vel * PROJECTILE_MASS, r);
+            }
+        }
+        pos
+    }
+
+    // Returns the entity id and new position of every object which was pushed
+    // out of something it was intersecting with, and of every dynamic body,
+    // launched projectile and knocked back unit which moved (and units carried
+    // by move_static).
+    pub fn update(&mut self) -> Vec<(u32, Isometry<N>)> {
+        let start = (self.clock)();
+        self.rebuild_navmeshes();
+        let mut moved_bodies = std::mem::take(&mut self.carried);
+        moved_bodies.extend(self.move_knocked_units());
+        moved_bodies.extend(self.integrate_bodies());
+        self.world.update();
+        self.collect_trigger_events();
+
+        // Everything try_move did this frame is now the last frame's sweeps.
+        std::mem::swap(&mut self.sweeps, &mut self.last_sweeps);
+        self.sweeps.clear();
+
+        moved_bodies.extend(self.step_projectiles());
+        moved_bodies.extend(self.solve_bodies());
+        self.moves.clear();
+
+        // And fix any objects which are actually intersecting. This should only
+        // be possible if the moving object is not a circle. Anything touching a
+        // dynamic body was dealt with by the solver.
+        let mut result = Vec::new();
+
+        let mut pairs = self.world.contact_pairs(true).filter_map(|(h1, h2, _a, manifold)| {
+            let c1 = self.world.collision_object(h1).unwrap();
+            let c2 = self.world.collision_object(h2).unwrap();
+            if c1.data().body.is_some() || c2.data().body.is_some() { return None; }
+
+            let (h, h_other, id, mut pos, m) = if c1.data().e_type == CGroup::Static {
+                (h2, h1, c2.data().id, *c2.position(), 1.0)
+            } else {
+                (h1, h2, c1.data().id, *c1.position(), -1.0)
+            };
+            let deepest = manifold.deepest_contact().unwrap();
+
+            // This is the delta we need to move by to make the object no longer
+            // colliding. This teleports it straight out - but it might be
+            // better to move it by a small amount each frame instead.
+            let depth = deepest.contact.depth;
+            // Leave things partway through a one way wall alone.
+            if self.passes_through(h_other, -m * deepest.contact.normal.into_inner(), depth) { return None; }
+            if depth > OVERLAP_SLOP {
+                let delta = (deepest.contact.depth + 0.01) * m * deepest.contact.normal.into_inner();
+                pos.append_translation_mut(&Translation::from(delta));
+                Some((h, h_other, id, pos))
+            } else { None }
+        }).collect::<Vec<_>>(); // I hate making this copy.
+
+        // If an object is in several walls the last fix wins, so the order
+        // matters.
+        if self.deterministic {
+            pairs.sort_by_key(|(h, h_other, _, _)| (h.0, h_other.0));
+        }
+
+        for (h, _h_other, id, new_pos) in pairs.iter() {
+            if let Some(co) = self.world.get_mut(*h) { co.set_position(*new_pos); }
+
+            // And tell the caller about the change.
+            result.push((*id, *new_pos));
+        }
+
+        moved_bodies.sort_by_key(|h| h.0);
+        moved_bodies.dedup();
+        for h in moved_bodies {
+            let co = self.world.collision_object(h).unwrap();
+            result.push((co.data().id, *co.position()));
+        }
+
+        // Last, so cones see where everything ended up.
+        self.update_vision();
+
+        self.finish_frame_stats((self.clock)() - start);
+
+        result
+    }
+
+    fn body(&self, handle: CollisionObjectHandle) -> Option<Body> {
+        self.world.collision_object(handle).and_then(|co| co.data().body)
+    }
+
+    fn body_mut(&mut self, handle: CollisionObjectHandle) -> Option<&mut Body> {
+        self.world.get_mut(handle).and_then(|co| co.data_mut().body.as_mut())
+    }
+
+    // How the object moves at a contact point, for the solver. Anything which
+    // isn't a body is immovable, and moves however try_move moved it.
+    fn contact_side(&self, handle: CollisionObjectHandle, point: Point<N>) -> ContactSide {
+        let co = self.world.collision_object(handle).unwrap();
+        let r = point - co.position().translation.vector;
+        match co.data().body {
+            Some(b) => ContactSide { vel: b.vel, ang_vel: b.ang_vel, inv_mass: b.inv_mass, inv_inertia: b.inv_inertia, r: r.coords },
+            None => ContactSide {
+                vel: self.moves.get(&handle.0).cloned().unwrap_or_else(Vector::zeros),
+                ang_vel: 0.0, inv_mass: 0.0, inv_inertia: 0.0, r: r.coords,
+            },
+        }
+    }
+
+    // Called when a unit runs into something. If it's a body, shove it so it
+    // moves at least as fast as the unit along the normal. Units are treated
+    // as infinitely strong.
+    fn push_body(&mut self, other: CollisionObjectHandle, point: Point<N>, normal: Vector<N>, vel: Vector<N>) {
+        let side = self.contact_side(other, point);
+        if let Some(body) = self.body_mut(other) {
+            let k = side.effective_inv_mass(normal);
+            let dv = normal.dot(&vel) - normal.dot(&side.point_vel());
+            if k > 0.0 && dv > 0.0 {
+                body.apply_impulse(normal * (dv / k), side.r);
+            }
+        }
+    }
+
+    // Units which are being knocked back keep moving even if try_move wasn't
+    // called for them this frame. Then everyone's knockback decays. Returns
+    // the units this moved.
+    fn move_knocked_units(&mut self) -> Vec<CollisionObjectHandle> {
+        let knocked = self.world.collision_objects()
+            .filter(|(_, co)| co.data().knockback != Vector::zeros())
+            .map(|(h, _)| h)
+            .collect::<Vec<_>>();
+
+        let mut moved = Vec::new();
+        for h in knocked {
+            if !self.moves.contains_key(&h.0) {
+                self.try_move(h.0, 0.0, 0.0, 0.0);
+                moved.push(h);
+            }
+            let decay = self.knockback_decay;
+            let k = &mut self.world.get_mut(h).unwrap().data_mut().knockback;
+            *k *= 1.0 - decay;
+            if v_d2(*k) < REST_SPEED * REST_SPEED { *k = Vector::zeros(); }
+        }
+        moved
+    }
+
+    // Knocks an object by (ix, iy). Units have no mass, so for them this is
+    // added straight on to their knockback velocity (per frame), which
+    // try_move adds to their movement until it decays away. Dynamic bodies
+    // get a normal impulse through their center. Does nothing to statics and
+    // projectiles.
+    pub fn apply_impulse(&mut self, handle: usize, ix: f64, iy: f64) {
+        let h = CollisionObjectHandle(handle);
+        let co = match self.world.get_mut(h) {
+            Some(co) => co,
+            None => return,
+        };
+        let impulse = Vector::new(ix, iy);
+        let data = co.data_mut();
+        match data.e_type {
+            CGroup::Unit => data.knockback += impulse,
+            CGroup::Dynamic => data.body.as_mut().unwrap().apply_impulse(impulse, Vector::zeros()),
+            CGroup::Static | CGroup::Projectile | CGroup::Trigger => {},
+        }
+    }
+
+    // A unit's current knockback velocity.
+    pub fn knockback(&self, handle: usize) -> Vector<N> {
+        self.world.collision_object(CollisionObjectHandle(handle)).map_or(Vector::zeros(), |co| co.data().knockback)
+    }
+
+    // Sets the fraction of their knockback units lose each frame. Defaults to
+    // 0.1.
+    pub fn set_knockback_decay(&mut self, decay: f64) {
+        self.knockback_decay = decay;
+    }
+
+    // Moves every body by its velocity and applies damping. Returns the
+    // bodies which moved.
+    fn integrate_bodies(&mut self) -> Vec<CollisionObjectHandle> {
+        let handles = self.world.collision_objects()
+            .filter(|(_, co)| co.data().body.is_some())
+            .map(|(h, _)| h)
+            .collect::<Vec<_>>();
+
+        let mut moved = Vec::new();
+        for h in handles {
+            let co = self.world.get_mut(h).unwrap();
+            let pos = *co.position();
+            let body = co.data_mut().body.as_mut().unwrap();
+            body.vel *= 1.0 - body.damping;
+            body.ang_vel *= 1.0 - body.damping;
+            if v_d2(body.vel) < REST_SPEED * REST_SPEED { body.vel = Vector::zeros(); }
+            if body.ang_vel.abs() < REST_SPEED { body.ang_vel = 0.0; }
+            if body.vel == Vector::zeros() && body.ang_vel == 0.0 { continue; }
+
+            let new_pos = Isometry::from_parts(
+                Translation::from(pos.translation.vector + body.vel),
+                UnitComplex::new(body.ang_vel) * pos.rotation
+            );
+            if let Some(co) = self.world.get_mut(h) { co.set_position(new_pos); }
+            moved.push(h);
+        }
+        moved
+    }
+
+    // Resolves every contact involving a body: bounce and friction impulses
+    // first, then push overlapping bodies apart. Returns the bodies which were
+    // pushed.
+    fn solve_bodies(&mut self) -> Vec<CollisionObjectHandle> {
+        let mut contacts = Vec::new();
+        let mut overlaps = Vec::new();
+        for (h1, h2, _alg, manifold) in self.world.contact_pairs(true) {
+            let b1 = self.body(h1);
+            let b2 = self.body(h2);
+            let (restitution, friction) = match (b1, b2) {
+                (None, None) => continue,
+                (Some(b), None) | (None, Some(b)) => (b.restitution, b.friction),
+                (Some(a), Some(b)) => (a.restitution.max(b.restitution), (a.friction * b.friction).sqrt()),
+            };
+            for tracked in manifold.contacts() {
+                let c = &tracked.contact;
+                if c.depth <= 0.0 { continue; }
+                contacts.push((h1, h2, midpoint(&c.world1, &c.world2), c.normal.into_inner(), restitution, friction));
+            }
+            if let Some(deepest) = manifold.deepest_contact() {
+                if deepest.contact.depth > PENETRATION_SLOP {
+                    overlaps.push((h1, h2, deepest.contact.normal.into_inner(), deepest.contact.depth));
+                }
+            }
+        }
+        if self.deterministic {
+            // ncollide keeps contacts from one frame to the next, so the
+            // order within a manifold depends on history. Sort by position.
+            contacts.sort_by(|a, b| (a.0 .0, a.1 .0).cmp(&(b.0 .0, b.1 .0))
+                .then(a.2.x.partial_cmp(&b.2.x).unwrap_or(Ordering::Equal))
+                .then(a.2.y.partial_cmp(&b.2.y).unwrap_or(Ordering::Equal)));
+            overlaps.sort_by_key(|c| (c.0 .0, c.1 .0));
+        }
+
+        for _ in 0..SOLVER_ITERATIONS {
+            for &(h1, h2, point, normal, restitution, friction) in contacts.iter() {
+                let a = self.contact_side(h1, point);
+                let b = self.contact_side(h2, point);
+                if let Some(j) = contact_impulse(&a, &b, normal, restitution, friction) {
+                    if let Some(body) = self.body_mut(h1) { body.apply_impulse(-j, a.r); }
+                    if let Some(body) = self.body_mut(h2) { body.apply_impulse(j, b.r); }
+                }
+            }
+        }
+
+        let mut moved = Vec::new();
+        for (h1, h2, normal, depth) in overlaps {
+            let m1 = self.body(h1).map_or(0.0, |b| b.inv_mass);
+            let m2 = self.body(h2).map_or(0.0, |b| b.inv_mass);
+            if m1 + m2 <= 0.0 { continue; }
+            let shift = normal * ((depth - PENETRATION_SLOP) * PENETRATION_CORRECTION / (m1 + m2));
+            for (h, delta) in [(h1, -shift * m1), (h2, shift * m2)].iter() {
+                if *delta == Vector::zeros() { continue; }
+                let mut pos = *self.world.collision_object(*h).unwrap().position();
+                pos.append_translation_mut(&Translation::from(*delta));
+                if let Some(co) = self.world.get_mut(*h) { co.set_position(pos); }
+                moved.push(*h);
+            }
+        }
+        moved
+    }
+
+    // Every object a shape would hit moving from pos by motion, as (time,
+    // handle) pairs sorted by time. Times are fractions of motion. Only
+    // objects a projectile could hit are considered.
+    fn sweep_hits(&self, shape: &ShapeHandle<N>, pos: &Isometry<N>, motion: Vector<N>, skip: CollisionObjectHandle) -> Vec<(N, CollisionObjectHandle)> {
+        let mut aabb = bounding_volume::aabb(shape.as_ref(), pos);
+        let end = Isometry::from_parts(Translation::from(pos.translation.vector + motion), pos.rotation);
+        aabb.merge(&bounding_volume::aabb(shape.as_ref(), &end));
+
+        let mut hits = self.world.interferences_with_aabb(&aabb, &self.projectile_groups)
+            .filter(|(h, co)| *h != skip && co.data().e_type != CGroup::Trigger)
+            .filter_map(|(h, co)| {
+                toi(pos, &motion, shape.as_ref(), co.position(), &Vector::zeros(), co.shape().as_ref())
+                    .filter(|t| *t <= 1.0)
+                    .map(|t| (t, h))
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal).then(a.1 .0.cmp(&b.1 .0)));
+        hits
+    }
+
+    // The normal (pointing into the other object) and contact point for a
+    // shape which has just run into something while moving by motion. Same
+    // trick as try_move - nudge it forward a little so they overlap.
+    fn hit_contact(&self, shape: &ShapeHandle<N>, pos: &Isometry<N>, motion: Vector<N>, other: CollisionObjectHandle) -> (Vector<N>, Point<N>) {
+        let co = self.world.collision_object(other).unwrap();
+        query::contact(
+            &(Isometry::new(motion * 0.001, 0.0) * pos), shape.as_ref(),
+            co.position(), co.shape().as_ref(),
+            0.01
+        ).map_or((-motion.normalize(), Point::from(pos.translation.vector)), |c| (c.normal.into_inner(), c.world2))
+    }
+
+    // Moves every launched projectile along its velocity, bouncing it off
+    // whatever it hits. Returns the projectiles which moved.
+    fn step_projectiles(&mut self) -> Vec<CollisionObjectHandle> {
+        self.projectile_events = std::mem::take(&mut self.swept_hits);
+        let handles = self.world.collision_objects()
+            .filter(|(_, co)| co.data().ricochet.is_some())
+            .map(|(h, _)| h)
+            .collect::<Vec<_>>();
+
+        let mut moved = Vec::new();
+        for h in handles {
+            let co = self.world.collision_object(h).unwrap();
+            let id = co.data().id;
+            let shape = co.shape().clone();
+            let mut pos = *co.position();
+            let mut ricochet = co.data().ricochet;
+            let mut t_remaining = 1.0;
+            // Right after a bounce we're sitting next to the surface we
+            // bounced off, so ignore it if it's hit straight away.
+            let mut last_hit = None;
+
+            for _ in 0..MAX_BOUNCES_PER_FRAME {
+                let r = match ricochet.as_mut() {
+                    Some(r) if v_d2(r.vel) > EPSILON && t_remaining > EPSILON => r,
+                    _ => break,
+                };
+                let motion = r.vel * t_remaining;
+                let hit = self.sweep_hits(&shape, &pos, motion, h).into_iter()
+                    .find(|(t, other)| !(Some(*other) == last_hit && *t < EPSILON));
+
+                let (t, other) = match hit {
+                    None => {
+                        push_sweep(&mut self.last_sweeps, pos.translation.vector, pos.translation.vector + motion);
+                        pos.append_translation_mut(&Translation::from(motion));
+                        break;
+                    },
+                    Some(hit) => hit,
+                };
+
+                let from = pos.translation.vector;
+                pos.append_translation_mut(&Translation::from(motion * t));
+                push_sweep(&mut self.last_sweeps, from, pos.translation.vector);
+
+                let (normal, point) = self.hit_contact(&shape, &pos, motion, other);
+                let other_data = self.world.collision_object(other).unwrap().data();
+
+                let kind = if other_data.e_type == CGroup::Unit {
+                    ProjectileEventKind::Hit
+                } else if r.bounces >= r.max_bounces {
+                    ProjectileEventKind::Stop
+                } else {
+                    ProjectileEventKind::Bounce
+                };
+                self.projectile_events.push(ProjectileEvent { kind, projectile: id, other: other_data.id, point, normal: -normal });
+                if kind != ProjectileEventKind::Bounce {
+                    ricochet = None;
+                    break;
+                }
+
+                let before = r.vel;
+                r.bounces += 1;
+                r.reflect(normal);
+                let knock = (before - r.vel) * PROJECTILE_MASS;
+                pos.append_translation_mut(&Translation::from(-normal * BOUNCE_GAP));
+                t_remaining *= 1.0 - t;
+                last_hit = Some(other);
+
+                // Bounces push dynamic bodies around.
+                let r_other = self.contact_side(other, point).r;
+                if let Some(body) = self.body_mut(other) {
+                    body.apply_impulse(knock, r_other);
+                }
+            }
+
+            // Face the way we're now going.
+            if let Some(r) = ricochet {
+                if r.vel != Vector::zeros() {
+                    pos = Isometry::new(pos.translation.vector, r.vel.y.atan2(r.vel.x));
+                }
+            }
+            self.world.get_mut(h).unwrap().data_mut().ricochet = ricochet;
+            if let Some(co) = self.world.get_mut(h) { co.set_position(pos); }
+            moved.push(h);
+        }
+        moved
+    }
+
+    // Turns a projectile into one which update moves, at (vx, vy) per frame.
+    // It bounces off statics and dynamic bodies up to max_bounces times,
+    // keeping restitution of its speed into the surface each time, and stops
+    // at the first unit it hits. Launched projectiles don't get proximity
+    // events - see projectile_events instead. Does nothing to other groups.
+    pub fn launch(&mut self, handle: usize, vx: f64, vy: f64, restitution: f64, max_bounces: u32) {
+        let handle = CollisionObjectHandle(handle);
+        match self.world.get_mut(handle) {
+            Some(co) if co.data().e_type == CGroup::Projectile => {
+                co.data_mut().ricochet = Some(Ricochet { vel: Vector::new(vx, vy), restitution, max_bounces, bounces: 0 });
+            },
+            _ => return,
+        }
+        if let Some(co) = self.world.get_mut(handle) { co.set_collision_groups(self.launched_groups); }
+    }
+
+    // What projectiles ran into over the last frame: first the hits from
+    // try_move, in the order it was called, then bounces, unit hits and stops
+    // from launched projectiles in handle order.
+    pub fn projectile_events(&self) -> Vec<ProjectileEvent> {
+        self.projectile_events.clone()
+    }
+
+    // Sets the physical properties of a dynamic body. A mass of 0 makes it
+    // immovable. Damping is the fraction of its velocity it loses each frame.
+    // Does nothing if the object isn't in the Dynamic group.
+    pub fn set_body(&mut self, handle: usize, mass: f64, restitution: f64, friction: f64, damping: f64) {
+        let handle = CollisionObjectHandle(handle);
+        let shape = match self.world.collision_object(handle) {
+            Some(co) => co.shape().clone(),
+            None => return,
+        };
+        if let Some(body) = self.body_mut(handle) {
+            body.set_mass(&shape, mass);
+            body.restitution = restitution;
+            body.friction = friction;
+            body.damping = damping;
+        }
+    }
+
+    // Hits a dynamic body at the point (px, py) in world space. Hitting off
+    // center makes it spin.
+    pub fn apply_impulse_at(&mut self, handle: usize, ix: f64, iy: f64, px: f64, py: f64) {
+        let handle = CollisionObjectHandle(handle);
+        if self.body(handle).is_none() { return; }
+        let r = self.contact_side(handle, Point::new(px, py)).r;
+        self.body_mut(handle).unwrap().apply_impulse(Vector::new(ix, iy), r);
+    }
+
+    // The linear (per frame) and angular (radians per frame) velocity of a
+    // dynamic body.
+    pub fn body_velocity(&self, handle: usize) -> Option<(Vector<N>, N)> {
+        self.body(CollisionObjectHandle(handle)).map(|b| (b.vel, b.ang_vel))
+    }
+
+    fn finish_frame_stats(&mut self, update_ms: f64) {
+        let stats = &mut self.frame_stats;
+        for (_, co) in self.world.collision_objects() {
+            match co.data().e_type {
+                CGroup::Static => stats.static_objects += 1,
+                CGroup::Unit => stats.unit_objects += 1,
+                CGroup::Projectile => stats.projectile_objects += 1,
+                CGroup::Dynamic => stats.dynamic_objects += 1,
+                CGroup::Trigger => stats.trigger_objects += 1,
+            }
+        }
+        stats.broad_phase_pairs = self.world.interaction_pairs(false).count() as u32;
+        stats.contact_manifolds = self.world.contact_pairs(true).count() as u32;
+        stats.update_ms = update_ms;
+
+        self.last_stats = std::mem::take(&mut self.frame_stats);
+    }
+
+    // Stats for the last frame. See Stats.
+    pub fn stats(&self) -> Stats {
+        self.last_stats
+    }
+
+    // Set the clock used to time update. This should return milliseconds.
+    pub fn set_clock(&mut self, clock: fn() -> f64) {
+        self.clock = clock;
+    }
+
+    // This is edge triggering collisions. There are some instances where this
+    // isn't ideal - but I'll cross that bridge when I get to it.
+    pub fn proximity_events(&self) -> Vec<(u32, u32)> {
+        let is_trigger = |h| self.world.collision_object(h).is_some_and(|co| co.data().e_type == CGroup::Trigger);
+        let mut events = self.world.proximity_events().iter()
+            .filter(|evt| evt.new_status == query::Proximity::Intersecting)
+            // Those come through trigger_events instead.
+            .filter(|evt| !is_trigger(evt.collider1) && !is_trigger(evt.collider2))
+            // Lower handle first, whichever way round ncollide has them.
+            .map(|evt| if evt.collider1.0 <= evt.collider2.0 { (evt.collider1, evt.collider2) } else { (evt.collider2, evt.collider1) })
+            .collect::<Vec<_>>();
+
+        if self.deterministic {
+            events.sort_by_key(|(h1, h2)| (h1.0, h2.0));
+        }
+
+        events.iter().map(|(h1, h2)| {
+            let c1 = self.world.collision_object(*h1).unwrap();
+            let c2 = self.world.collision_object(*h2).unwrap();
+            (c1.data().id, c2.data().id)
+        }).collect()
+    }
+
+    // In deterministic mode every contact list and event list gets processed
+    // in handle order, so two worlds fed the same sequence of calls end up
+    // bit-identical - as long as they're running the same build. This is off
+    // by default because of the extra sorting.
+    //
+    // Rotations go through sin / cos / atan2 (here, and inside nalgebra and
+    // ncollide), which come from whatever libm the build was linked with.
+    // Clients all running the same wasm build agree, but a native build, or
+    // one for another platform, isn't guaranteed to match it in the last bit.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    // A hash of the id, group and exact position (and velocity, for dynamic
+    // bodies and launched projectiles) of every object, in handle order.
+    // Clients running in lockstep can compare these to detect desyncs.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        for (h, co) in self.world.collision_objects() {
+            let pos = co.position();
+            hasher.write_u64(h.0 as u64);
+            hasher.write_u32(co.data().id);
+            hasher.write_u32(co.data().e_type as u32);
+            hasher.write_f64(pos.translation.vector.x);
+            hasher.write_f64(pos.translation.vector.y);
+            hasher.write_f64(pos.rotation.re);
+            hasher.write_f64(pos.rotation.im);
+            if let Some(body) = co.data().body {
+                hasher.write_f64(body.vel.x);
+                hasher.write_f64(body.vel.y);
+                hasher.write_f64(body.ang_vel);
+            }
+            if co.data().e_type == CGroup::Unit {
+                hasher.write_f64(co.data().knockback.x);
+                hasher.write_f64(co.data().knockback.y);
+            }
+            if let Some(r) = co.data().ricochet {
+                hasher.write_f64(r.vel.x);
+                hasher.write_f64(r.vel.y);
+                hasher.write_u32(r.bounces);
+            }
+            if let Some(v) = &co.data().vision {
+                hasher.write_f64(v.half_angle);
+                hasher.write_f64(v.range);
+                for h in v.seen.iter() { hasher.write_u64(*h as u64); }
+            }
+            if let Some(n) = co.data().one_way {
+                hasher.write_f64(n.x);
+                hasher.write_f64(n.y);
+            }
+            let m = co.data().material;
+            hasher.write_u32(m.id);
+            hasher.write_f64(m.friction);
+            hasher.write_f64(m.slip);
+            hasher.write_u32(m.sound);
+        }
+        hasher.finish()
+    }
+
+    // Returns line segments describing everything the collision engine knows
+    // about - collider outlines, AABBs, the contacts found by the last update,
+    // the paths taken by try_move over the last frame, the navigation mesh
+    // and vision cones. See DebugLine for the buffer format.
+    pub fn debug_lines(&self) -> Vec<f64> {
+        let mut out = Vec::<f64>::new();
+
+        for (_, co) in self.world.collision_objects() {
+            let pos = co.position();
+            push_outline(&mut out, co.shape(), pos);
+
+            let aabb = bounding_volume::aabb(co.shape().as_ref(), pos);
+            let (mins, maxs) = (aabb.mins, aabb.maxs);
+            push_polygon(&mut out, DebugLine::Aabb, &[
+                mins,
+                Point::new(maxs.x, mins.y),
+                maxs,
+                Point::new(mins.x, maxs.y),
+            ]);
+        }
+
+        for (_h1, _h2, _alg, manifold) in self.world.contact_pairs(true) {
+            for tracked in manifold.contacts() {
+                let p = tracked.contact.world1;
+                let d = DEBUG_POINT_SIZE;
+                push_line(&mut out, DebugLine::ContactPoint, p + Vector::new(-d, -d), p + Vector::new(d, d));
+                push_line(&mut out, DebugLine::ContactPoint, p + Vector::new(-d, d), p + Vector::new(d, -d));
+                push_line(&mut out, DebugLine::ContactNormal, p, p + tracked.contact.normal.into_inner() * DEBUG_NORMAL_LENGTH);
+            }
+        }
+
+        for seg in self.last_sweeps.chunks(4) {
+            push_line(&mut out, DebugLine::Sweep, Point::new(seg[0], seg[1]), Point::new(seg[2], seg[3]));
+        }
+
+        for navmesh in self.navmeshes.iter() {
+            for (_, points) in navmesh.polygons() {
+                push_polygon(&mut out, DebugLine::NavMesh, points);
+            }
+        }
+
+        for (_, co) in self.world.collision_objects() {
+            if let Some(v) = &co.data().vision {
+                push_cone(&mut out, co.position(), v.half_angle, v.range);
+            }
+        }
+
+        out
+    }
+
+    pub fn print_events(&mut self) {
+        for evt in self.world.proximity_events() {
+            log_info!(self.log, "Prox event {:?}", evt);
+        }
+        for evt in self.world.contact_events() {
+            log_info!(self.log, "Contact event {:?}", evt);
+        }
+    }
+
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.log.level = level;
+    }
+
+    // Serializes every collision object into a compact binary blob. See
+    // snapshot.rs for the format. Contacts aren't saved - restore recomputes
+    // them - so take snapshots straight after update. Fails if an object has
+    // a shape which isn't one of the make_* ones.
+    pub fn snapshot(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut data = SnapshotData {
+            deterministic: self.deterministic,
+            knockback_decay: self.knockback_decay,
+            free_handles: self.free_handles.iter().map(|h| *h as u32).collect(),
+            ..SnapshotData::default()
+        };
+
+        for (h, co) in self.world.collision_objects() {
+            let pos = co.position();
+            data.objects.push(ObjectRecord {
+                handle: h.0 as u32,
+                id: co.data().id,
+                e_type: co.data().e_type,
+                shape: ShapeRecord::from_shape(co.shape(), co.data().id)?,
+                pos: [pos.translation.vector.x, pos.translation.vector.y, pos.rotation.re, pos.rotation.im],
+                groups: groups_to_masks(co.collision_groups()),
+                query: QueryRecord::from_query(co.query_type()),
+                body: co.data().body,
+                ricochet: co.data().ricochet,
+                knockback: co.data().knockback,
+                vision: co.data().vision.clone(),
+                one_way: co.data().one_way,
+                material: co.data().material,
+            });
+        }
+        data.slots = (data.objects.len() + data.free_handles.len()) as u32;
+
+        Ok(data.encode())
+    }
+
+    // Replaces everything in the world with the contents of a snapshot. Every
+    // object keeps its handle, and objects added afterwards get the same
+    // handles they would have in the world the snapshot came from. On error
+    // the world is left untouched.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let data = SnapshotData::decode(bytes)?;
+
+        // Build the shapes first so a bad one can't leave us half restored.
+        let shapes = data.objects.iter().map(|o| o.shape.to_shape()).collect::<Result<Vec<_>, _>>()?;
+
+        let mut slots = vec![None; data.slots as usize];
+        for (o, shape) in data.objects.iter().zip(shapes) {
+            slots[o.handle as usize] = Some((o, shape));
+        }
+
+        // ncollide doesn't let us pick handles, but it allocates them in
+        // order in a fresh world. So fill the holes with placeholders and
+        // remove those at the end, in the order they were freed originally.
+        let mut world = CollisionWorld::<_, _>::new(0.02);
+        for (i, slot) in slots.into_iter().enumerate() {
+            let handle = match slot {
+                Some((o, shape)) => {
+                    let [x, y, re, im] = o.pos;
+                    let pos = Isometry::from_parts(
+                        Translation::from(Vector::new(x, y)),
+                        UnitComplex::new_unchecked(Complex::new(re, im))
+                    );
+                    world.add(pos, shape, masks_to_groups(o.groups),
+                        o.query.to_query(), EntityData { id: o.id, e_type: o.e_type, body: o.body, ricochet: o.ricochet, knockback: o.knockback, vision: o.vision.clone(), one_way: o.one_way, material: o.material }).0
+                },
+                None => world.add(Isometry::identity(), make_circle(1.0), self.static_groups,
+                    GeometricQueryType::Proximity(0.0), EntityData { id: 0, e_type: CGroup::Static, body: None, ricochet: None, knockback: Vector::zeros(), vision: None, one_way: None, material: Material::default() }).0,
+            };
+            assert_eq!(handle.0, i);
+        }
+        let free_handles = data.free_handles.iter().map(|h| *h as usize).collect::<Vec<_>>();
+        world.remove(&free_handles.iter().map(|h| CollisionObjectHandle(*h)).collect::<Vec<_>>());
+
+        // Run the pipeline once so try_move has contacts to work with.
+        world.update();
+
+        self.world = world;
+        self.free_handles = free_handles;
+        self.deterministic = data.deterministic;
+        self.knockback_decay = data.knockback_decay;
+        // Navmeshes aren't saved - they're rebuilt from the restored statics.
+        let radii = self.navmeshes.drain(..).map(|n| n.agent_radius()).collect::<Vec<_>>();
+        for radius in radii {
+            self.enable_navmesh(radius);
+        }
+        // And the flow field gets rasterized again next time it's built.
+        self.statics_version += 1;
+        self.moves.clear();
+        self.carried.clear();
+        self.swept_hits.clear();
+        self.projectile_events.clear();
+        self.vision_events.clear();
+        self.trigger_events.clear();
+        self.sweeps.clear();
+        self.last_sweeps.clear();
+        Ok(())
+    }
+
+    // Returns and clears the buffered log messages.
+    pub fn drain_log(&mut self) -> Vec<(LogLevel, String)> {
+        std::mem::take(&mut self.log.lines)
+    }
+
+    // Returns and clears the buffered trace records. See TraceKind.
+    pub fn drain_trace(&mut self) -> Vec<f64> {
+        std::mem::take(&mut self.log.trace)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,125 @@
+// Tests for steering units around each other with avoid.
+use collide_wasm::*;
+use ncollide2d::math::{Point, Vector};
+mod common;
+use common::assert_near;
+
+// Walks units of radius 0.5 from each start to each goal at speed, avoiding
+// each other. Returns the closest any two got, and where they ended up.
+fn walk(world: &mut World, units: &[(Point<f64>, Point<f64>)], speed: f64, frames: usize) -> (f64, Vec<Point<f64>>) {
+    let handles = units.iter().enumerate()
+        .map(|(i, (start, _))| world.add(i as u32 + 1, start.x, start.y, 0.0, make_circle(0.5), CGroup::Unit, speed))
+        .collect::<Vec<_>>();
+    let mut positions = units.iter().map(|(start, _)| *start).collect::<Vec<_>>();
+    let mut closest = f64::INFINITY;
+
+    for _ in 0..frames {
+        let preferred = handles.iter().zip(units.iter().zip(positions.iter()))
+            .map(|(&h, ((_, goal), pos))| {
+                let to_goal = goal - pos;
+                (h, if to_goal.norm() > speed { to_goal.normalize() * speed } else { to_goal })
+            })
+            .collect::<Vec<_>>();
+        let velocities = world.avoid(&preferred);
+        for (i, (&h, v)) in handles.iter().zip(velocities).enumerate() {
+            positions[i] = Point::from(world.try_move(h, v.x, v.y, 0.0).translation.vector);
+        }
+        world.update();
+
+        for i in 0..positions.len() {
+            for j in i + 1..positions.len() {
+                closest = closest.min((positions[i] - positions[j]).norm());
+            }
+        }
+    }
+    (closest, positions)
+}
+
+#[test]
+fn nothing_nearby_leaves_velocities_alone() {
+    let mut world = World::new();
+    let a = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let b = world.add(2, 50.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let wall = world.add(3, 0.0, 50.0, 0.0, make_box(1.0, 1.0), CGroup::Static, 0.0);
+    let v = Vector::new(0.1, 0.05);
+    assert_eq!(world.avoid(&[(a, v), (b, -v), (wall, v), (99, v)]), vec![v, -v, v, v]);
+}
+
+#[test]
+fn head_on_units_pass_each_other() {
+    let mut world = World::new();
+    let units = [
+        (Point::new(0.0, 0.0), Point::new(10.0, 0.0)),
+        (Point::new(10.0, 0.0), Point::new(0.0, 0.0)),
+    ];
+    let (closest, end) = walk(&mut world, &units, 0.1, 200);
+    assert!(closest > 0.98, "got within {}", closest);
+    assert!((end[0] - units[0].1).norm() < 1e-6, "ended at {:?}", end);
+    assert!((end[1] - units[1].1).norm() < 1e-6, "ended at {:?}", end);
+}
+
+#[test]
+fn crowds_swap_sides() {
+    // Eight units on a circle, each heading for the opposite side.
+    let mut world = World::new();
+    let units = (0..8).map(|i| {
+        let a = i as f64 * std::f64::consts::PI / 4.0;
+        let p = Vector::new(a.cos(), a.sin()) * 5.0;
+        (Point::from(p), Point::from(-p))
+    }).collect::<Vec<_>>();
+    let (closest, end) = walk(&mut world, &units, 0.1, 400);
+    assert!(closest > 0.95, "got within {}", closest);
+    for (p, (_, goal)) in end.iter().zip(units.iter()) {
+        assert!((p - goal).norm() < 0.1, "ended at {:?}, not {:?}", p, goal);
+    }
+}
+
+#[test]
+fn units_go_around_ones_standing_still() {
+    // The one in the way isn't avoiding, so the other has to do all of it.
+    let mut world = World::new();
+    let mover = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let still = world.add(2, 5.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let mut closest = f64::INFINITY;
+    let mut pos = Point::origin();
+    for _ in 0..150 {
+        let to_goal = Point::new(10.0, 0.0) - pos;
+        let pref = if to_goal.norm() > 0.1 { to_goal.normalize() * 0.1 } else { to_goal };
+        let v = world.avoid(&[(mover, pref)])[0];
+        assert!(v.norm() <= 0.1 + 1e-9);
+        pos = Point::from(world.try_move(mover, v.x, v.y, 0.0).translation.vector);
+        world.update();
+        closest = closest.min((pos - Point::new(5.0, 0.0)).norm());
+    }
+    assert!(closest > 0.99, "got within {}", closest);
+    assert!((pos - Point::new(10.0, 0.0)).norm() < 1e-6, "ended at {:?}", pos);
+    assert_eq!(world.try_move(still, 0.0, 0.0, 0.0).translation.x, 5.0);
+}
+
+#[test]
+fn units_slow_down_for_statics() {
+    let mut world = World::new();
+    // A wall with its face at x = 2.5.
+    world.add(1, 3.0, 0.0, 0.0, make_box(1.0, 10.0), CGroup::Static, 0.0);
+    let unit = world.add(2, 1.8, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.2);
+    world.update();
+
+    // 0.2 from the wall, which it can close at a fifth of per frame. Sliding
+    // along it is fine.
+    let v = world.avoid(&[(unit, Vector::new(0.2, 0.1))])[0];
+    assert_near(v, Vector::new(0.04, 0.1), 1e-6);
+    assert_eq!(world.avoid(&[(unit, Vector::new(-0.2, 0.1))])[0], Vector::new(-0.2, 0.1));
+    world.update();
+    assert_eq!(world.stats().avoidance_neighbours, 2);
+}
+
+#[test]
+fn avoidance_is_deterministic() {
+    let units = (0..6).map(|i| {
+        let y = i as f64 * 1.3;
+        (Point::new(0.0, y), Point::new(8.0, 6.5 - y))
+    }).collect::<Vec<_>>();
+    let (_, first) = walk(&mut World::new(), &units, 0.1, 100);
+    let (_, second) = walk(&mut World::new(), &units, 0.1, 100);
+    assert_eq!(first, second);
+}
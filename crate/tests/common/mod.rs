@@ -0,0 +1,76 @@
+// Helpers shared by the integration tests. Each test file is built as its own
+// crate and only uses some of these, hence the allow.
+#![allow(dead_code)]
+
+use std::fmt::Debug;
+use collide_wasm::*;
+use ncollide2d::math::{Point, Vector};
+
+// Things assert_near can compare.
+pub trait Near: Debug {
+    fn distance(&self, other: &Self) -> f64;
+}
+
+impl Near for f64 {
+    fn distance(&self, other: &f64) -> f64 {
+        (self - other).abs()
+    }
+}
+
+impl Near for Vector<f64> {
+    fn distance(&self, other: &Vector<f64>) -> f64 {
+        (self - other).norm()
+    }
+}
+
+// (x, y) pairs are near if both parts are.
+impl Near for (f64, f64) {
+    fn distance(&self, other: &(f64, f64)) -> f64 {
+        (self.0 - other.0).abs().max((self.1 - other.1).abs())
+    }
+}
+
+pub fn assert_near<T: Near>(actual: T, expected: T, tolerance: f64) {
+    assert!(actual.distance(&expected) <= tolerance, "expected {:?} to be within {} of {:?}", actual, tolerance, expected);
+}
+
+// Signed area, positive if the points go anticlockwise.
+pub fn area(points: &[Point<f64>]) -> f64 {
+    (0..points.len()).map(|i| {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        a.x * b.y - b.x * a.y
+    }).sum::<f64>() / 2.0
+}
+
+// A room from (0, 0) to (20, 10), split down the middle at x = 10 by a wall
+// with a gap from y = 4 to 6. It straddles the navmesh tile border at x = 16.
+pub fn rooms() -> World {
+    let mut world = World::new();
+    world.add(1, -0.5, 5.0, 0.0, make_box(1.0, 12.0), CGroup::Static, 0.0);
+    world.add(2, 20.5, 5.0, 0.0, make_box(1.0, 12.0), CGroup::Static, 0.0);
+    world.add(3, 10.0, -0.5, 0.0, make_box(22.0, 1.0), CGroup::Static, 0.0);
+    world.add(4, 10.0, 10.5, 0.0, make_box(22.0, 1.0), CGroup::Static, 0.0);
+    world.add(5, 10.0, 2.0, 0.0, make_box(1.0, 4.0), CGroup::Static, 0.0);
+    world.add(6, 10.0, 8.0, 0.0, make_box(1.0, 4.0), CGroup::Static, 0.0);
+    world
+}
+// Restores a snapshot of world into a new one, checks it comes back the same
+// (and snapshots to the same bytes), then runs both for frames updates
+// checking they agree on everything update reports. Returns the copy.
+pub fn assert_round_trips(world: &mut World, frames: usize) -> World {
+    let bytes = world.snapshot().unwrap();
+    let mut restored = World::new();
+    restored.restore(&bytes).unwrap();
+    assert_eq!(restored.state_hash(), world.state_hash());
+    assert_eq!(restored.snapshot().unwrap(), bytes);
+
+    for _ in 0..frames {
+        assert_eq!(restored.update(), world.update());
+        assert_eq!(restored.proximity_events(), world.proximity_events());
+        assert_eq!(restored.projectile_events(), world.projectile_events());
+        assert_eq!(restored.trigger_events(), world.trigger_events());
+        assert_eq!(restored.vision_events(), world.vision_events());
+    }
+    assert_eq!(restored.state_hash(), world.state_hash());
+    restored
+}
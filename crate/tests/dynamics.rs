@@ -0,0 +1,168 @@
+// Tests for the dynamic body solver: pushing, bouncing, projectile hits, and
+// that it plays nicely with deterministic mode and snapshots.
+use collide_wasm::*;
+use ncollide2d::math::{Isometry, Vector};
+mod common;
+use common::assert_round_trips;
+
+fn step(world: &mut World, frames: usize) -> Vec<(u32, Isometry<f64>)> {
+    let mut moved = Vec::new();
+    for _ in 0..frames {
+        moved = world.update();
+    }
+    moved
+}
+
+fn x_of(world: &mut World, handle: usize) -> f64 {
+    world.try_move(handle, 0.0, 0.0, 0.0).translation.x
+}
+
+#[test]
+fn units_push_crates() {
+    let mut world = World::new();
+    let unit = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let crate_ = world.add(2, 1.6, 0.0, 0.0, make_box(1.0, 1.0), CGroup::Dynamic, 0.1);
+
+    let mut reported = false;
+    for _ in 0..40 {
+        reported |= world.update().iter().any(|(id, _)| *id == 2);
+        let pos = world.try_move(unit, 0.1, 0.0, 0.0).translation.x;
+        // The unit never ends up inside the crate.
+        assert!(pos - 0.5 <= x_of(&mut world, crate_) - 0.5 + 1e-6);
+    }
+    assert!(reported);
+
+    // The unit would have got to x = 4 by itself. It's held up a little by
+    // the crate, which ends up in front of it.
+    let unit_x = x_of(&mut world, unit);
+    let crate_x = x_of(&mut world, crate_);
+    assert!(unit_x > 3.0, "unit at {}", unit_x);
+    assert!(crate_x > unit_x + 0.99, "crate at {}, unit at {}", crate_x, unit_x);
+
+    // Pushing it square on doesn't make it spin.
+    let (_, spin) = world.body_velocity(crate_).unwrap();
+    assert!(spin.abs() < 1e-9);
+}
+
+#[test]
+fn bodies_bounce_off_walls() {
+    let bounce = |restitution: f64| {
+        let mut world = World::new();
+        world.add(1, 3.0, 0.0, 0.0, make_box(1.0, 10.0), CGroup::Static, 0.0);
+        let ball = world.add(2, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Dynamic, 0.1);
+        world.set_body(ball, 1.0, restitution, 0.0, 0.0);
+        world.apply_impulse_at(ball, 0.1, 0.0, 0.0, 0.0);
+
+        for _ in 0..40 {
+            world.update();
+            // Never sinks more than a frame's movement into the wall.
+            assert!(x_of(&mut world, ball) < 2.0 + 0.1);
+        }
+        world.body_velocity(ball).unwrap().0.x
+    };
+
+    assert!((bounce(1.0) + 0.1).abs() < 1e-6);
+    assert!((bounce(0.5) + 0.05).abs() < 1e-6);
+    assert!(bounce(0.0).abs() < 1e-6);
+}
+
+#[test]
+fn friction_and_damping_slow_bodies() {
+    let mut world = World::new();
+    let a = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Dynamic, 0.1);
+    let b = world.add(2, 0.0, 5.0, 0.0, make_circle(0.5), CGroup::Dynamic, 0.1);
+    world.set_body(b, 1.0, 0.2, 0.5, 0.0);
+    world.apply_impulse_at(a, 0.5, 0.0, 0.0, 0.0);
+    world.apply_impulse_at(b, 0.1, 0.0, 0.0, 0.0);
+
+    // The default damping brings a to rest eventually.
+    step(&mut world, 400);
+    assert_eq!(world.body_velocity(a).unwrap().0, Vector::zeros());
+    // b isn't damped, so it keeps going.
+    assert!((world.body_velocity(b).unwrap().0.x - 0.1).abs() < 1e-9);
+
+    // Sliding along a wall, friction slows a body down and sets it rolling.
+    let mut world = World::new();
+    world.add(1, 0.0, 1.0, 0.0, make_box(100.0, 1.0), CGroup::Static, 0.0);
+    let ball = world.add(2, 0.0, 0.0, 0.0, make_circle(0.51), CGroup::Dynamic, 0.1);
+    world.set_body(ball, 1.0, 0.0, 0.5, 0.0);
+    world.apply_impulse_at(ball, 0.1, 0.02, 0.0, 0.0);
+    step(&mut world, 10);
+    let (vel, spin) = world.body_velocity(ball).unwrap();
+    assert!(vel.x < 0.1 && vel.x > 0.0);
+    assert!(spin != 0.0);
+}
+
+#[test]
+fn projectiles_knock_bodies() {
+    let mut world = World::new();
+    let barrel = world.add(1, 2.0, 0.0, 0.0, make_circle(0.5), CGroup::Dynamic, 0.1);
+    let bullet = world.add(2, 0.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.3);
+
+    world.update();
+    let mut hit = false;
+    for _ in 0..10 {
+        world.try_move(bullet, 0.3, 0.0, 0.0);
+        world.update();
+        hit |= world.projectile_events().iter().any(|e| e.other == 1);
+        if hit { break; }
+    }
+    assert!(hit);
+
+    let (vel, spin) = world.body_velocity(barrel).unwrap();
+    assert!(vel.x > 0.0);
+    assert!(vel.y.abs() < 1e-9 && spin.abs() < 1e-9);
+}
+
+#[test]
+fn off_center_hits_spin() {
+    let mut world = World::new();
+    let crate_ = world.add(1, 0.0, 0.0, 0.0, make_box(1.0, 1.0), CGroup::Dynamic, 0.1);
+    world.apply_impulse_at(crate_, 0.0, 0.1, 0.5, 0.0);
+    let (vel, spin) = world.body_velocity(crate_).unwrap();
+    assert!(vel.y > 0.0);
+    assert!(spin > 0.0);
+
+    let moved = step(&mut world, 5);
+    assert_eq!(moved.len(), 1);
+    assert!(moved[0].1.rotation.angle() > 0.0);
+
+    // Statics and units aren't bodies, and have nothing to hit.
+    let wall = world.add(2, 5.0, 0.0, 0.0, make_box(1.0, 1.0), CGroup::Static, 0.0);
+    world.apply_impulse_at(wall, 1.0, 0.0, 5.0, 0.0);
+    assert_eq!(world.body_velocity(wall), None);
+}
+
+// A pile of bodies knocking into each other and the walls of a box.
+fn pile() -> World {
+    let mut world = World::new();
+    world.set_deterministic(true);
+    world.add(1, 0.0, -5.0, 0.0, make_box(12.0, 1.0), CGroup::Static, 0.0);
+    world.add(2, 0.0, 5.0, 0.0, make_box(12.0, 1.0), CGroup::Static, 0.0);
+    world.add(3, -5.0, 0.0, 0.0, make_box(1.0, 12.0), CGroup::Static, 0.0);
+    world.add(4, 5.0, 0.0, 0.0, make_box(1.0, 12.0), CGroup::Static, 0.0);
+    for i in 0..6 {
+        let x = -3.0 + i as f64 * 1.2;
+        let h = world.add(10 + i, x, (i % 3) as f64 - 1.0, i as f64 * 0.3, make_box(0.8, 0.8), CGroup::Dynamic, 0.1);
+        world.apply_impulse_at(h, 0.05 * (3.0 - i as f64), 0.03, x + 0.2, 0.0);
+    }
+    world
+}
+
+#[test]
+fn bodies_are_deterministic() {
+    let mut a = pile();
+    let mut b = pile();
+    for _ in 0..60 {
+        assert_eq!(a.update(), b.update());
+        assert_eq!(a.state_hash(), b.state_hash());
+    }
+}
+
+#[test]
+fn bodies_survive_snapshots() {
+    let mut world = pile();
+    step(&mut world, 20);
+    // Still moving, and moving the same way.
+    assert_round_trips(&mut world, 20);
+}
@@ -0,0 +1,102 @@
+// Tests for steering with the flow field.
+use collide_wasm::*;
+use ncollide2d::math::{Point, Vector};
+mod common;
+use common::rooms;
+
+// Follows the field in small steps, checking it never walks into the wall.
+// Returns where it ended up.
+fn follow(world: &World, mut p: Point<f64>, steps: usize) -> Point<f64> {
+    for _ in 0..steps {
+        p += world.flow_direction(p.x, p.y) * 0.1;
+        let in_wall = p.x > 9.5 && p.x < 10.5 && (p.y < 4.0 || p.y > 6.0);
+        assert!(!in_wall, "walked into the wall at {:?}", p);
+    }
+    p
+}
+
+#[test]
+fn open_ground_points_at_the_target() {
+    let mut world = World::new();
+    assert_eq!(world.flow_direction(0.0, 0.0), Vector::zeros());
+
+    world.build_flow_field(10.0, 0.0, 1.0);
+    let d = world.flow_direction(0.0, 0.0);
+    assert!((d.x - 1.0).abs() < 1e-9 && d.y.abs() < 1e-9, "{:?}", d);
+    let d = world.flow_direction(-50.0, 50.0);
+    assert!((d - Vector::new(1.0, -0.833333).normalize()).norm() < 1e-3, "{:?}", d);
+    assert_eq!(world.flow_direction(10.0, 0.0), Vector::zeros());
+}
+
+#[test]
+fn flows_through_gaps() {
+    let mut world = rooms();
+    world.build_flow_field(18.0, 2.0, 0.5);
+
+    // Straight at the wall would be (1, 0). It heads for the gap instead.
+    let d = world.flow_direction(2.0, 2.0);
+    assert!(d.y > 0.1, "{:?}", d);
+
+    let end = follow(&world, Point::new(2.0, 2.0), 300);
+    assert!((end - Point::new(18.0, 2.0)).norm() < 0.1, "ended up at {:?}", end);
+
+    // Starting up against a wall works too.
+    let end = follow(&world, Point::new(9.45, 1.0), 300);
+    assert!((end - Point::new(18.0, 2.0)).norm() < 0.1, "ended up at {:?}", end);
+}
+
+#[test]
+fn no_direction_when_unreachable() {
+    let mut world = rooms();
+    world.add(7, 10.0, 5.0, 0.0, make_box(1.0, 2.0), CGroup::Static, 0.0);
+    world.build_flow_field(18.0, 2.0, 0.5);
+    assert_eq!(world.flow_direction(2.0, 2.0), Vector::zeros());
+    assert!(world.flow_direction(15.0, 8.0).norm() > 0.99);
+}
+
+#[test]
+fn bad_targets_and_cells_are_ignored() {
+    let mut world = rooms();
+    world.build_flow_field(18.0, 2.0, f64::INFINITY);
+    assert_eq!(world.flow_direction(2.0, 2.0), Vector::zeros());
+
+    // A bad target leaves the old field alone.
+    world.build_flow_field(18.0, 2.0, 0.5);
+    world.build_flow_field(f64::NAN, 2.0, 0.5);
+    world.build_flow_field(18.0, f64::INFINITY, 0.5);
+    let end = follow(&world, Point::new(2.0, 2.0), 300);
+    assert!((end - Point::new(18.0, 2.0)).norm() < 0.1, "ended up at {:?}", end);
+}
+
+#[test]
+fn moving_the_target_is_cheap() {
+    let mut world = rooms();
+    world.build_flow_field(18.0, 2.0, 0.5);
+    world.update();
+    assert_eq!(world.stats().flow_field_rebuilds, 1);
+    assert!(world.stats().flow_field_cells_visited > 0);
+
+    // Somewhere else in the same cell doesn't need a new search.
+    world.build_flow_field(18.1, 2.1, 0.5);
+    world.update();
+    assert_eq!(world.stats().flow_field_rebuilds, 0);
+    assert_eq!(world.stats().flow_field_cells_visited, 0);
+    let d = world.flow_direction(18.0, 2.0);
+    assert!((d - Vector::new(1.0, 1.0).normalize()).norm() < 1e-9);
+
+    // Another cell needs a search, but not the statics again.
+    world.build_flow_field(5.0, 5.0, 0.5);
+    world.update();
+    assert_eq!(world.stats().flow_field_rebuilds, 0);
+    assert!(world.stats().flow_field_cells_visited > 0);
+    // (Directions point at the middle of the next cell, so this is in the
+    // middle of one.)
+    assert!(world.flow_direction(15.25, 5.25).x < -0.99);
+
+    // Until the statics change, or the cells do.
+    world.add(7, 5.0, 8.0, 0.0, make_box(1.0, 1.0), CGroup::Static, 0.0);
+    world.build_flow_field(5.0, 5.0, 0.5);
+    world.build_flow_field(5.0, 5.0, 1.0);
+    world.update();
+    assert_eq!(world.stats().flow_field_rebuilds, 2);
+}
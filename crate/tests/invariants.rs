@@ -0,0 +1,119 @@
+// Randomized tests for the movement invariants we rely on. The scenes here are
+// random static layouts with a unit wandering around in them.
+use collide_wasm::*;
+use ncollide2d::math::{Isometry, Vector};
+use ncollide2d::query;
+use ncollide2d::shape::ShapeHandle;
+use proptest::prelude::*;
+
+// update pushes objects out to 0.01 clear, but tiny overlaps can creep in from
+// the narrow phase margins.
+const TOLERANCE: f64 = 0.001;
+
+#[derive(Debug, Clone)]
+enum ShapeDesc {
+    Circle(f64),
+    Box(f64, f64),
+}
+
+impl ShapeDesc {
+    fn make(&self) -> ShapeHandle<f64> {
+        match *self {
+            ShapeDesc::Circle(r) => make_circle(r),
+            ShapeDesc::Box(w, h) => make_box(w, h),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Static {
+    x: f64, y: f64, a: f64,
+    shape: ShapeDesc,
+}
+
+fn shape_desc(min: f64, max: f64) -> impl Strategy<Value = ShapeDesc> {
+    prop_oneof![
+        (min..max).prop_map(ShapeDesc::Circle),
+        (min..max * 2.0, min..max * 2.0).prop_map(|(w, h)| ShapeDesc::Box(w, h)),
+    ]
+}
+
+fn statics() -> impl Strategy<Value = Vec<Static>> {
+    prop::collection::vec(
+        (-6.0..6.0f64, -6.0..6.0f64, -3.2..3.2f64, shape_desc(0.1, 2.0))
+            .prop_map(|(x, y, a, shape)| Static { x, y, a, shape }),
+        0..10
+    )
+}
+
+// Per-frame velocities, as fractions of max_speed.
+fn velocities(frames: usize) -> impl Strategy<Value = Vec<(f64, f64, f64)>> {
+    prop::collection::vec((-1.0..1.0f64, -1.0..1.0f64, -0.2..0.2f64), frames)
+}
+
+fn overlaps(shape1: &ShapeHandle<f64>, pos1: &Isometry<f64>, shape2: &ShapeHandle<f64>, pos2: &Isometry<f64>, tolerance: f64) -> bool {
+    match query::contact(pos1, shape1.as_ref(), pos2, shape2.as_ref(), 0.0) {
+        Some(c) => c.depth > tolerance,
+        None => false,
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(200))]
+
+    #[test]
+    fn movement_invariants(
+        statics in statics(),
+        unit_shape in shape_desc(0.2, 0.6),
+        max_speed in 0.01..0.5f64,
+        rotate in any::<bool>(),
+        vels in velocities(60),
+    ) {
+        let mut world = World::new();
+        let placed = statics.iter().enumerate().map(|(i, s)| {
+            let shape = s.shape.make();
+            world.add(i as u32 + 1, s.x, s.y, s.a, shape.clone(), CGroup::Static, 0.0);
+            (shape, Isometry::new(Vector::new(s.x, s.y), s.a))
+        }).collect::<Vec<_>>();
+
+        let unit_id = 1000;
+        let shape = unit_shape.make();
+        let mut pos = Isometry::new(Vector::new(-9.0, -9.0), 0.0);
+        // Don't start the unit off inside anything.
+        prop_assume!(placed.iter().all(|(s, p)| !overlaps(&shape, &pos, s, p, 0.0)));
+        let handle = world.add(unit_id, pos.translation.x, pos.translation.y, 0.0, shape.clone(), CGroup::Unit, max_speed);
+        world.update();
+
+        for (fx, fy, fa) in vels {
+            let (vx, vy, va) = (fx * max_speed, fy * max_speed, if rotate { fa } else { 0.0 });
+
+            // Steer toward the middle of the scene so we actually hit things.
+            let to_center = -pos.translation.vector;
+            let (vx, vy) = if to_center.norm() > 4.0 {
+                let d = to_center.normalize() * max_speed;
+                (d.x, d.y)
+            } else { (vx, vy) };
+
+            let new_pos = world.try_move(handle, vx, vy, va);
+            let t = new_pos.translation.vector;
+            prop_assert!(t.x.is_finite() && t.y.is_finite() && new_pos.rotation.angle().is_finite(),
+                "try_move returned {:?}", new_pos);
+
+            let moved = (t - pos.translation.vector).norm();
+            let requested = Vector::new(vx, vy).norm();
+            prop_assert!(moved <= requested + 1e-9, "moved {} but only asked for {}", moved, requested);
+            pos = new_pos;
+
+            for (id, fixed) in world.update() {
+                if id == unit_id { pos = fixed; }
+            }
+
+            // Turning is swept too, so boxes which rotate can't end up in
+            // walls either.
+            for (s, p) in placed.iter() {
+                prop_assert!(!overlaps(&shape, &pos, s, p, TOLERANCE),
+                    "unit {:?} at {:?} is inside static {:?} at {:?}", unit_shape, pos, s.as_ref().aabb(p), p);
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+// Tests for knocking units back with apply_impulse.
+use collide_wasm::*;
+use ncollide2d::math::Vector;
+mod common;
+use common::{assert_near, assert_round_trips};
+
+#[test]
+fn knockback_decays() {
+    let mut world = World::new();
+    let unit = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.set_knockback_decay(0.5);
+    world.apply_impulse(unit, 0.4, 0.0);
+    world.apply_impulse(unit, 0.4, 0.0);
+    assert_eq!(world.knockback(unit), Vector::new(0.8, 0.0));
+
+    // Nobody calls try_move, but the unit keeps moving and gets reported.
+    let moved = world.update();
+    assert_eq!(moved.len(), 1);
+    assert_eq!(moved[0].0, 1);
+    assert_near(moved[0].1.translation.x, 0.8, 1e-6);
+    assert_near(world.knockback(unit).x, 0.4, 1e-6);
+
+    // It halves each frame, so it would tend to 1.6, but try_move ignores
+    // tiny movements so it falls a little short.
+    for _ in 0..40 { world.update(); }
+    assert_eq!(world.knockback(unit), Vector::zeros());
+    let x = world.try_move(unit, 0.0, 0.0, 0.0).translation.x;
+    assert!(x < 1.6 && x > 1.59, "unit at {}", x);
+    assert!(world.update().is_empty());
+}
+
+#[test]
+fn knockback_adds_to_movement() {
+    let mut world = World::new();
+    let unit = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.apply_impulse(unit, 0.0, 0.2);
+    let pos = world.try_move(unit, 0.1, 0.0, 0.0).translation.vector;
+    assert_near(pos.x, 0.1, 1e-6);
+    assert_near(pos.y, 0.2, 1e-6);
+
+    // The unit already moved this frame, so update doesn't move it again.
+    assert!(world.update().is_empty());
+    assert_near(world.knockback(unit).y, 0.18, 1e-6);
+}
+
+#[test]
+fn knockback_slides_along_walls() {
+    let mut world = World::new();
+    world.add(1, 0.0, 1.5, 0.0, make_box(20.0, 1.0), CGroup::Static, 0.0);
+    let unit = world.add(2, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.3);
+    world.update();
+    world.apply_impulse(unit, 0.2, 0.2);
+
+    let mut pos = Vector::zeros();
+    for _ in 0..30 {
+        pos = world.try_move(unit, 0.0, 0.0, 0.0).translation.vector;
+        world.update();
+        assert!(pos.y <= 0.5 + 1e-6);
+    }
+    // It's pressed up against the wall, and slid along it.
+    assert_near(pos.y, 0.5, 1e-6);
+    assert!(pos.x > 1.0);
+}
+
+#[test]
+fn impulses_only_move_units_and_bodies() {
+    let mut world = World::new();
+    let wall = world.add(1, 5.0, 0.0, 0.0, make_box(1.0, 1.0), CGroup::Static, 0.0);
+    let bullet = world.add(2, -5.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+    let crate_ = world.add(3, 0.0, 5.0, 0.0, make_box(1.0, 1.0), CGroup::Dynamic, 0.1);
+    world.apply_impulse(wall, 1.0, 0.0);
+    world.apply_impulse(bullet, 1.0, 0.0);
+    world.apply_impulse(99, 1.0, 0.0);
+    assert_eq!(world.knockback(wall), Vector::zeros());
+    assert_eq!(world.knockback(bullet), Vector::zeros());
+
+    world.apply_impulse(crate_, 1.0, 0.0);
+    let (vel, spin) = world.body_velocity(crate_).unwrap();
+    assert!(vel.x > 0.0);
+    assert_eq!(spin, 0.0);
+}
+
+#[test]
+fn knockback_survives_snapshots() {
+    let mut world = World::new();
+    world.set_deterministic(true);
+    world.set_knockback_decay(0.2);
+    world.add(1, 2.0, 0.0, 0.5, make_box(1.0, 6.0), CGroup::Static, 0.0);
+    let unit = world.add(2, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.3);
+    world.update();
+    world.apply_impulse(unit, 0.3, 0.1);
+    world.update();
+    // The unit keeps sliding, the same way in both.
+    assert_round_trips(&mut world, 20);
+}
@@ -0,0 +1,59 @@
+use collide_wasm::*;
+
+#[test]
+fn loads_objects_in_order() {
+    let mut world = World::new();
+    let objects = world.load_level(r#"{
+        "version": 1,
+        "objects": [
+            { "shape": { "type": "circle", "radius": 1 }, "x": 2.5, "y": -3 },
+            { "shape": { "type": "box", "w": 1, "h": 5 }, "x": -10, "y": 0, "angle": -0.2,
+              "id": 7, "tags": ["wall", "north"], "color": "red" },
+            { "shape": { "type": "circle", "radius": 0.5 }, "x": 0, "y": 0,
+              "group": "unit", "speed": 0.1 }
+        ]
+    }"#, 100).unwrap();
+
+    assert_eq!(objects.iter().map(|o| o.id).collect::<Vec<_>>(), vec![100, 7, 102]);
+    assert_eq!(objects[0].tags, Vec::<String>::new());
+    assert_eq!(objects[1].tags, vec!["wall", "north"]);
+
+    // The unit is sitting in the open, and the walls stop it.
+    let pos = world.try_move(objects[2].handle, 0.1, 0.0, 0.0);
+    assert!((pos.translation.x - 0.1).abs() < 1e-9);
+    world.set_position(objects[2].handle, 1.0, -3.0, 0.0);
+    world.update();
+    let pos = world.try_move(objects[2].handle, 0.1, 0.0, 0.0);
+    assert!((pos.translation.x - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn shipped_level_loads() {
+    let mut world = World::new();
+    let objects = world.load_level(include_str!("../../lib/levels/arena.json"), 1).unwrap();
+    assert_eq!(objects.len(), 9);
+}
+
+#[test]
+fn bad_levels_add_nothing() {
+    let mut world = World::new();
+    let empty = world.state_hash();
+
+    match world.load_level("{ \"version\": 1, \"objects\": [{ \"x\": 0 }] }", 1) {
+        Err(LevelError::Parse(_)) => {},
+        r => panic!("expected a parse error, got {:?}", r),
+    }
+
+    assert_eq!(world.load_level(r#"{ "version": 2, "objects": [] }"#, 1),
+        Err(LevelError::UnsupportedVersion(2)));
+
+    assert_eq!(world.load_level(r#"{
+        "version": 1,
+        "objects": [
+            { "shape": { "type": "circle", "radius": 1 }, "x": 0, "y": 0 },
+            { "shape": { "type": "box", "w": -1, "h": 5 }, "x": 0, "y": 0 }
+        ]
+    }"#, 1), Err(LevelError::Invalid(1, "box size must be positive")));
+
+    assert_eq!(world.state_hash(), empty);
+}
@@ -0,0 +1,75 @@
+// Tests for surface materials.
+use collide_wasm::*;
+mod common;
+use common::assert_round_trips;
+
+// A unit pressed diagonally against the floor (y = 0.5, with y pointing
+// down) for a while. Returns how far it slid in the last frame, and the
+// materials it touched then.
+fn slide(friction: f64, slip: f64) -> (f64, Vec<u32>) {
+    let mut world = World::new();
+    let floor = world.add(1, 0.0, 1.0, 0.0, make_box(100.0, 1.0), CGroup::Static, 0.0);
+    world.set_material(floor, 7, friction, slip, 3);
+    let unit = world.add(2, 0.0, -0.5, 0.0, make_circle(0.5), CGroup::Unit, 1.0);
+    world.update();
+
+    let mut x = 0.0;
+    for _ in 0..10 {
+        x = world.try_move(unit, 0.1, 0.1, 0.0).translation.x;
+        world.update();
+    }
+    let (pos, materials) = world.try_move_with_materials(unit, 0.1, 0.1, 0.0);
+    (pos.translation.x - x, materials)
+}
+
+#[test]
+fn plain_surfaces_keep_the_speed_along_them() {
+    let (dx, materials) = slide(0.0, 0.0);
+    assert!((dx - 0.1).abs() < 1e-9);
+    assert_eq!(materials, vec![7]);
+}
+
+#[test]
+fn ice_keeps_all_the_speed() {
+    let (dx, _) = slide(0.0, 1.0);
+    assert!((dx - 0.1 * 2f64.sqrt()).abs() < 1e-9);
+}
+
+#[test]
+fn mud_slows_the_slide() {
+    let (dx, _) = slide(0.5, 0.0);
+    assert!((dx - 0.05).abs() < 1e-9);
+}
+
+#[test]
+fn materials_are_reported_once_each_in_order() {
+    let mut world = World::new();
+    // A corner: floor below, wall to the right.
+    let floor = world.add(1, 0.0, 1.0, 0.0, make_box(10.0, 1.0), CGroup::Static, 0.0);
+    let wall = world.add(2, 1.5, 0.0, 0.0, make_box(1.0, 10.0), CGroup::Static, 0.0);
+    world.set_material(floor, 1, 0.0, 0.0, 0);
+    world.set_material(wall, 2, 0.0, 0.0, 0);
+    let unit = world.add(3, 0.0, -0.4, 0.0, make_circle(0.5), CGroup::Unit, 1.0);
+    world.update();
+
+    let (_, materials) = world.try_move_with_materials(unit, 0.0, 0.0, 0.0);
+    assert!(materials.is_empty());
+    for _ in 0..10 {
+        world.try_move(unit, 0.2, 0.2, 0.0);
+        world.update();
+    }
+    let (_, materials) = world.try_move_with_materials(unit, 0.2, 0.2, 0.0);
+    assert_eq!(materials, vec![1, 2]);
+}
+
+#[test]
+fn materials_survive_snapshots() {
+    let mut world = World::new();
+    let floor = world.add(1, 0.0, 1.0, 0.0, make_box(10.0, 1.0), CGroup::Static, 0.0);
+    world.set_material(floor, 4, 0.25, 2.0, 9);
+    let expected = Material { id: 4, friction: 0.25, slip: 1.0, sound: 9 };
+    assert_eq!(world.material(floor), Some(expected));
+    let restored = assert_round_trips(&mut world, 0);
+    assert_eq!(restored.material(floor), Some(expected));
+    assert_eq!(restored.material(1000), None);
+}
@@ -0,0 +1,100 @@
+// Tests for statics moved with move_static.
+use collide_wasm::*;
+use ncollide2d::math::Isometry;
+mod common;
+use common::assert_near;
+
+fn pos_of(moved: &[(u32, Isometry<f64>)], id: u32) -> Option<(f64, f64)> {
+    moved.iter().find(|(i, _)| *i == id).map(|(_, p)| (p.translation.vector.x, p.translation.vector.y))
+}
+
+#[test]
+fn carries_units_touching_it() {
+    let mut world = World::new();
+    let platform = world.add(1, 0.0, 0.0, 0.0, make_box(4.0, 1.0), CGroup::Static, 0.0);
+    // Standing on top, and off to the side.
+    world.add(2, 0.0, -1.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.add(3, 5.0, -1.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+
+    let mut moved = Vec::new();
+    for i in 1..=10 {
+        let pos = world.move_static(platform, 0.1, 0.0, 0.0).unwrap();
+        assert!((pos.translation.vector.x - 0.1 * i as f64).abs() < 1e-9);
+        moved = world.update();
+    }
+    assert_near(pos_of(&moved, 2).unwrap(), (1.0, -1.0), 0.02);
+    assert_eq!(pos_of(&moved, 3), None);
+    assert_eq!(world.stats().carried_units, 1);
+}
+
+#[test]
+fn turning_carries_units_around() {
+    let mut world = World::new();
+    let platform = world.add(1, 0.0, 0.0, 0.0, make_box(4.0, 1.0), CGroup::Static, 0.0);
+    world.add(2, 1.5, -1.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+
+    world.move_static(platform, 0.0, 0.0, std::f64::consts::FRAC_PI_2);
+    let moved = world.update();
+    assert_near(pos_of(&moved, 2).unwrap(), (1.0, 1.5), 0.02);
+    // And it turns too.
+    let angle = moved.iter().find(|(id, _)| *id == 2).unwrap().1.rotation.angle();
+    assert!((angle - std::f64::consts::FRAC_PI_2).abs() < 1e-9);
+}
+
+#[test]
+fn pushes_units_in_the_way() {
+    let mut world = World::new();
+    let door = world.add(1, 0.0, 0.0, 0.0, make_box(1.0, 4.0), CGroup::Static, 0.0);
+    world.add(2, 2.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+
+    // A fast move, which would go straight past the unit if it was only
+    // pushed out afterwards.
+    world.move_static(door, 3.0, 0.0, 0.0);
+    let moved = world.update();
+    assert_near(pos_of(&moved, 2).unwrap(), (4.0, 0.0), 0.02);
+
+    // Then it's carried the rest of the way.
+    world.move_static(door, 1.0, 0.0, 0.0);
+    let moved = world.update();
+    assert_near(pos_of(&moved, 2).unwrap(), (5.0, 0.0), 0.02);
+}
+
+#[test]
+fn units_walk_off_moving_platforms() {
+    let mut world = World::new();
+    let platform = world.add(1, 0.0, 0.0, 0.0, make_box(4.0, 1.0), CGroup::Static, 0.0);
+    let unit = world.add(2, 0.0, -1.0, 0.0, make_circle(0.5), CGroup::Unit, 1.0);
+    world.update();
+
+    // Walking along it, while it goes the other way.
+    let mut pos = Isometry::identity();
+    for _ in 0..10 {
+        world.move_static(platform, -0.1, 0.0, 0.0);
+        pos = world.try_move(unit, 0.2, 0.0, 0.0);
+        world.update();
+    }
+    assert_near((pos.translation.vector.x, pos.translation.vector.y), (1.0, -1.0), 0.02);
+
+    // Then off the end, where it's left behind.
+    for _ in 0..30 {
+        world.move_static(platform, -0.1, 0.0, 0.0);
+        pos = world.try_move(unit, 0.2, 0.0, 0.0);
+        world.update();
+    }
+    world.move_static(platform, -0.1, 0.0, 0.0);
+    let next = world.try_move(unit, 0.2, 0.0, 0.0);
+    assert!((next.translation.vector.x - pos.translation.vector.x - 0.2).abs() < 1e-9);
+}
+
+#[test]
+fn only_statics_move() {
+    let mut world = World::new();
+    let unit = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+    let pos = world.move_static(unit, 1.0, 0.0, 0.0).unwrap();
+    assert_eq!(pos.translation.vector.x, 0.0);
+    assert_eq!(world.move_static(99, 1.0, 0.0, 0.0), None);
+}
@@ -0,0 +1,176 @@
+// Tests for the navigation mesh built from the statics.
+use std::collections::{BTreeSet, VecDeque};
+use collide_wasm::*;
+use ncollide2d::math::Point;
+mod common;
+use common::{area, rooms};
+
+fn walkable(world: &mut World, x: f64, y: f64) -> bool {
+    world.navmesh(0.5).unwrap().find_polygon(&Point::new(x, y)).is_some()
+}
+
+// Whether you can walk from a to b along the links.
+fn connected(nav: &NavMesh, a: Point<f64>, b: Point<f64>) -> bool {
+    let start = nav.find_polygon(&a).unwrap();
+    let end = nav.find_polygon(&b).unwrap();
+    let mut seen = BTreeSet::new();
+    let mut queue = VecDeque::new();
+    seen.insert(start);
+    queue.push_back(start);
+    while let Some(r) = queue.pop_front() {
+        if r == end { return true; }
+        for link in nav.links(r) {
+            if seen.insert(link.poly) { queue.push_back(link.poly); }
+        }
+    }
+    false
+}
+
+#[test]
+fn statics_are_inflated_by_the_agent_radius() {
+    let mut world = World::new();
+    assert!(world.navmesh(0.5).is_none());
+    world.enable_navmesh(0.5);
+    assert_eq!(world.navmesh(0.5).unwrap().polygon_count(), 0);
+
+    world.add(1, 8.0, 8.0, 0.0, make_box(2.0, 2.0), CGroup::Static, 0.0);
+    // Only statics count.
+    world.add(2, 3.0, 3.0, 0.0, make_circle(1.0), CGroup::Unit, 0.1);
+    world.add(3, 12.0, 3.0, 0.0, make_box(1.0, 1.0), CGroup::Dynamic, 0.1);
+
+    assert!(!walkable(&mut world, 8.0, 8.0));
+    assert!(!walkable(&mut world, 9.4, 8.0));
+    assert!(walkable(&mut world, 9.6, 8.0));
+    assert!(walkable(&mut world, 3.0, 3.0));
+    assert!(walkable(&mut world, 12.0, 3.0));
+    // The corners are rounded off (well, cut at 45 degrees).
+    assert!(!walkable(&mut world, 9.3, 9.3));
+    assert!(walkable(&mut world, 9.4, 9.4));
+
+    // The box grows by 0.5 on each side, and by a bit less than a quarter
+    // circle at each corner.
+    let inflated = 4.0 + 8.0 * 0.5 + 8.0 * 0.25 * (std::f64::consts::PI / 8.0).tan();
+    let nav = world.navmesh(0.5).unwrap();
+    // The box's tile, and the ring of empty ones around it.
+    assert_eq!(nav.tile_count(), 9);
+    let free = nav.polygons().map(|(_, points)| area(points)).sum::<f64>();
+    assert!((free - (9.0 * 256.0 - inflated)).abs() < 1e-6, "free area {}", free);
+}
+
+#[test]
+fn polygons_are_convex_and_triangulate() {
+    let mut world = rooms();
+    world.add(7, 5.0, 5.0, 0.7, make_box(2.0, 1.0), CGroup::Static, 0.0);
+    world.add(8, 15.0, 3.0, 0.0, make_circle(1.0), CGroup::Static, 0.0);
+    world.enable_navmesh(0.4);
+    let nav = world.navmesh(0.4).unwrap();
+
+    let mut total = 0.0;
+    for (_, points) in nav.polygons() {
+        let n = points.len();
+        for i in 0..n {
+            let (a, b, c) = (points[i], points[(i + 1) % n], points[(i + 2) % n]);
+            assert!((b - a).perp(&(c - b)) > 0.0, "not convex: {:?}", points);
+        }
+        total += area(points);
+    }
+    let triangles = nav.triangles().iter().map(|t| area(t)).sum::<f64>();
+    assert!((triangles - total).abs() < 1e-6);
+    assert!(nav.triangles().iter().all(|t| area(t) > 0.0));
+}
+
+#[test]
+fn links_cross_slabs_and_tiles() {
+    let mut world = rooms();
+    world.enable_navmesh(0.5);
+    let nav = world.navmesh(0.5).unwrap();
+    assert!(nav.tile_count() > 1);
+
+    // Through the gap, and over the tile border at x = 16.
+    assert!(connected(nav, Point::new(2.0, 2.0), Point::new(18.0, 8.0)));
+    // The outside isn't connected to the inside.
+    assert!(!connected(nav, Point::new(2.0, 2.0), Point::new(-3.0, 5.0)));
+
+    // Portals run along the shared edge, left then right as you go through.
+    for (r, _) in nav.polygons() {
+        for link in nav.links(r) {
+            let back = nav.links(link.poly).iter().find(|l| l.poly == r).unwrap();
+            assert_eq!((back.left, back.right), (link.right, link.left));
+        }
+    }
+
+    // The gap is 2 wide, which leaves 1 for the agent's center. A fatter
+    // agent doesn't fit.
+    world.enable_navmesh(1.1);
+    let nav = world.navmesh(1.1).unwrap();
+    assert!(!connected(nav, Point::new(2.0, 2.0), Point::new(18.0, 8.0)));
+}
+
+#[test]
+fn statics_update_the_mesh_incrementally() {
+    let mut world = rooms();
+    world.enable_navmesh(0.5);
+    world.update();
+    let tiles = world.navmesh(0.5).unwrap().tile_count();
+    let before = world.navmesh(0.5).unwrap().polygons()
+        .map(|(_, points)| points.to_vec())
+        .collect::<Vec<_>>();
+    world.update();
+    assert_eq!(world.stats().navmesh_tiles_rebuilt, 0);
+
+    // Block up the gap. Only the tile it's in needs rebuilding.
+    let block = world.add(7, 10.0, 5.0, 0.0, make_box(1.0, 2.0), CGroup::Static, 0.0);
+    world.update();
+    assert_eq!(world.stats().navmesh_tiles_rebuilt, 1);
+    world.update();
+    assert_eq!(world.stats().navmesh_tiles_rebuilt, 0);
+    assert!(!connected(world.navmesh(0.5).unwrap(), Point::new(2.0, 2.0), Point::new(18.0, 8.0)));
+
+    // Move it out of the way, then take it away again.
+    world.set_position(block, 5.0, 5.0, 0.0);
+    world.update();
+    assert!(connected(world.navmesh(0.5).unwrap(), Point::new(2.0, 2.0), Point::new(18.0, 8.0)));
+    assert!(!walkable(&mut world, 5.0, 5.0));
+    world.remove(block);
+    world.update();
+    assert_eq!(world.stats().navmesh_tiles_rebuilt, 1);
+    assert!(world.stats().navmesh_tiles_rebuilt < tiles as u32);
+
+    let after = world.navmesh(0.5).unwrap().polygons()
+        .map(|(_, points)| points.to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(after, before);
+}
+
+#[test]
+fn spread_out_statics_give_up() {
+    let mut world = rooms();
+    world.enable_navmesh(0.5);
+    world.set_log_level(LogLevel::Warn);
+    let far = world.add(7, 1e6, 1e6, 0.0, make_box(1.0, 1.0), CGroup::Static, 0.0);
+    assert!(world.navmesh(0.5).is_none());
+    assert_eq!(world.find_path(2.0, 2.0, 8.0, 8.0, 0.5), None);
+    assert_eq!(world.drain_log().len(), 1);
+
+    // Only once, rather than every update.
+    world.update();
+    assert!(world.drain_log().is_empty());
+
+    world.remove(far);
+    assert!(connected(world.navmesh(0.5).unwrap(), Point::new(2.0, 2.0), Point::new(18.0, 8.0)));
+}
+
+#[test]
+fn navmesh_survives_restore() {
+    let mut world = rooms();
+    world.enable_navmesh(0.5);
+    let bytes = world.snapshot().unwrap();
+    let before = world.navmesh(0.5).unwrap().polygon_count();
+
+    world.add(7, 10.0, 5.0, 0.0, make_box(1.0, 2.0), CGroup::Static, 0.0);
+    assert!(!connected(world.navmesh(0.5).unwrap(), Point::new(2.0, 2.0), Point::new(18.0, 8.0)));
+    world.restore(&bytes).unwrap();
+    let nav = world.navmesh(0.5).unwrap();
+    assert_eq!(nav.polygon_count(), before);
+    assert!(connected(nav, Point::new(2.0, 2.0), Point::new(18.0, 8.0)));
+}
@@ -0,0 +1,69 @@
+// Tests for one way walls.
+use collide_wasm::*;
+mod common;
+use common::assert_near;
+
+// A ledge along y = 0 which is solid from above (y pointing down, so its
+// normal is -y), and a unit at (0, y).
+fn ledge(y: f64) -> (World, usize, usize) {
+    let mut world = World::new();
+    let ledge = world.add(1, 0.0, 0.0, 0.0, make_box(4.0, 0.2), CGroup::Static, 0.0);
+    world.set_one_way(ledge, 0.0, -1.0);
+    let unit = world.add(2, 0.0, y, 0.0, make_circle(0.5), CGroup::Unit, 1.0);
+    world.update();
+    (world, ledge, unit)
+}
+
+// Moves the unit by (vx, vy) for a number of frames, and returns where it
+// ends up.
+fn walk(world: &mut World, unit: usize, vx: f64, vy: f64, frames: usize) -> (f64, f64) {
+    let mut pos = (0.0, 0.0);
+    for _ in 0..frames {
+        let p = world.try_move(unit, vx, vy, 0.0).translation.vector;
+        pos = (p.x, p.y);
+        world.update();
+    }
+    pos
+}
+
+#[test]
+fn blocks_from_the_solid_side() {
+    let (mut world, _, unit) = ledge(-2.0);
+    let (x, y) = walk(&mut world, unit, 0.0, 0.1, 30);
+    assert_near(x, 0.0, 0.02);
+    assert_near(y, -0.6, 0.02);
+}
+
+#[test]
+fn passes_through_from_behind() {
+    let (mut world, _, unit) = ledge(2.0);
+    let (_, y) = walk(&mut world, unit, 0.0, -0.1, 30);
+    assert_near(y, -1.0, 0.02);
+
+    // And lands on it coming back.
+    let (_, y) = walk(&mut world, unit, 0.0, 0.1, 30);
+    assert_near(y, -0.6, 0.02);
+}
+
+#[test]
+fn slides_along_the_solid_side() {
+    let (mut world, _, unit) = ledge(-1.0);
+    let (x, y) = walk(&mut world, unit, 0.1, 0.1, 10);
+    assert_near(x, 1.0, 0.02);
+    assert_near(y, -0.6, 0.02);
+}
+
+#[test]
+fn walls_can_go_back_to_blocking_both_ways() {
+    let (mut world, ledge, unit) = ledge(2.0);
+    let bytes = world.snapshot().unwrap();
+    world.clear_one_way(ledge);
+    let (_, y) = walk(&mut world, unit, 0.0, -0.1, 30);
+    assert_near(y, 0.6, 0.02);
+
+    // Snapshots remember which walls are one way.
+    let mut restored = World::new();
+    restored.restore(&bytes).unwrap();
+    let (_, y) = walk(&mut restored, unit, 0.0, -0.1, 30);
+    assert_near(y, -1.0, 0.02);
+}
@@ -0,0 +1,103 @@
+// Tests for find_path.
+use collide_wasm::*;
+use ncollide2d::math::Point;
+mod common;
+use common::rooms;
+
+fn assert_path(path: &[Point<f64>], expected: &[(f64, f64)]) {
+    assert_eq!(path.len(), expected.len(), "path {:?}", path);
+    for (p, (x, y)) in path.iter().zip(expected) {
+        assert!((p.x - x).abs() < 1e-4 && (p.y - y).abs() < 1e-4, "path {:?}", path);
+    }
+}
+
+// The rooms, with a navmesh for agents of radius 0.5.
+fn navigable_rooms() -> World {
+    let mut world = rooms();
+    world.enable_navmesh(0.5);
+    world
+}
+
+#[test]
+fn open_ground_is_a_straight_line() {
+    let mut world = World::new();
+    world.enable_navmesh(0.5);
+    let path = world.find_path(1.0, 2.0, 30.0, -4.0, 0.5).unwrap();
+    assert_path(&path, &[(1.0, 2.0), (30.0, -4.0)]);
+
+    let mut world = navigable_rooms();
+    let path = world.find_path(2.0, 2.0, 8.0, 8.0, 0.5).unwrap();
+    assert_path(&path, &[(2.0, 2.0), (8.0, 8.0)]);
+}
+
+#[test]
+fn paths_turn_at_corners() {
+    let mut world = navigable_rooms();
+    let path = world.find_path(2.0, 2.0, 18.0, 2.0, 0.5).unwrap();
+
+    // Over the top of the bottom half of the wall, just clearing its
+    // corners. The corners of the inflated wall are cut at 45 degrees, which
+    // puts them 0.5 tan(22.5) in from the corners of the box.
+    let cut = 0.5 * (std::f64::consts::PI / 8.0).tan();
+    assert_path(&path, &[(2.0, 2.0), (9.5 - cut, 4.5), (10.5 + cut, 4.5), (18.0, 2.0)]);
+
+    // And the same the other way.
+    let back = world.find_path(18.0, 2.0, 2.0, 2.0, 0.5).unwrap();
+    assert_eq!(back.iter().rev().cloned().collect::<Vec<_>>(), path);
+}
+
+#[test]
+fn no_path_when_blocked() {
+    let mut world = navigable_rooms();
+    let block = world.add(7, 10.0, 5.0, 0.0, make_box(1.0, 2.0), CGroup::Static, 0.0);
+    assert_eq!(world.find_path(2.0, 2.0, 18.0, 2.0, 0.5), None);
+
+    // Fat agents don't fit through the gap either.
+    world.remove(block);
+    assert!(world.find_path(2.0, 2.0, 18.0, 2.0, 0.5).is_some());
+    world.enable_navmesh(1.2);
+    assert_eq!(world.find_path(2.0, 2.0, 18.0, 2.0, 1.2), None);
+
+    // Nor can you get into a wall.
+    assert_eq!(world.find_path(2.0, 2.0, 10.0, 2.0, 0.5), None);
+}
+
+#[test]
+fn ends_near_walls_are_snapped() {
+    let mut world = navigable_rooms();
+    // A unit up against the wall is a little inside the inflated outline.
+    let path = world.find_path(9.1, 2.0, 2.0, 2.0, 0.5).unwrap();
+    assert_path(&path, &[(9.0, 2.0), (2.0, 2.0)]);
+}
+
+#[test]
+fn paths_leave_the_mesh() {
+    let mut world = navigable_rooms();
+    // Outside the room, and well past the edge of the mesh.
+    let path = world.find_path(-3.0, 5.0, 100.0, 50.0, 0.5).unwrap();
+    assert_eq!(path.first(), Some(&Point::new(-3.0, 5.0)));
+    assert_eq!(path.last(), Some(&Point::new(100.0, 50.0)));
+    assert!(path.len() > 2);
+
+    // But still not in to the room.
+    assert_eq!(world.find_path(100.0, 50.0, 5.0, 5.0, 0.5), None);
+}
+
+#[test]
+fn only_enabled_radii_find_paths() {
+    let mut world = navigable_rooms();
+    assert_eq!(world.find_path(2.0, 2.0, 18.0, 2.0, 0.3), None);
+    assert!(world.navmesh(0.3).is_none());
+
+    world.enable_navmesh(0.3);
+    assert!(world.find_path(2.0, 2.0, 18.0, 2.0, 0.3).is_some());
+    assert!(world.navmesh(0.5).is_some());
+}
+
+#[test]
+fn bad_radii_find_nothing() {
+    let mut world = navigable_rooms();
+    assert_eq!(world.find_path(2.0, 2.0, 18.0, 2.0, f64::NAN), None);
+    assert_eq!(world.find_path(2.0, 2.0, 18.0, 2.0, -1.0), None);
+    assert!(world.navmesh(-1.0).is_none());
+}
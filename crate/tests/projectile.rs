@@ -0,0 +1,222 @@
+// Tests for projectiles - launched ones which bounce, and ones moved with
+// try_move, which are swept so they can't tunnel.
+use collide_wasm::*;
+use ncollide2d::math::Vector;
+mod common;
+use common::{assert_near, assert_round_trips};
+
+// Runs updates until something happens, and returns the events.
+fn next_events(world: &mut World) -> Vec<ProjectileEvent> {
+    for _ in 0..100 {
+        world.update();
+        let events = world.projectile_events();
+        if !events.is_empty() { return events; }
+    }
+    panic!("nothing happened");
+}
+
+fn position(world: &mut World, handle: usize) -> Vector<f64> {
+    world.try_move(handle, 0.0, 0.0, 0.0).translation.vector
+}
+
+#[test]
+fn bounces_off_walls() {
+    let mut world = World::new();
+    world.add(1, 3.0, 0.0, 0.0, make_box(1.0, 10.0), CGroup::Static, 0.0);
+    let p = world.add(2, 0.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+    world.launch(p, 0.35, 0.0, 1.0, 3);
+
+    let events = next_events(&mut world);
+    assert_eq!(events.len(), 1);
+    let e = events[0];
+    assert_eq!((e.kind, e.projectile, e.other), (ProjectileEventKind::Bounce, 2, 1));
+    assert_near(e.point.x, 2.5, 1e-6);
+    assert_near(e.point.y, 0.0, 1e-6);
+    assert_near(e.normal.x, -1.0, 1e-6);
+
+    // It hit at x = 2.4 with 0.05 of its movement left, which it's used to
+    // come back (after backing off the wall slightly).
+    assert_near(position(&mut world, p).x, 2.4 - 0.001 - 0.05, 1e-6);
+    world.update();
+    assert_near(position(&mut world, p).x, 2.4 - 0.001 - 0.05 - 0.35, 1e-6);
+}
+
+#[test]
+fn restitution_scales_the_bounce() {
+    let mut world = World::new();
+    world.add(1, 0.0, 3.0, 0.0, make_box(20.0, 1.0), CGroup::Static, 0.0);
+    let p = world.add(2, 0.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+    world.launch(p, 0.2, 0.2, 0.5, 3);
+
+    let e = next_events(&mut world)[0];
+    assert_near(e.normal.y, -1.0, 1e-6);
+    let before = position(&mut world, p);
+    world.update();
+    let after = position(&mut world, p);
+    assert_near(after.x - before.x, 0.2, 1e-6);
+    assert_near(after.y - before.y, -0.1, 1e-6);
+
+    // And it's facing the way it's going.
+    let angle = world.try_move(p, 0.0, 0.0, 0.0).rotation.angle();
+    assert_near(angle, (-0.1f64).atan2(0.2), 1e-6);
+}
+
+#[test]
+fn stops_after_max_bounces() {
+    // A narrow corridor, so it bounces several times a frame.
+    let mut world = World::new();
+    world.add(1, -1.0, 0.0, 0.0, make_box(1.0, 10.0), CGroup::Static, 0.0);
+    world.add(2, 1.0, 0.0, 0.0, make_box(1.0, 10.0), CGroup::Static, 0.0);
+    let p = world.add(3, 0.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+    world.launch(p, 2.5, 0.01, 1.0, 2);
+
+    let events = next_events(&mut world);
+    let summary = events.iter().map(|e| (e.kind, e.other)).collect::<Vec<_>>();
+    assert_eq!(summary, vec![
+        (ProjectileEventKind::Bounce, 2),
+        (ProjectileEventKind::Bounce, 1),
+        (ProjectileEventKind::Stop, 2),
+    ]);
+
+    // Then it stays where it stopped, against the right wall.
+    let pos = position(&mut world, p);
+    assert_near(pos.x, 0.4, 1e-6);
+    for _ in 0..5 {
+        world.update();
+        assert!(world.projectile_events().is_empty());
+    }
+    assert_eq!(position(&mut world, p), pos);
+}
+
+#[test]
+fn stops_at_the_first_unit() {
+    let mut world = World::new();
+    world.add(1, 10.0, 0.0, 0.0, make_box(1.0, 10.0), CGroup::Static, 0.0);
+    world.add(2, 3.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.add(3, 5.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let p = world.add(4, 0.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+    // Fast enough to go straight past both units in one frame.
+    world.launch(p, 20.0, 0.0, 1.0, 3);
+
+    world.update();
+    let events = world.projectile_events();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].kind, events[0].other), (ProjectileEventKind::Hit, 2));
+    assert_near(events[0].point.x, 2.5, 1e-6);
+    assert_near(position(&mut world, p).x, 2.4, 1e-6);
+
+    // Launched projectiles don't get proximity events.
+    assert!(world.proximity_events().is_empty());
+}
+
+#[test]
+fn bounces_knock_bodies() {
+    let mut world = World::new();
+    let barrel = world.add(1, 3.0, 0.0, 0.0, make_circle(0.5), CGroup::Dynamic, 0.1);
+    let p = world.add(2, 0.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+    world.launch(p, 0.5, 0.0, 1.0, 3);
+
+    let e = next_events(&mut world)[0];
+    assert_eq!((e.kind, e.other), (ProjectileEventKind::Bounce, 1));
+    let (vel, _) = world.body_velocity(barrel).unwrap();
+    assert!(vel.x > 0.0);
+}
+
+#[test]
+fn only_projectiles_launch() {
+    let mut world = World::new();
+    let unit = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let hash = world.state_hash();
+    world.launch(unit, 1.0, 0.0, 1.0, 3);
+    world.launch(99, 1.0, 0.0, 1.0, 3);
+    assert_eq!(world.state_hash(), hash);
+}
+
+#[test]
+fn launched_projectiles_survive_snapshots() {
+    let mut world = World::new();
+    world.set_deterministic(true);
+    world.add(1, -2.0, 0.0, 0.0, make_box(1.0, 10.0), CGroup::Static, 0.0);
+    world.add(2, 2.0, 0.0, 0.3, make_box(1.0, 10.0), CGroup::Static, 0.0);
+    world.add(3, 0.0, 0.0, 0.0, make_box(1.0, 1.0), CGroup::Dynamic, 0.1);
+    for i in 0..4 {
+        let p = world.add(10 + i, -1.0, -4.0 + 2.0 * i as f64, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+        world.launch(p, 0.3, 0.1 * i as f64, 0.8, 5);
+    }
+    for _ in 0..5 { world.update(); }
+    let bytes = world.snapshot().unwrap();
+    assert_round_trips(&mut world, 40);
+
+    // Those frames had plenty of bounces in them.
+    let mut replay = World::new();
+    replay.restore(&bytes).unwrap();
+    let bounces = (0..40).map(|_| { replay.update(); replay.projectile_events().len() }).sum::<usize>();
+    assert!(bounces > 4);
+}
+
+// Projectiles moved with try_move are swept too.
+#[test]
+fn fast_projectiles_do_not_tunnel() {
+    let mut world = World::new();
+    world.add(1, 3.0, 0.0, 0.0, make_box(0.2, 10.0), CGroup::Static, 0.0);
+    let p = world.add(2, 0.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+    world.update();
+
+    // Without sweeping this would end up at x = 5, well past the wall.
+    assert_near(world.try_move(p, 5.0, 0.0, 0.0).translation.x, 2.8, 1e-6);
+    world.update();
+    let events = world.projectile_events();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].kind, events[0].projectile, events[0].other), (ProjectileEventKind::Stop, 2, 1));
+    assert_near(events[0].point.x, 2.9, 1e-6);
+    assert_near(events[0].normal.x, -1.0, 1e-6);
+
+    // The events only last a frame.
+    world.update();
+    assert!(world.projectile_events().is_empty());
+}
+
+#[test]
+fn fast_projectiles_report_units_they_pass() {
+    let mut world = World::new();
+    world.add(1, 8.0, 0.0, 0.0, make_box(1.0, 10.0), CGroup::Static, 0.0);
+    world.add(2, 2.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.add(3, 3.5, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let p = world.add(4, 0.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+    world.update();
+
+    // Straight through both units and out the other side. Both get reported,
+    // in the order they were hit.
+    assert_near(world.try_move(p, 5.0, 0.0, 0.0).translation.x, 5.0, 1e-6);
+    world.update();
+    let events = world.projectile_events();
+    let summary = events.iter().map(|e| (e.kind, e.other)).collect::<Vec<_>>();
+    assert_eq!(summary, vec![(ProjectileEventKind::Hit, 2), (ProjectileEventKind::Hit, 3)]);
+    assert_near(events[0].point.x, 1.5, 1e-6);
+    assert_near(events[1].point.x, 3.0, 1e-6);
+
+    // Next frame it gets to the wall, and stops.
+    assert_near(world.try_move(p, 5.0, 0.0, 0.0).translation.x, 7.4, 1e-6);
+    world.update();
+    let events = world.projectile_events();
+    assert_eq!((events[0].kind, events[0].other), (ProjectileEventKind::Stop, 1));
+}
+
+#[test]
+fn fast_projectiles_report_units_before_stopping() {
+    let mut world = World::new();
+    world.add(1, 4.0, 0.0, 0.0, make_box(1.0, 10.0), CGroup::Static, 0.0);
+    world.add(2, 2.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    // Behind the wall, so never reached.
+    world.add(3, 6.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    // The shooter, which it starts off overlapping.
+    world.add(4, -0.3, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let p = world.add(5, 0.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+    world.update();
+
+    assert_near(world.try_move(p, 8.0, 0.0, 0.0).translation.x, 3.4, 1e-6);
+    world.update();
+    let events = world.projectile_events();
+    let summary = events.iter().map(|e| (e.kind, e.other)).collect::<Vec<_>>();
+    assert_eq!(summary, vec![(ProjectileEventKind::Hit, 2), (ProjectileEventKind::Stop, 1)]);
+}
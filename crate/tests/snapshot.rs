@@ -0,0 +1,166 @@
+use collide_wasm::*;
+use ncollide2d::math::Point;
+use ncollide2d::shape::{Segment, ShapeHandle};
+
+struct Game {
+    world: World,
+    // (id, handle)
+    units: Vec<(u32, usize)>,
+    next_id: u32,
+}
+
+impl Game {
+    fn new() -> Game {
+        let mut world = World::new();
+        world.set_deterministic(true);
+        world.add(1, 0.0, 0.0, 0.3, make_box(4.0, 0.5), CGroup::Static, 0.0);
+        world.add(2, 3.0, 2.0, 0.0, make_circle(1.0), CGroup::Static, 0.0);
+        let mut game = Game { world, units: Vec::new(), next_id: 100 };
+        for i in 0..5 {
+            game.spawn(-2.0 + i as f64, 1.5, i % 2 == 0);
+        }
+        game.world.update();
+        game
+    }
+
+    fn spawn(&mut self, x: f64, y: f64, circle: bool) {
+        let shape = if circle { make_circle(0.3) } else { make_box(0.5, 0.8) };
+        let id = self.next_id;
+        self.next_id += 1;
+        self.units.push((id, self.world.add(id, x, y, 0.0, shape, CGroup::Unit, 0.2)));
+    }
+
+    // Every few frames a unit dies and another spawns, so handles get freed
+    // and reused.
+    fn step(&mut self, frame: usize) -> u64 {
+        for (i, (_id, h)) in self.units.iter().enumerate() {
+            let a = (frame + i * 7) as f64 * 0.1;
+            self.world.try_move(*h, a.cos() * 0.2, a.sin() * 0.2 - 0.05, 0.05);
+        }
+        for (id, pos) in self.world.update() {
+            let h = self.units.iter().find(|(uid, _)| *uid == id).unwrap().1;
+            self.world.set_position(h, pos.translation.x, pos.translation.y, pos.rotation.angle());
+        }
+
+        if frame % 10 == 3 || frame % 10 == 5 {
+            let (_id, h) = self.units.remove(frame % self.units.len());
+            self.world.remove(h);
+        }
+        if frame % 10 == 7 || frame % 10 == 9 {
+            self.spawn(0.0, 4.0, frame % 20 < 10);
+        }
+        self.world.state_hash()
+    }
+}
+
+#[test]
+fn restore_round_trips() {
+    let mut game = Game::new();
+    for frame in 0..25 { game.step(frame); }
+
+    let bytes = game.world.snapshot().unwrap();
+    let mut world = World::new();
+    world.restore(&bytes).unwrap();
+
+    assert_eq!(world.state_hash(), game.world.state_hash());
+    assert_eq!(world.snapshot().unwrap(), bytes);
+}
+
+#[test]
+fn rollback_resimulates_identically() {
+    // Two units have died and not been replaced yet, so restore needs to get
+    // the order their handles are reused in right.
+    let mut game = Game::new();
+    for frame in 0..26 { game.step(frame); }
+
+    let bytes = game.world.snapshot().unwrap();
+    let (units, next_id) = (game.units.clone(), game.next_id);
+    let expected = (26..90).map(|frame| game.step(frame)).collect::<Vec<_>>();
+
+    // Roll back into the same world, after it has moved on.
+    game.world.restore(&bytes).unwrap();
+    game.units = units.clone();
+    game.next_id = next_id;
+    let actual = (26..90).map(|frame| game.step(frame)).collect::<Vec<_>>();
+    assert_eq!(actual, expected);
+
+    // And into a fresh one.
+    let mut world = World::new();
+    world.restore(&bytes).unwrap();
+    let mut game = Game { world, units, next_id };
+    let actual = (26..90).map(|frame| game.step(frame)).collect::<Vec<_>>();
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn rejects_bad_snapshots() {
+    let mut game = Game::new();
+    for frame in 0..25 { game.step(frame); }
+    let bytes = game.world.snapshot().unwrap();
+    let hash = game.world.state_hash();
+
+    assert_eq!(game.world.restore(b"nope"), Err(SnapshotError::BadMagic));
+    assert_eq!(game.world.restore(&bytes[..bytes.len() - 1]), Err(SnapshotError::Truncated));
+
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert_eq!(game.world.restore(&longer), Err(SnapshotError::TrailingBytes));
+
+    let mut future = bytes.clone();
+    future[4] = 99;
+    assert_eq!(game.world.restore(&future), Err(SnapshotError::UnsupportedVersion(99)));
+
+    // None of that should have touched the world.
+    assert_eq!(game.world.state_hash(), hash);
+}
+
+#[test]
+fn rejects_bad_positions() {
+    let mut world = World::new();
+    world.add(1, 2.0, 3.0, 0.5, make_circle(1.0), CGroup::Static, 0.0);
+    let bytes = world.snapshot().unwrap();
+    // The header, then handle, id, group and a ball.
+    let pos = 4 + 2 + 1 + 8 + 4 + 4 + 4 + 4 + 4 + 1 + 1 + 8;
+    let with = |i: usize, v: f64| {
+        let mut b = bytes.clone();
+        b[pos + i * 8..pos + i * 8 + 8].copy_from_slice(&v.to_le_bytes());
+        b
+    };
+    assert_eq!(world.restore(&with(0, f64::NAN)), Err(SnapshotError::BadPosition));
+    assert_eq!(world.restore(&with(1, f64::INFINITY)), Err(SnapshotError::BadPosition));
+    assert_eq!(world.restore(&with(2, 2.0)), Err(SnapshotError::BadPosition));
+    assert_eq!(world.restore(&with(3, 0.0)), Err(SnapshotError::BadPosition));
+    assert_eq!(world.restore(&bytes), Ok(()));
+}
+
+#[test]
+fn rejects_unsorted_vision() {
+    let mut world = World::new();
+    world.add(2, 5.0, -1.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.add(3, 5.0, 1.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    // Added last, so its seen list (handles 0 and 1) comes right before the
+    // material at the very end.
+    let guard = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.set_vision(guard, std::f64::consts::FRAC_PI_2, 10.0);
+    world.update();
+    let bytes = world.snapshot().unwrap();
+    let seen = bytes.len() - 24 - 8;
+    assert_eq!(bytes[seen..seen + 8], [0, 0, 0, 0, 1, 0, 0, 0]);
+
+    let mut swapped = bytes.clone();
+    swapped[seen..seen + 8].copy_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(world.restore(&swapped), Err(SnapshotError::BadHandles));
+    let mut repeated = bytes.clone();
+    repeated[seen..seen + 8].copy_from_slice(&[1, 0, 0, 0, 1, 0, 0, 0]);
+    assert_eq!(world.restore(&repeated), Err(SnapshotError::BadHandles));
+    assert_eq!(world.restore(&bytes), Ok(()));
+}
+
+#[test]
+fn unsupported_shapes_are_an_error() {
+    let mut world = World::new();
+    world.add(1, 0.0, 0.0, 0.0, make_circle(1.0), CGroup::Static, 0.0);
+    let segment = ShapeHandle::new(Segment::new(Point::new(0.0, 0.0), Point::new(1.0, 0.0)));
+    world.add(2, 0.0, 0.0, 0.0, segment, CGroup::Static, 0.0);
+    assert_eq!(world.snapshot(), Err(SnapshotError::UnsupportedShape(2)));
+}
@@ -0,0 +1,156 @@
+use collide_wasm::*;
+mod common;
+use common::{assert_near, assert_round_trips};
+
+// A 4x3 map with 32px tiles. The walls layer is all solid; in the decor layer
+// only tile 1 of the tileset (gid 2) is, and one of those is flipped.
+const MAP: &str = r#"{
+    "width": 4, "height": 3, "tilewidth": 32, "tileheight": 32,
+    "orientation": "orthogonal", "infinite": false,
+    "tilesets": [{
+        "firstgid": 1, "name": "tiles",
+        "tiles": [{ "id": 1, "properties": [{ "name": "collides", "type": "bool", "value": true }] }]
+    }],
+    "layers": [
+        { "type": "tilelayer", "name": "walls", "width": 4, "height": 3,
+          "properties": [{ "name": "collision", "type": "bool", "value": true }],
+          "data": [1, 1, 1, 1,
+                   1, 0, 0, 1,
+                   1, 0, 0, 1] },
+        { "type": "tilelayer", "name": "decor", "width": 4, "height": 3,
+          "data": [0, 0, 0, 0,
+                   0, 3, 2147483650, 0,
+                   0, 0, 0, 0] },
+        { "type": "imagelayer", "name": "background", "image": "bg.png" },
+        { "type": "objectgroup", "name": "things", "offsetx": 32, "objects": [
+            { "id": 1, "name": "crate", "type": "prop", "x": 64, "y": 64, "width": 32, "height": 32, "rotation": 0 },
+            { "id": 2, "name": "spawn", "type": "spawn", "x": 16, "y": 48, "point": true,
+              "properties": [{ "name": "team", "type": "string", "value": "red" }] },
+            { "id": 3, "name": "door", "class": "trigger", "x": 0, "y": 0, "width": 32, "height": 64,
+              "properties": [{ "name": "collide", "type": "bool", "value": false },
+                             { "name": "target", "type": "int", "value": 7 }] },
+            { "id": 4, "name": "pillar", "x": 128, "y": 0, "width": 64, "height": 64, "ellipse": true },
+            { "id": 5, "name": "beam", "x": 0, "y": 160, "width": 64, "height": 32, "rotation": 90 },
+            { "id": 6, "name": "wedge", "x": 256, "y": 0, "polygon": [
+                { "x": 0, "y": 0 }, { "x": 64, "y": 0 }, { "x": 64, "y": 64 }, { "x": 32, "y": 64 }] },
+            { "id": 7, "name": "fence", "x": 0, "y": 320, "polyline": [{ "x": 0, "y": 0 }, { "x": 320, "y": 0 }],
+              "properties": [{ "name": "group", "type": "string", "value": "projectile" }] }
+        ] }
+    ]
+}"#;
+
+#[test]
+fn imports_tiles_and_objects() {
+    let mut world = World::new();
+    let map = world.load_tiled(MAP, 10).unwrap();
+
+    // The walls merge into the top row plus the two sides, and the decor
+    // layer gives one box (the flipped tile) - gid 3 isn't solid.
+    assert_eq!(map.tiles.len(), 4);
+    assert_eq!(map.tiles.iter().map(|t| t.id).collect::<Vec<_>>(), vec![10, 11, 12, 13]);
+    assert_eq!(map.tiles[0].tags, vec!["walls"]);
+    assert_eq!(map.tiles[3].tags, vec!["decor"]);
+
+    let names = map.objects.iter().map(|o| o.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["crate", "spawn", "door", "pillar", "beam", "wedge", "fence"]);
+    let get = |name: &str| map.objects.iter().find(|o| o.name == name).unwrap();
+
+    // Object ids carry on from the tiles, skipping objects without colliders.
+    let ids = map.objects.iter().map(|o| o.collider.as_ref().map(|c| c.id)).collect::<Vec<_>>();
+    assert_eq!(ids, vec![Some(14), None, None, Some(15), Some(16), Some(17), Some(18)]);
+    assert_eq!(get("crate").collider.as_ref().unwrap().tags, vec!["things", "prop"]);
+
+    let spawn = get("spawn");
+    assert_eq!(spawn.kind, "spawn");
+    assert_near(spawn.x, 1.5, 1e-9); // Includes the layer offset.
+    assert_near(spawn.y, 1.5, 1e-9);
+    assert_eq!(spawn.properties["team"], "red");
+
+    let door = get("door");
+    assert_eq!(door.kind, "trigger");
+    assert_eq!(door.properties["target"], 7);
+    assert_near(door.height, 2.0, 1e-9);
+
+    assert_near(get("beam").angle, std::f64::consts::FRAC_PI_2, 1e-9);
+
+    // Walk a unit into each collider and check it stops at the surface.
+    let unit = world.add(1, 0.0, 0.0, 0.0, make_circle(0.25), CGroup::Unit, 0.1);
+    let mut walk = |x: f64, y: f64, dx: f64, dy: f64| {
+        world.set_position(unit, x, y, 0.0);
+        world.update();
+        let mut pos = world.try_move(unit, 0.0, 0.0, 0.0);
+        for _ in 0..40 {
+            world.update();
+            pos = world.try_move(unit, dx, dy, 0.0);
+        }
+        pos.translation.vector
+    };
+
+    // Up into the top row of wall tiles, which ends at y = 1. Cell (1, 1) is
+    // decor but not solid.
+    assert_near(walk(1.5, 2.5, 0.0, -0.1).y, 1.25, 1e-9);
+    // Cell (2, 1) is solid though.
+    assert_near(walk(2.5, 2.5, 0.0, -0.1).y, 2.25, 1e-9);
+    // Left into the crate (3..4 after the offset).
+    assert_near(walk(4.5, 2.5, -0.1, 0.0).x, 4.25, 1e-9);
+    // Right into the pillar, a circle of radius 1 at (6, 1).
+    assert_near(walk(4.0, 1.0, 0.1, 0.0).x, 4.75, 1e-9);
+    // The beam is rotated around its top left corner (1, 5), so it covers
+    // x from 0 to 1 and y from 5 to 7.
+    assert_near(walk(2.0, 6.0, -0.1, 0.0).x, 1.25, 1e-9);
+    // Up into the polygon at (9, 0), whose bottom edge runs from x = 10 to 11.
+    assert_near(walk(10.5, 3.0, 0.0, -0.1).y, 2.25, 1e-9);
+}
+
+#[test]
+fn tiled_shapes_survive_snapshots() {
+    let mut world = World::new();
+    world.load_tiled(MAP, 10).unwrap();
+    let restored = assert_round_trips(&mut world, 0);
+    assert_eq!(restored.debug_lines(), world.debug_lines());
+}
+
+#[test]
+fn unsupported_maps_add_nothing() {
+    let mut world = World::new();
+    let empty = world.state_hash();
+
+    let base64 = MAP.replacen(r#""data": [1, 1, 1, 1,
+                   1, 0, 0, 1,
+                   1, 0, 0, 1]"#, r#""encoding": "base64", "data": "AQAAAA==""#, 1);
+    assert_ne!(base64, MAP);
+    assert_eq!(world.load_tiled(&base64, 1).unwrap_err(), TiledError::Unsupported("tile layers must be saved as CSV"));
+
+    let infinite = MAP.replacen(r#""infinite": false"#, r#""infinite": true"#, 1);
+    assert_eq!(world.load_tiled(&infinite, 1).unwrap_err(), TiledError::Unsupported("infinite maps"));
+
+    let chunked = MAP.replacen(r#""data": [1, 1, 1, 1,
+                   1, 0, 0, 1,
+                   1, 0, 0, 1]"#, r#""chunks": [], "startx": 0, "starty": 0"#, 1);
+    assert_eq!(world.load_tiled(&chunked, 1).unwrap_err(), TiledError::Unsupported("infinite maps"));
+
+    let gzip = MAP.replacen(r#""name": "walls","#, r#""name": "walls", "encoding": "base64", "compression": "gzip","#, 1);
+    assert_eq!(world.load_tiled(&gzip, 1).unwrap_err(), TiledError::Unsupported("tile layers must be saved as CSV"));
+
+    // The decor layer's tiles come from the tileset.
+    let external = MAP.replacen(r#""firstgid": 1, "name": "tiles","#, r#""firstgid": 1, "source": "tiles.tsj","#, 1);
+    assert_eq!(world.load_tiled(&external, 1).unwrap_err(), TiledError::Unsupported("external tilesets"));
+
+    let tile_shapes = MAP.replacen(r#""id": 1, "properties""#,
+        r#""id": 1, "objectgroup": { "type": "objectgroup", "objects": [{ "x": 0, "y": 0, "width": 16, "height": 16 }] }, "properties""#, 1);
+    assert_eq!(world.load_tiled(&tile_shapes, 1).unwrap_err(), TiledError::Unsupported("tiles with their own collision shapes"));
+
+    let concave = MAP.replacen(r#"{ "x": 64, "y": 64 }, { "x": 32, "y": 64 }] }"#,
+        r#"{ "x": 64, "y": 64 }, { "x": 32, "y": 64 }, { "x": 32, "y": 32 }, { "x": 0, "y": 32 }] }"#, 1);
+    assert_eq!(world.load_tiled(&concave, 1).unwrap_err(), TiledError::Invalid("wedge".to_string(), "polygon is concave"));
+
+    // A star turns the same way at every point, but crosses itself.
+    let star = MAP.replacen(r#"{ "x": 0, "y": 0 }, { "x": 64, "y": 0 }, { "x": 64, "y": 64 }, { "x": 32, "y": 64 }] }"#,
+        r#"{ "x": 32, "y": 0 }, { "x": 51, "y": 58 }, { "x": 2, "y": 22 }, { "x": 62, "y": 22 }, { "x": 13, "y": 58 }] }"#, 1);
+    assert_eq!(world.load_tiled(&star, 1).unwrap_err(), TiledError::Invalid("wedge".to_string(), "polygon is concave"));
+
+    let bad_group = MAP.replacen(r#""value": "projectile""#, r#""value": "wall""#, 1);
+    assert_eq!(world.load_tiled(&bad_group, 1).unwrap_err(), TiledError::Invalid("fence".to_string(), "unknown group"));
+
+    assert_eq!(world.state_hash(), empty);
+}
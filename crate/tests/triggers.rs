@@ -0,0 +1,99 @@
+// Tests for trigger volumes.
+use collide_wasm::*;
+mod common;
+use common::assert_round_trips;
+
+fn event(kind: TriggerEventKind, trigger: u32, other: u32) -> TriggerEvent {
+    TriggerEvent { kind, trigger, other }
+}
+
+#[test]
+fn triggers_do_not_block_movement() {
+    let mut world = World::new();
+    world.add(1, 2.0, 0.0, 0.0, make_box(1.0, 4.0), CGroup::Trigger, 0.0);
+    let unit = world.add(2, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 1.0);
+    world.update();
+    let mut pos = None;
+    for _ in 0..4 {
+        pos = Some(world.try_move(unit, 1.0, 0.0, 0.0));
+        world.update();
+    }
+    assert!((pos.unwrap().translation.vector.x - 4.0).abs() < 1e-9);
+}
+
+#[test]
+fn units_enter_and_exit() {
+    let mut world = World::new();
+    world.add(1, 5.0, 0.0, 0.0, make_box(2.0, 2.0), CGroup::Trigger, 0.0);
+    let unit = world.add(2, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    // Statics don't set it off.
+    world.add(3, 5.0, 0.5, 0.0, make_box(1.0, 1.0), CGroup::Static, 0.0);
+    world.update();
+    assert_eq!(world.trigger_events(), vec![]);
+
+    world.set_position(unit, 4.0, 0.0, 0.0);
+    world.update();
+    assert_eq!(world.trigger_events(), vec![event(TriggerEventKind::Enter, 1, 2)]);
+    world.update();
+    assert_eq!(world.trigger_events(), vec![]);
+    // Or the proximity events.
+    assert_eq!(world.proximity_events(), vec![]);
+
+    world.set_position(unit, 8.0, 0.0, 0.0);
+    world.update();
+    assert_eq!(world.trigger_events(), vec![event(TriggerEventKind::Exit, 1, 2)]);
+    assert_eq!(world.stats().trigger_objects, 1);
+}
+
+#[test]
+fn groups_pick_what_is_detected() {
+    let mut world = World::new();
+    let trigger = world.add(1, 0.0, 0.0, 0.0, make_circle(2.0), CGroup::Trigger, 0.0);
+    world.add(2, 0.5, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.add(3, -0.5, 0.0, 0.0, make_box(1.0, 1.0), CGroup::Dynamic, 0.1);
+    world.set_trigger_groups(trigger, 1 << CGroup::Dynamic as u32);
+    world.update();
+    assert_eq!(world.trigger_events(), vec![event(TriggerEventKind::Enter, 1, 3)]);
+
+    // Both at once, ordered by handle.
+    let both = 1 << CGroup::Dynamic as u32 | 1 << CGroup::Unit as u32;
+    let other = world.add(4, 0.0, 0.0, 0.0, make_circle(1.0), CGroup::Trigger, 0.0);
+    world.set_trigger_groups(other, both);
+    world.update();
+    assert_eq!(world.trigger_events(), vec![
+        event(TriggerEventKind::Enter, 4, 2),
+        event(TriggerEventKind::Enter, 4, 3),
+    ]);
+}
+
+#[test]
+fn launched_projectiles_set_off_triggers() {
+    let mut world = World::new();
+    let trigger = world.add(1, 3.0, 0.0, 0.0, make_box(1.0, 4.0), CGroup::Trigger, 0.0);
+    world.set_trigger_groups(trigger, 1 << CGroup::Projectile as u32);
+    // Only looking for units, so this one doesn't see it.
+    world.add(2, 3.0, 0.0, 0.0, make_box(1.0, 4.0), CGroup::Trigger, 0.0);
+    let p = world.add(3, 0.0, 0.0, 0.0, make_circle(0.1), CGroup::Projectile, 0.0);
+    world.launch(p, 1.0, 0.0, 1.0, 3);
+
+    let mut events = Vec::new();
+    for _ in 0..6 {
+        world.update();
+        events.extend(world.trigger_events());
+        // It flies straight through.
+        assert!(world.projectile_events().is_empty());
+    }
+    assert_eq!(events, vec![event(TriggerEventKind::Enter, 1, 3), event(TriggerEventKind::Exit, 1, 3)]);
+}
+
+#[test]
+fn triggers_survive_snapshots() {
+    let mut world = World::new();
+    world.add(1, 0.0, 0.0, 0.0, make_box(2.0, 2.0), CGroup::Trigger, 0.0);
+    let unit = world.add(2, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+    let mut restored = assert_round_trips(&mut world, 1);
+    restored.set_position(unit, 5.0, 0.0, 0.0);
+    restored.update();
+    assert_eq!(restored.trigger_events(), vec![event(TriggerEventKind::Exit, 1, 2)]);
+}
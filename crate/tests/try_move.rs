@@ -0,0 +1,296 @@
+// Regression tests for the sliding logic in World::try_move. Each test builds a
+// little scene, drives a unit through it the same way the JS collision system
+// does (try_move then update every frame) and checks where it ends up.
+use std::f64::consts::PI;
+use collide_wasm::*;
+use ncollide2d::math::{Isometry, Vector};
+use ncollide2d::query;
+use ncollide2d::shape::ShapeHandle;
+mod common;
+use common::assert_near;
+
+// How far a unit is allowed to sink into a wall before we call it
+// interpenetration. update pushes objects out to 0.01 away.
+const TOLERANCE: f64 = 0.001;
+
+struct Scene {
+    world: World,
+    statics: Vec<(ShapeHandle<f64>, Isometry<f64>)>,
+    next_id: u32,
+}
+
+struct Unit {
+    handle: usize,
+    id: u32,
+    shape: ShapeHandle<f64>,
+    pos: Isometry<f64>,
+}
+
+impl Scene {
+    fn new() -> Scene {
+        Scene { world: World::new(), statics: Vec::new(), next_id: 1 }
+    }
+
+    fn next_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn wall(&mut self, x: f64, y: f64, a: f64, shape: ShapeHandle<f64>) {
+        let id = self.next_id();
+        self.world.add(id, x, y, a, shape.clone(), CGroup::Static, 0.0);
+        self.statics.push((shape, Isometry::new(Vector::new(x, y), a)));
+    }
+
+    fn wall_box(&mut self, x: f64, y: f64, a: f64, w: f64, h: f64) {
+        self.wall(x, y, a, make_box(w, h));
+    }
+
+    fn wall_circle(&mut self, x: f64, y: f64, r: f64) {
+        self.wall(x, y, 0.0, make_circle(r));
+    }
+
+    fn unit(&mut self, x: f64, y: f64, shape: ShapeHandle<f64>, max_speed: f64) -> Unit {
+        let id = self.next_id();
+        let handle = self.world.add(id, x, y, 0.0, shape.clone(), CGroup::Unit, max_speed);
+        // The JS side always calls update once before anything moves.
+        self.world.update();
+        Unit { handle, id, shape, pos: Isometry::new(Vector::new(x, y), 0.0) }
+    }
+
+    // One frame of movement, same as the collision system in space.ts.
+    fn step(&mut self, u: &mut Unit, vx: f64, vy: f64, va: f64) {
+        u.pos = self.world.try_move(u.handle, vx, vy, va);
+        for (id, pos) in self.world.update() {
+            if id == u.id { u.pos = pos; }
+        }
+        self.assert_no_overlap(u);
+    }
+
+    fn run(&mut self, u: &mut Unit, frames: usize, vx: f64, vy: f64) {
+        for _ in 0..frames { self.step(u, vx, vy, 0.0); }
+    }
+
+    fn assert_no_overlap(&self, u: &Unit) {
+        for (shape, pos) in self.statics.iter() {
+            if let Some(c) = query::contact(&u.pos, u.shape.as_ref(), pos, shape.as_ref(), 0.0) {
+                assert!(c.depth < TOLERANCE,
+                    "unit at {:?} is {} inside the wall at {:?}", u.pos.translation.vector, c.depth, pos.translation.vector);
+            }
+        }
+    }
+}
+
+#[test]
+fn moves_freely_in_open_space() {
+    let mut scene = Scene::new();
+    let mut u = scene.unit(0.0, 0.0, make_circle(0.5), 0.1);
+    scene.run(&mut u, 10, 0.1, -0.05);
+    assert_near(u.pos.translation.x, 1.0, 1e-9);
+    assert_near(u.pos.translation.y, -0.5, 1e-9);
+}
+
+#[test]
+fn slides_along_wall() {
+    let mut scene = Scene::new();
+    scene.wall_box(0.0, 0.0, 0.0, 1.0, 10.0);
+    let mut u = scene.unit(-2.0, 0.0, make_circle(0.5), 0.1);
+    scene.run(&mut u, 30, 0.1, 0.05);
+
+    // Pressed flat against the wall, but keeping all of its tangential speed.
+    assert_near(u.pos.translation.x, -1.0, TOLERANCE);
+    assert_near(u.pos.translation.y, 1.5, TOLERANCE);
+}
+
+#[test]
+fn slides_along_wall_added_after_unit() {
+    // ncollide orders contact manifolds by handle, so this exercises the
+    // other orientation of the contact normal.
+    let mut scene = Scene::new();
+    let mut u = scene.unit(-2.0, 0.0, make_circle(0.5), 0.1);
+    scene.wall_box(0.0, 0.0, 0.0, 1.0, 10.0);
+    scene.world.update();
+    scene.run(&mut u, 30, 0.1, 0.05);
+
+    assert_near(u.pos.translation.x, -1.0, TOLERANCE);
+    assert_near(u.pos.translation.y, 1.5, TOLERANCE);
+}
+
+#[test]
+fn slides_along_rotated_wall() {
+    let mut scene = Scene::new();
+    // A long wall running diagonally through the origin, along y = -x.
+    scene.wall_box(0.0, 0.0, PI / 4.0, 0.5, 20.0);
+    let mut u = scene.unit(-3.0, 0.0, make_circle(0.5), 0.1);
+    scene.run(&mut u, 60, 0.1, 0.0);
+
+    // We should end up resting against the wall, having slid down it.
+    assert!(u.pos.translation.y < -1.0, "{:?}", u.pos.translation);
+    let dist = (u.pos.translation.x + u.pos.translation.y).abs() / 2f64.sqrt();
+    assert_near(dist, 0.75, 0.011);
+}
+
+#[test]
+fn stops_in_v_corner() {
+    let mut scene = Scene::new();
+    // Two walls making a wedge with its point at the origin, opening toward -x.
+    let d = 3.0 * (PI / 4.0).cos();
+    scene.wall_box(-d, d, PI / 4.0, 0.5, 6.0);
+    scene.wall_box(-d, -d, -PI / 4.0, 0.5, 6.0);
+    let mut u = scene.unit(-5.0, 0.1, make_circle(0.5), 0.1);
+    scene.run(&mut u, 60, 0.1, 0.0);
+
+    // We should come to rest in the bottom of the V, touching both walls, and
+    // stay there.
+    let rest = u.pos;
+    scene.run(&mut u, 20, 0.1, 0.0);
+    assert_near(u.pos.translation.x, rest.translation.x, TOLERANCE);
+    assert_near(u.pos.translation.y, rest.translation.y, TOLERANCE);
+    assert_near(u.pos.translation.x, -0.75 / (PI / 4.0).sin(), 0.02);
+    assert_near(u.pos.translation.y, 0.0, 0.02);
+    assert_eq!(scene.world.stats().stuck, 0);
+}
+
+#[test]
+fn grazes_circle() {
+    let mut scene = Scene::new();
+    scene.wall_circle(0.0, 0.0, 1.0);
+    // Heading for a glancing blow on the top of the circle.
+    let mut u = scene.unit(-4.0, 1.2, make_circle(0.5), 0.1);
+    scene.run(&mut u, 80, 0.1, 0.0);
+
+    // We should be deflected around the circle and carry on past it.
+    assert!(u.pos.translation.x > 2.0, "{:?}", u.pos.translation);
+    assert!(u.pos.translation.y >= 1.2, "{:?}", u.pos.translation);
+}
+
+#[test]
+fn does_not_clip_corner_of_rotated_box() {
+    let mut scene = Scene::new();
+    scene.wall_box(0.0, 0.0, 0.3, 1.0, 1.0);
+    // Aim straight at one of the box's corners.
+    let corner = Isometry::new(Vector::new(0.0, 0.0), 0.3) * ncollide2d::math::Point::new(-0.5, -0.5);
+    let mut u = scene.unit(corner.x - 3.0, corner.y, make_box(0.5, 0.5), 0.1);
+    scene.run(&mut u, 60, 0.1, 0.0);
+
+    // We should have been pushed down around the corner rather than cutting
+    // through it.
+    assert!(u.pos.translation.x > 1.0, "{:?}", u.pos.translation);
+    assert!(u.pos.translation.y < corner.y - 0.25 + TOLERANCE, "{:?}", u.pos.translation);
+}
+
+#[test]
+fn rotated_boxes_make_a_sealed_corner() {
+    let mut scene = Scene::new();
+    // These are the two walls from core.ts which meet at a corner.
+    scene.wall_box(-10.0, 0.0, -0.2, 1.0, 5.0);
+    scene.wall_box(-8.0, 2.0, 0.2 + PI / 2.0, 1.0, 5.0);
+    let mut u = scene.unit(-7.0, -1.0, make_circle(0.5), 0.1);
+    scene.run(&mut u, 100, -0.1, 0.1);
+
+    // Shoved into the corner, but still on the inside of both walls.
+    assert!(u.pos.translation.x > -9.5, "{:?}", u.pos.translation);
+    assert!(u.pos.translation.y < 1.5, "{:?}", u.pos.translation);
+}
+
+#[test]
+fn epsilon_gap_does_not_jitter() {
+    let mut scene = Scene::new();
+    scene.wall_box(0.0, 0.0, 0.0, 1.0, 10.0);
+    // Start a hair away from the wall.
+    let mut u = scene.unit(-1.0 - 1e-6, 0.0, make_circle(0.5), 0.1);
+    scene.run(&mut u, 20, 0.1, 0.05);
+
+    assert_near(u.pos.translation.x, -1.0, TOLERANCE);
+    assert_near(u.pos.translation.y, 1.0, TOLERANCE);
+    assert_eq!(scene.world.stats().stuck, 0);
+}
+
+#[test]
+fn epsilon_wide_corridor() {
+    let mut scene = Scene::new();
+    // A corridor exactly as wide as the unit, plus a hair.
+    scene.wall_box(0.0, 1.0 + 1e-6, 0.0, 20.0, 1.0);
+    scene.wall_box(0.0, -1.0 - 1e-6, 0.0, 20.0, 1.0);
+    let mut u = scene.unit(-5.0, 0.0, make_circle(0.5), 0.1);
+    // Push diagonally into both walls.
+    scene.run(&mut u, 20, 0.1, 0.05);
+    scene.run(&mut u, 20, 0.1, -0.05);
+
+    assert_near(u.pos.translation.x, -1.0, TOLERANCE);
+    assert_near(u.pos.translation.y, 0.0, TOLERANCE);
+}
+
+#[test]
+fn fast_unit_does_not_tunnel_through_thin_wall() {
+    let mut scene = Scene::new();
+    scene.wall_box(0.0, 0.0, 0.0, 0.2, 10.0);
+    let mut u = scene.unit(-3.0, 0.0, make_circle(0.25), 5.0);
+    scene.run(&mut u, 5, 5.0, 0.0);
+
+    assert_near(u.pos.translation.x, -0.35, TOLERANCE);
+}
+
+#[test]
+fn boxes_cannot_turn_into_walls() {
+    let mut scene = Scene::new();
+    scene.wall_box(2.0, 0.0, 0.0, 1.0, 10.0);
+    // Tall and thin, so turning it swings its ends out towards the wall.
+    let mut u = scene.unit(0.0, 0.0, make_box(0.5, 4.0), 0.1);
+    for _ in 0..10 {
+        // Check before update gets a chance to push it out.
+        u.pos = scene.world.try_move(u.handle, 0.0, 0.0, 0.2);
+        scene.assert_no_overlap(&u);
+        assert!(scene.world.update().iter().all(|(id, _)| *id != u.id));
+    }
+    let angle = u.pos.rotation.angle();
+    assert!(angle > 0.5 && angle < 1.0, "{}", angle);
+    let (shape, pos) = &scene.statics[0];
+    let gap = query::distance(&u.pos, u.shape.as_ref(), pos, shape.as_ref());
+    assert!(gap < 0.01, "{}", gap);
+    assert!(scene.world.stats().blocked_turns > 0);
+
+    // Turning back is fine.
+    u.pos = scene.world.try_move(u.handle, 0.0, 0.0, -0.2);
+    assert_near(u.pos.rotation.angle(), angle - 0.2, 1e-9);
+}
+
+#[test]
+fn circles_turn_freely_against_walls() {
+    let mut scene = Scene::new();
+    scene.wall_box(1.0, 0.0, 0.0, 1.0, 10.0);
+    let mut u = scene.unit(0.0, 0.0, make_circle(0.5), 0.1);
+    for _ in 0..10 { scene.step(&mut u, 0.0, 0.0, 0.2); }
+    assert_near(u.pos.rotation.angle(), 2.0, 1e-9);
+    assert_eq!(scene.world.stats().blocked_turns, 0);
+}
+
+#[test]
+fn set_position_ignores_stale_handles() {
+    let mut world = World::new();
+    let wall = world.add(1, 0.0, 0.0, 0.0, make_box(1.0, 1.0), CGroup::Static, 0.0);
+    world.remove(wall);
+    let hash = world.state_hash();
+    world.set_position(wall, 5.0, 0.0, 0.0);
+    world.set_position(99, 5.0, 0.0, 0.0);
+    assert_eq!(world.state_hash(), hash);
+}
+
+#[test]
+fn stale_handles_are_ignored() {
+    let mut world = World::new();
+    let unit = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.remove(unit);
+    let hash = world.state_hash();
+    assert_eq!(world.try_move(unit, 0.1, 0.0, 0.0), Isometry::identity());
+    world.remove(unit);
+    world.remove(99);
+    assert_eq!(world.state_hash(), hash);
+
+    // The handle was only freed once, so it only gets reused once.
+    let a = world.add(2, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let b = world.add(3, 5.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    assert_ne!(a, b);
+    let bytes = world.snapshot().unwrap();
+    assert_eq!(world.restore(&bytes), Ok(()));
+}
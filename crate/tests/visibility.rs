@@ -0,0 +1,92 @@
+// Tests for line of sight and visibility polygons.
+use collide_wasm::*;
+use ncollide2d::math::Point;
+mod common;
+use common::{area, rooms};
+
+const STATICS: u32 = 1 << CGroup::Static as u32;
+
+// Even-odd test, which is fine for the star-shaped polygons we get.
+fn inside(points: &[Point<f64>], p: Point<f64>) -> bool {
+    let mut result = false;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            result = !result;
+        }
+    }
+    result
+}
+
+fn has_vertex(points: &[Point<f64>], x: f64, y: f64) -> bool {
+    points.iter().any(|p| (p - Point::new(x, y)).norm() < 1e-6)
+}
+
+#[test]
+fn walls_block_line_of_sight() {
+    let mut world = rooms();
+    let a = world.add(7, 2.0, 2.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let b = world.add(8, 18.0, 2.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let c = world.add(9, 18.0, 5.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    let d = world.add(10, 2.0, 5.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    // Units don't get in the way.
+    world.add(11, 5.0, 5.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+
+    assert!(!world.line_of_sight(a, b));
+    assert!(!world.line_of_sight(b, a));
+    assert!(world.line_of_sight(c, d));
+    assert!(world.line_of_sight(a, d));
+    assert!(!world.line_of_sight(a, 1000));
+    world.update();
+    assert_eq!(world.stats().visibility_rays, 4);
+}
+
+#[test]
+fn open_ground_is_a_circle() {
+    let mut world = World::new();
+    let points = world.visibility_polygon(1.0, 2.0, 5.0, STATICS);
+    assert_eq!(points.len(), 64);
+    assert!(points.iter().all(|p| ((p - Point::new(1.0, 2.0)).norm() - 5.0).abs() < 1e-9));
+    let expected = 0.5 * 64.0 * 25.0 * (2.0 * std::f64::consts::PI / 64.0).sin();
+    assert!((area(&points) - expected).abs() < 1e-9);
+}
+
+#[test]
+fn statics_cast_shadows() {
+    let mut world = World::new();
+    world.add(1, 5.0, 0.0, 0.0, make_box(2.0, 2.0), CGroup::Static, 0.0);
+    world.update();
+    let points = world.visibility_polygon(0.0, 0.0, 10.0, STATICS);
+    assert!(area(&points) > 0.0);
+
+    // The near corners of the box, and where the shadow meets the edge of
+    // the view.
+    assert!(has_vertex(&points, 4.0, 1.0));
+    assert!(has_vertex(&points, 4.0, -1.0));
+    assert!(inside(&points, Point::new(3.9, 0.0)));
+    assert!(!inside(&points, Point::new(4.1, 0.0)));
+    assert!(!inside(&points, Point::new(8.0, 1.9)));
+    assert!(inside(&points, Point::new(8.0, 2.1)));
+    assert!(inside(&points, Point::new(-9.0, 0.0)));
+}
+
+#[test]
+fn occluder_mask_picks_what_blocks() {
+    let mut world = World::new();
+    world.add(1, 5.0, 0.0, 0.0, make_circle(1.0), CGroup::Unit, 0.1);
+    // The unit doing the looking.
+    world.add(2, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+
+    let points = world.visibility_polygon(0.0, 0.0, 10.0, STATICS);
+    assert!(inside(&points, Point::new(8.0, 0.0)));
+
+    let units = STATICS | 1 << CGroup::Unit as u32;
+    let points = world.visibility_polygon(0.0, 0.0, 10.0, units);
+    assert!(!inside(&points, Point::new(8.0, 0.0)));
+    assert!(inside(&points, Point::new(3.9, 0.0)));
+    assert!(inside(&points, Point::new(8.0, 2.0)));
+    // It was looking out past its own edge.
+    assert!(inside(&points, Point::new(0.0, 9.0)));
+}
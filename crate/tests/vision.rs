@@ -0,0 +1,100 @@
+// Tests for vision cone sensors.
+use collide_wasm::*;
+mod common;
+use common::assert_round_trips;
+
+fn event(kind: VisionEventKind, watcher: u32, unit: u32) -> VisionEvent {
+    VisionEvent { kind, watcher, unit }
+}
+
+// A guard at the origin facing along x, with a 90 degree cone.
+fn guard() -> (World, usize) {
+    let mut world = World::new();
+    let guard = world.add(1, 0.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.set_vision(guard, std::f64::consts::FRAC_PI_2, 10.0);
+    (world, guard)
+}
+
+#[test]
+fn units_are_spotted_and_lost() {
+    let (mut world, _) = guard();
+    let unit = world.add(2, 5.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    // Only units get spotted.
+    world.add(3, 5.0, 2.0, 0.0, make_box(1.0, 1.0), CGroup::Dynamic, 0.1);
+    world.update();
+    assert_eq!(world.vision_events(), vec![event(VisionEventKind::Spotted, 1, 2)]);
+    world.update();
+    assert_eq!(world.vision_events(), vec![]);
+
+    // Behind it.
+    world.set_position(unit, -5.0, 0.0, 0.0);
+    world.update();
+    assert_eq!(world.vision_events(), vec![event(VisionEventKind::Lost, 1, 2)]);
+    world.update();
+    assert_eq!(world.vision_events(), vec![]);
+}
+
+#[test]
+fn cones_reach_the_edges_of_units() {
+    let (mut world, _) = guard();
+    // Centers just outside the cone and its range, but not their edges.
+    world.add(2, 5.0, 5.3, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.add(3, 10.4, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    // And these are all the way out.
+    world.add(4, 5.0, 5.8, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.add(5, 10.6, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+    assert_eq!(world.vision_events(), vec![
+        event(VisionEventKind::Spotted, 1, 2),
+        event(VisionEventKind::Spotted, 1, 3),
+    ]);
+}
+
+#[test]
+fn cones_turn_with_their_owner() {
+    let (mut world, guard) = guard();
+    world.add(2, -5.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+    assert_eq!(world.vision_events(), vec![]);
+    world.set_position(guard, 0.0, 0.0, std::f64::consts::PI);
+    world.update();
+    assert_eq!(world.vision_events(), vec![event(VisionEventKind::Spotted, 1, 2)]);
+}
+
+#[test]
+fn statics_hide_units() {
+    let (mut world, _) = guard();
+    world.add(2, 3.0, 0.0, 0.0, make_box(1.0, 2.0), CGroup::Static, 0.0);
+    let unit = world.add(3, 6.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+    assert_eq!(world.vision_events(), vec![]);
+
+    // Out from behind the wall.
+    world.set_position(unit, 6.0, 3.0, 0.0);
+    world.update();
+    assert_eq!(world.vision_events(), vec![event(VisionEventKind::Spotted, 1, 3)]);
+}
+
+#[test]
+fn vision_survives_snapshots() {
+    let (mut world, guard) = guard();
+    let unit = world.add(2, 5.0, 0.0, 0.0, make_circle(0.5), CGroup::Unit, 0.1);
+    world.update();
+    let hash = world.state_hash();
+
+    // It remembers what it could see, so nothing new is spotted.
+    let mut restored = assert_round_trips(&mut world, 1);
+    assert_eq!(restored.vision_events(), vec![]);
+
+    // Units which are removed are forgotten quietly.
+    restored.remove(unit);
+    restored.update();
+    assert_eq!(restored.vision_events(), vec![]);
+
+    // And without a cone, nothing is seen.
+    world.clear_vision(guard);
+    world.set_position(unit, 6.0, 0.0, 0.0);
+    world.update();
+    assert_eq!(world.vision_events(), vec![]);
+    assert_ne!(world.state_hash(), hash);
+}
@@ -14,7 +14,10 @@ export interface TransformC {
 
 export interface MovableC {
   maxSpeed: number, // Linear units / second.
-  rotSpeed: number
+  rotSpeed: number,
+  avoid?: boolean, // Steer around other units rather than into them.
+  // Called with the material ids of whatever it touched each time it moves.
+  didTouch?(self: Entity, materials: number[]): void,
 }
 
 export const enum ShapeType {
@@ -45,6 +48,25 @@ export interface ColliderC {
   cgroup: CGroup,
   handle?: number, // Filled in by the space system.
   didCollideWith?(self: Entity, other: Entity): void, // ??? Not sure about the signature here.
+  // Only for launched projectiles. other is missing for Tiled colliders.
+  didBounce?(self: Entity, other: Entity | undefined, nx: number, ny: number): void,
+  // A vision cone facing the way the entity does. angle is its full width in
+  // radians. Units coming into and going out of view (past the walls) are
+  // reported to didSpot and didLoseSight.
+  vision?: {angle: number, range: number},
+  didSpot?(self: Entity, other: Entity): void,
+  didLoseSight?(self: Entity, other: Entity): void,
+  // What sliding along it is like. friction and slip go from 0 to 1; id is
+  // reported to movable.didTouch. Eg ice is {slip: 1}, mud {friction: 0.5}.
+  material?: {id: number, friction?: number, slip?: number, sound?: number},
+  // Only for statics. Makes it block things only from the side this points
+  // out of, so they can pass through the other way.
+  oneWay?: {x: number, y: number},
+  // Only for triggers. Which groups set off didEnter and didExit. Defaults to
+  // just units.
+  detects?: CGroup[],
+  didEnter?(self: Entity, other: Entity): void,
+  didExit?(self: Entity, other: Entity): void,
 }
 
 // export type AIController = () => IterableIterator<void>
@@ -75,9 +97,17 @@ export interface Entity extends EntityComponents {
 export type Entities = Map<number, Entity>
 
 let nextId = 1000
-export const addEntity = (es: Entities, c: EntityComponents): Entity => {
+// For things which hand out their own ids (like the collision world when it
+// loads a level). Returns the first of n fresh ids.
+export const reserveIds = (n: number): number => {
+  const first = nextId
+  nextId += n
+  return first
+}
+
+export const addEntity = (es: Entities, c: EntityComponents, id: number = nextId++): Entity => {
   // TODO: Might be worth enforcing some preconditions here too - render requires transform, etc.
-  const e: Entity = {id: nextId++, ...c}
+  const e: Entity = {id, ...c}
   es.set(e.id, e)
 
   // TODO: I don't like this direct dependancy here.
@@ -4,6 +4,7 @@ import update from "./update"
 import * as wasm from '../crate/Cargo.toml'
 import { CGroup } from '../crate/Cargo.toml'
 import { turnTo, moveTo, stall, moveForwardBehaviour } from "./systems/behaviour";
+import { loadLevel } from "./systems/space";
 
 wasm.init()
 
@@ -77,7 +78,7 @@ addEntity(es, {
           cgroup: CGroup.Projectile,
           didCollideWith(self, e) {
             // if (e.collider!.cgroup === CGroup.Static) self.reap = e.reap = true
-            if (e.collider!.cgroup === CGroup.Static) self.reap = true
+            if (e.collider!.cgroup === CGroup.Static || e.collider!.cgroup === CGroup.Dynamic) self.reap = true
             else if (e.unitType === UnitType.Player) self.reap = e.reap = true
           }
         },
@@ -93,7 +94,7 @@ const patrolRoute = [
 ]
 addEntity(es, {
   transform: {x:-2, y:-2, angle: 0, ...stationary},
-  movable: {maxSpeed: 2*dt, rotSpeed: 4*dt},
+  movable: {maxSpeed: 2*dt, rotSpeed: 4*dt, avoid: true},
   shape: { color: 'green', shape: {
     type: ShapeType.Circle,
     radius: 0.6
@@ -107,38 +108,7 @@ addEntity(es, {
   },
 })
 
-const TAU = Math.PI * 2
-// x, y, w, h, a.
-const boxes: [number, number, number, number, number][] = [
-  [6, 3, 1, 5, 5],
-  [0.9, 4, 0.8, 5, 2],
-
-  [-10, 0, -0.2, 1, 5],
-  [-8, 2, 0.2 + TAU/4, 1, 5],
-]
-
-const circles: [number, number, number][] = [
-  [2.5, -3, 1],
-  [5, -3, 1],
-]
-
-circles.forEach(([x, y, radius]) => addEntity(es, {
-  transform: {x, y, angle: 0, ...stationary},
-  shape: {
-    color: 'red',
-    shape: { type: ShapeType.Circle, radius, }
-  },
-  collider: { cgroup: CGroup.Static, },
-}))
-
-boxes.forEach(([x, y, angle, w, h]) => addEntity(es, {
-  transform: {x, y, angle, ...stationary},
-  shape: {
-    color: 'red',
-    shape: { type: ShapeType.Box, w, h, }
-  },
-  collider: { cgroup: CGroup.Static },
-}))
+loadLevel(es, require('./levels/arena.json'))
 
 // addEntity(es, {
 //   transform: {x: 100, y: 100, angle: 0},
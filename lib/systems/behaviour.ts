@@ -3,6 +3,7 @@ import System from './system'
 import { screenToWorld } from "../render";
 import * as vec from '../vec'
 import { lookTowardXY, lookTowardA, moveRaw, moveToward } from "./rawmovement";
+import { findPath } from "./space";
 
 const keysHeld = new Set()
 const keysPressedThisFrame = []
@@ -57,6 +58,13 @@ export const localController: System = {
 
 
 export function *moveTo(e: Entity, target: vec.Vec, range: number = 0) {
+  // Go around walls by way of the corners in between. If there's no path we
+  // just head straight there, like before.
+  const path = findPath(e, target)
+  for (let i = 2; i < path.length - 2; i += 2) {
+    const waypoint = {x: path[i], y: path[i+1]}
+    while (moveToward(e, 1, waypoint) === 0) yield
+  }
   while (moveToward(e, 1, target, range) === 0) yield
 }
 
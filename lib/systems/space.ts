@@ -1,13 +1,18 @@
 // This handles interacting with the collision space.
 // This is used for boss abilities and walls.
-import {World, make_circle, CGroup, LocalShapeHandle, make_box} from '../../crate/Cargo.toml'
+import {World, make_circle, CGroup, LocalShapeHandle, make_box, DebugLine, LogLevel, ProjectileEventKind, VisionEventKind, TriggerEventKind} from '../../crate/Cargo.toml'
 import System from './system'
-import { eachEntity, Entity, ShapeType } from '../components/entities'
+import { eachEntity, Entity, Entities, ShapeType, Shape, addEntity, reserveIds } from '../components/entities'
+import { worldToScreen } from '../render'
 
 // So much for there being no state in systems. I guess if this were blizzard
 // they'd make an entity which owns the world space.
 const world = World.new()
 
+// Sized for the patrolling enemies. The boss is bigger, but it doesn't path.
+const NAV_AGENT_RADIUS = 0.6
+world.enable_navmesh(NAV_AGENT_RADIUS)
+
 // This might not be the best way to do this.
 // const entityByRef = new Map<number, Entity>()
 
@@ -18,6 +23,39 @@ const enum ContactType {
 
 let debugText = ''
 
+// Toggle with the backtick key. This draws what the collision engine sees.
+let showCollisionDebug = false
+window.addEventListener('keydown', e => {
+  if (e.code === 'Backquote') showCollisionDebug = !showCollisionDebug
+})
+
+// Call setCollisionLogLevel(3) from the console to get try_move trace records.
+;(window as any).setCollisionLogLevel = (level: LogLevel) => world.set_log_level(level)
+
+const TRACE_RECORD_SIZE = 7
+const flushCollisionLog = () => {
+  for (const line of world.drain_log().split('\n')) {
+    if (line.startsWith('warn: ')) console.warn(line.slice(6))
+    else if (line !== '') console.log(line.slice(6))
+  }
+
+  const trace = world.drain_trace()
+  for (let i = 0; i < trace.length; i += TRACE_RECORD_SIZE) {
+    // [kind, id, x, y, vx, vy, value]. See TraceKind in the crate.
+    console.log('trace', Array.from(trace.slice(i, i + TRACE_RECORD_SIZE)))
+  }
+}
+
+const debugLineColor = (kind: DebugLine) => (
+  kind === DebugLine.Outline ? 'lime'
+  : kind === DebugLine.Aabb ? 'rgba(255, 255, 255, 0.3)'
+  : kind === DebugLine.ContactPoint ? 'yellow'
+  : kind === DebugLine.ContactNormal ? 'orange'
+  : kind === DebugLine.Sweep ? 'cyan'
+  : kind === DebugLine.Vision ? 'rgba(255, 100, 100, 0.6)'
+  : 'rgba(100, 150, 255, 0.4)' // NavMesh
+)
+
 export const simpleMovement: System = {
   pred: (e: Entity) => !e.collider && e.transform && e.shape,
   update(es) {
@@ -31,12 +69,132 @@ export const simpleMovement: System = {
   }
 }
 
+// See level.rs in the crate for the format. The crate ignores color.
+export interface LevelObject {
+  id?: number,
+  x: number, y: number, angle?: number,
+  shape: {type: 'circle', radius: number} | {type: 'box', w: number, h: number},
+  group?: 'static' | 'unit' | 'projectile' | 'dynamic' | 'trigger',
+  speed?: number,
+  tags?: string[],
+  color?: string,
+}
+export interface Level {
+  version: number,
+  objects: LevelObject[],
+}
+
+const levelGroup = {static: CGroup.Static, unit: CGroup.Unit, projectile: CGroup.Projectile, dynamic: CGroup.Dynamic, trigger: CGroup.Trigger}
+
+// Adds an entity for everything in the level. The collision world parses the
+// level itself, so we only need to hook up the handles and ids it made.
+export const loadLevel = (es: Entities, level: Level) => {
+  const loaded = world.load_level(JSON.stringify(level), reserveIds(level.objects.length))
+
+  level.objects.forEach((obj, i) => {
+    const shape: Shape = obj.shape.type === 'circle'
+      ? { type: ShapeType.Circle, radius: obj.shape.radius }
+      : { type: ShapeType.Box, w: obj.shape.w, h: obj.shape.h }
+
+    addEntity(es, {
+      transform: {x: obj.x, y: obj.y, angle: obj.angle || 0, vx: 0, vy: 0, va: 0},
+      shape: { color: obj.color || 'red', shape },
+      collider: { cgroup: levelGroup[obj.group || 'static'], handle: loaded[i*2] },
+    }, loaded[i*2+1])
+  })
+}
+
+// Adds the colliders from a map made in Tiled and saved as JSON. See tiled.rs
+// in the crate for what becomes what. The colliders don't get entities, but
+// every object comes back with its properties (for spawn points, triggers and
+// so on).
+export interface TiledObject {
+  name: string, kind: string, layer: string,
+  x: number, y: number, width: number, height: number, angle: number,
+  collider: {handle: number, id: number, tags: string[]} | null,
+  properties: {[name: string]: any},
+}
+export const loadTiledMap = (json: string): TiledObject[] => {
+  const map = JSON.parse(world.load_tiled(json, reserveIds(0)))
+  const colliders = map.tiles.length + map.objects.filter((o: TiledObject) => o.collider).length
+  reserveIds(colliders)
+  return map.objects
+}
+
+// Hands a projectile over to the collision world, which moves it from then on
+// and bounces it off walls. So it shouldn't be movable. Hits come back through
+// didCollideWith, and bounces through didBounce. (Projectiles which aren't
+// launched get didCollideWith for the first thing they hit each frame.)
+export const launchProjectile = (e: Entity, vx: number, vy: number, restitution: number, maxBounces: number) => {
+  world.launch(e.collider!.handle!, vx, vy, restitution, maxBounces)
+}
+
+// Knocks a unit (or dynamic body) back. The collision world keeps moving it,
+// and slows it down over a few frames.
+export const applyImpulse = (e: Entity, ix: number, iy: number) => {
+  world.apply_impulse(e.collider!.handle!, ix, iy)
+}
+
+// Waypoints from the entity to the target, as [x, y] pairs which start where
+// it is and end at the target. Empty if it can't get there. Everything paths
+// as if it were a patrolling enemy, since that's the only navmesh we keep.
+export const findPath = (e: Entity, target: {x: number, y: number}): Float64Array => {
+  return world.find_path(e.transform!.x, e.transform!.y, target.x, target.y, NAV_AGENT_RADIUS)
+}
+
+// For swarms chasing one target (usually the player). Call buildFlowField
+// every frame - it's cheap unless the walls have changed.
+const FLOW_FIELD_CELL_SIZE = 0.5
+export const buildFlowField = (target: {x: number, y: number}) => {
+  world.build_flow_field(target.x, target.y, FLOW_FIELD_CELL_SIZE)
+}
+
+// Which way each entity should go to reach the flow field's target, as
+// [dx, dy] unit vectors in the same order. [0, 0] if it can't get there.
+export const flowDirections = (es: Entity[]): Float64Array => {
+  const positions = new Float64Array(es.length * 2)
+  es.forEach((e, i) => {
+    positions[i*2] = e.transform!.x
+    positions[i*2+1] = e.transform!.y
+  })
+  return world.flow_directions(positions)
+}
+
+// Steers each entity's velocity (per frame, [vx, vy] pairs in the same order)
+// around the others. Pass every unit which is about to move, so they can
+// dodge each other. The collision system does this for anything with
+// movable.avoid set.
+export const avoid = (es: Entity[], velocities: Float64Array): Float64Array => {
+  const preferred = new Float64Array(es.length * 3)
+  es.forEach((e, i) => {
+    preferred[i*3] = e.collider!.handle!
+    preferred[i*3+1] = velocities[i*2]
+    preferred[i*3+2] = velocities[i*2+1]
+  })
+  return world.avoid(preferred)
+}
+
+// Whether two entities can see each other past the walls.
+export const lineOfSight = (a: Entity, b: Entity): boolean =>
+  world.line_of_sight(a.collider!.handle!, b.collider!.handle!)
+
+// What can be seen from a point, as [x, y] pairs going around it. Fill it as
+// a polygon to light the area or cut it out of the fog of war.
+const SIGHT_BLOCKERS = (1 << CGroup.Static) | (1 << CGroup.Dynamic)
+export const visibilityPolygon = (x: number, y: number, radius: number): Float64Array =>
+  world.visibility_polygon(x, y, radius, SIGHT_BLOCKERS)
+
+const PROJECTILE_EVENT_SIZE = 7
+const VISION_EVENT_SIZE = 3
+const TRIGGER_EVENT_SIZE = 3
+
 const pred = (e: Entity) => e.collider && e.transform && e.shape
 export const collisionSystem: System = {
   pred,
 
   onAdded(e) {
-    if (pred(e)) {
+    // Entities from a level are already in the world.
+    if (pred(e) && e.collider!.handle == null) {
       const {cgroup} = e.collider!
       const {x, y, angle} = e.transform!
       // console.log('adding entity to space', e.id, cgroup)
@@ -50,27 +208,91 @@ export const collisionSystem: System = {
 
       const speed = e.movable ? e.movable.maxSpeed : 0
       const handle = e.collider!.handle = world.add(e.id, x, y, angle, shape, cgroup, speed)
+      const {vision} = e.collider!
+      if (vision) world.set_vision(handle, vision.angle, vision.range)
+      const {material} = e.collider!
+      if (material) world.set_material(handle, material.id, material.friction || 0, material.slip || 0, material.sound || 0)
+      const {oneWay} = e.collider!
+      if (oneWay) world.set_one_way(handle, oneWay.x, oneWay.y)
+      const {detects} = e.collider!
+      if (detects) world.set_trigger_groups(handle, detects.reduce((mask, g) => mask | (1 << g), 0))
       // console.log('added handle', handle, cgroup)
       // entityByRef.set(handle, e)
     }
   },
 
-  renderDebug(ctx) {
+  renderDebug(ctx, width, height) {
     if (debugText !== '') {
       ctx.font = '14px sans-serif'
       ctx.fillStyle = 'white'
       ctx.fillText(debugText, 20, 20)
     }
+
+    if (showCollisionDebug) {
+      const stats = world.stats()
+      ctx.font = '12px monospace'
+      ctx.fillStyle = 'white'
+      ;[
+        `objects: ${stats.static_objects} static, ${stats.unit_objects} units, ${stats.projectile_objects} projectiles, ${stats.dynamic_objects} dynamic, ${stats.trigger_objects} triggers`,
+        `pairs: ${stats.broad_phase_pairs} broad phase, ${stats.contact_manifolds} contact manifolds`,
+        `try_move: ${stats.try_move_calls} calls, ${stats.try_move_iterations} iterations (max ${stats.max_try_move_iterations}), ${stats.stuck} stuck`,
+        `toi queries: ${stats.toi_queries}, turns: ${stats.turn_queries} queries, ${stats.blocked_turns} blocked, navmesh tiles rebuilt: ${stats.navmesh_tiles_rebuilt}`,
+        `flow field: ${stats.flow_field_rebuilds} rebuilds, ${stats.flow_field_cells_visited} cells visited`,
+        `avoidance: ${stats.avoidance_neighbours} neighbours, sight: ${stats.visibility_rays} rays, carried: ${stats.carried_units} units`,
+        `update: ${stats.update_ms.toFixed(2)}ms`,
+      ].forEach((line, i) => ctx.fillText(line, 20, height - 108 + i * 14))
+      stats.free()
+
+      const lines = world.debug_lines()
+      ctx.lineWidth = 1
+      for (let i = 0; i < lines.length; i += 5) {
+        const a = worldToScreen(lines[i+1], lines[i+2])
+        const b = worldToScreen(lines[i+3], lines[i+4])
+        ctx.strokeStyle = debugLineColor(lines[i])
+        ctx.beginPath()
+        ctx.moveTo(a.x, a.y)
+        ctx.lineTo(b.x, b.y)
+        ctx.stroke()
+      }
+    }
   },
 
   update(es) {
-    for (const e of eachEntity(es, e => pred(e) && e.movable)) {
+    // Everything which avoids steers at once, including units standing still
+    // (so they can step aside), before anyone moves.
+    const avoiding = Array.from(eachEntity(es, e => pred(e) && e.movable && e.movable.avoid))
+    if (avoiding.length) {
+      const velocities = new Float64Array(avoiding.length * 2)
+      avoiding.forEach((e, i) => {
+        velocities[i*2] = e.transform!.vx
+        velocities[i*2+1] = e.transform!.vy
+      })
+      const steered = avoid(avoiding, velocities)
+      avoiding.forEach((e, i) => {
+        e.transform!.vx = steered[i*2]
+        e.transform!.vy = steered[i*2+1]
+      })
+    }
+
+    // Moving platforms go first, so the units they carry move from where
+    // they've been carried to.
+    for (const e of eachEntity(es, e => pred(e) && e.movable && e.collider!.cgroup === CGroup.Static)) {
+      const {vx, vy, va} = e.transform!
+      if (vx !== 0 || vy !== 0 || va !== 0) {
+        const pos = world.move_static(e.collider!.handle!, vx, vy, va)
+        if (pos.length) [e.transform!.x, e.transform!.y, e.transform!.angle] = pos
+        e.transform!.vx = e.transform!.vy = e.transform!.va = 0
+      }
+    }
+
+    for (const e of eachEntity(es, e => pred(e) && e.movable && e.collider!.cgroup !== CGroup.Static)) {
       // e.transform!.va = 0
       const {x, y, angle, vx, vy, va} = e.transform!
       if (vx !== 0 || vy !== 0 || va !== 0) {
         // console.log(vx, vy, va)
-        const [newx, newy, newa] = world.try_move(e.collider!.handle!, vx, vy, va)
+        const [newx, newy, newa, ...materials] = world.try_move(e.collider!.handle!, vx, vy, va)
         ;[e.transform!.x, e.transform!.y, e.transform!.angle] = [newx, newy, newa]
+        if (materials.length && e.movable!.didTouch) e.movable!.didTouch(e, materials)
         // e.transform!.angle += e.transform!.va
         e.transform!.vx = e.transform!.vy = e.transform!.va = 0
       }
@@ -80,13 +302,14 @@ export const collisionSystem: System = {
     const fixes = world.update()
     // if (fixes.length) console.log('fixes', fixes)
 
-    for (let i = 0; i < fixes.length; i += 3) {
+    for (let i = 0; i < fixes.length; i += 4) {
       const id = fixes[i]
-      const x = fixes[i+1]
-      const y = fixes[i+2]
-      const e = es.get(id)!
-      e.transform!.x = x
-      e.transform!.y = y
+      const e = es.get(id)
+      // Again, Tiled colliders don't have entities.
+      if (!e) continue
+      e.transform!.x = fixes[i+1]
+      e.transform!.y = fixes[i+2]
+      e.transform!.angle = fixes[i+3]
     }
 
     // world.print_events()
@@ -94,12 +317,61 @@ export const collisionSystem: System = {
     const prox = world.proximity_events()
 
     for (let i = 0; i < prox.length; i += 2) {
-      const e1 = es.get(prox[i])!
-      const e2 = es.get(prox[i+1])!
+      const e1 = es.get(prox[i])
+      const e2 = es.get(prox[i+1])
+      // Colliders from a Tiled map don't have entities.
+      if (!e1 || !e2) continue
+      // Projectile hits come through projectile events instead.
+      if (e1.collider!.cgroup === CGroup.Projectile || e2.collider!.cgroup === CGroup.Projectile) continue
       // console.log('collide', e1, e2)
       if (e1.collider!.didCollideWith) e1.collider!.didCollideWith(e1, e2)
       if (e2.collider!.didCollideWith) e2.collider!.didCollideWith(e2, e1)
     }
+
+    const pev = world.projectile_events()
+    for (let i = 0; i < pev.length; i += PROJECTILE_EVENT_SIZE) {
+      // [kind, projectile id, other id, x, y, nx, ny]
+      const self = es.get(pev[i+1])
+      const other = es.get(pev[i+2])
+      if (!self) continue
+      if (pev[i] === ProjectileEventKind.Bounce) {
+        if (self.collider!.didBounce) self.collider!.didBounce(self, other, pev[i+5], pev[i+6])
+      } else if (other) {
+        if (self.collider!.didCollideWith) self.collider!.didCollideWith(self, other)
+        if (other.collider!.didCollideWith) other.collider!.didCollideWith(other, self)
+      } else {
+        // Stopped on a Tiled collider.
+        self.reap = true
+      }
+    }
+
+    const vev = world.vision_events()
+    for (let i = 0; i < vev.length; i += VISION_EVENT_SIZE) {
+      // [kind, watcher id, unit id]
+      const self = es.get(vev[i+1])
+      const other = es.get(vev[i+2])
+      if (!self || !other) continue
+      if (vev[i] === VisionEventKind.Spotted) {
+        if (self.collider!.didSpot) self.collider!.didSpot(self, other)
+      } else if (self.collider!.didLoseSight) {
+        self.collider!.didLoseSight(self, other)
+      }
+    }
+
+    const tev = world.trigger_events()
+    for (let i = 0; i < tev.length; i += TRIGGER_EVENT_SIZE) {
+      // [kind, trigger id, other id]
+      const self = es.get(tev[i+1])
+      const other = es.get(tev[i+2])
+      if (!self || !other) continue
+      if (tev[i] === TriggerEventKind.Enter) {
+        if (self.collider!.didEnter) self.collider!.didEnter(self, other)
+      } else if (self.collider!.didExit) {
+        self.collider!.didExit(self, other)
+      }
+    }
+
+    flushCollisionLog()
   },
 
   onRemoved(es, e) {